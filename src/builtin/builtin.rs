@@ -1,15 +1,22 @@
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc};
 use std::io::Write;
 use std::str::FromStr;
 
-use crate::defs::expression::{Exp, Expression};
+use regex::Regex;
+
+use crate::defs::expression::{Body, Exp, Expression, Constraint};
 use crate::defs::keyword::Keyword;
 use crate::{Interpreter, Type};
 use crate::defs::retl_type::Type::*;
-use crate::defs::retl_type::type_conforms;
-use crate::interpreter::value::{Value, Env, Val};
-use crate::interpreter::interpreter::error;
+use crate::interpreter::value::{Value, Val, SeqStage};
+use crate::interpreter::env::Env;
+use crate::interpreter::interpreter::{error, raise, closure_env, conform, literal_matches};
+use crate::builtin::pool::{Pool, MIN_CHUNK};
+use crate::interpreter::retl_error::{RetlError, ErrorKind};
 use crate::scanner::token::make_empty_token;
 
 fn null_val() -> Value {
@@ -30,17 +37,38 @@ fn func_value(ident: &str, params: Vec<(String, Type)>, return_type: Type) -> Va
             parameters: params.iter()
                 .map(|p: &(String, Type)| { (p.0.to_owned(), p.1.clone()) })
                 .collect(),
-            body: Exp{
+            body: Arc::new(Body::new(Exp{
                 exp: Expression::Empty,
                 exp_type: func_type.clone(),
                 token: make_empty_token()
-            },
-            env: Env::new()
+            })),
+            env: Env::new(),
+            group: Arc::new(vec![])
         },
         val_type: func_type
     }
 }
 
+fn generic(ident: &str) -> Type {
+    GenericType{ident: ident.to_string()}
+}
+
+/// Parameter type a builtin argument is checked against, the list builtins also accept a seq and the
+/// seq builtins also accept a list
+pub fn seq_param_type(ident: &Keyword, param_type: &Type, arg_type: &Type) -> Type {
+    match (ident, param_type, arg_type) {
+        (Keyword::Map, ListType{list_type}, SeqType{..}) |
+        (Keyword::Filter, ListType{list_type}, SeqType{..}) |
+        (Keyword::Pmap, ListType{list_type}, SeqType{..}) |
+        (Keyword::Pfilter, ListType{list_type}, SeqType{..}) |
+        (Keyword::Foldl, ListType{list_type}, SeqType{..}) |
+        (Keyword::Foldr, ListType{list_type}, SeqType{..}) => SeqType{seq_type: list_type.clone()},
+        (Keyword::Take, SeqType{seq_type}, ListType{..}) |
+        (Keyword::ToList, SeqType{seq_type}, ListType{..}) => ListType{list_type: seq_type.clone()},
+        _ => param_type.clone()
+    }
+}
+
 #[derive(Clone)]
 struct BuiltinMeta {
     params: Vec<(String, Type)>,
@@ -49,17 +77,9 @@ struct BuiltinMeta {
 
 #[derive(Clone)]
 pub struct Builtin {
-    builtins: HashMap<String, BuiltinMeta>
-}
-
-fn value_to_string(val: &Val) -> Option<String> { // TODO - Collection types
-    match val {
-        Val::IntValue{value} => Some(value.to_string()),
-        Val::BoolValue{value} => Some(if *value { "true".to_string() } else { "false".to_string() }),
-        Val::CharValue{value} => Some(value.to_string()),
-        Val::StringValue{value} => Some(value.clone()),
-        _ => None
-    }
+    builtins: HashMap<String, BuiltinMeta>,
+    params: HashMap<String, String>, // --param name=value pairs
+    args: Vec<String> // positional arguments after the script
 }
 
 impl Builtin {
@@ -84,51 +104,91 @@ impl Builtin {
         builtins.insert("println".to_string(), BuiltinMeta { params: vec![("str".to_string(), Any)], return_type: NullType });
         builtins.insert("print".to_string(), BuiltinMeta { params: vec![("str".to_string(), Any)], return_type: NullType });
         builtins.insert("map".to_string(), BuiltinMeta { params: vec![
-            ("l".to_string(), ListType{list_type: Box::new(Any)}),
-            ("f".to_string(), FuncType{param_types: vec![Any], return_type: Box::new(Any)})
-        ], return_type: Any });
+            ("l".to_string(), ListType{list_type: Box::new(generic("A"))}),
+            ("f".to_string(), FuncType{param_types: vec![generic("A")], return_type: Box::new(generic("B"))})
+        ], return_type: ListType{list_type: Box::new(generic("B"))} });
         builtins.insert("filter".to_string(), BuiltinMeta { params: vec![
-            ("l".to_string(), ListType{list_type: Box::new(Any)}),
-            ("f".to_string(), FuncType{param_types: vec![Any], return_type: Box::new(Any)})
-        ], return_type: Any });
+            ("l".to_string(), ListType{list_type: Box::new(generic("A"))}),
+            ("f".to_string(), FuncType{param_types: vec![generic("A")], return_type: Box::new(BoolType)})
+        ], return_type: ListType{list_type: Box::new(generic("A"))} });
+        builtins.insert("pmap".to_string(), BuiltinMeta { params: vec![
+            ("l".to_string(), ListType{list_type: Box::new(generic("A"))}),
+            ("f".to_string(), FuncType{param_types: vec![generic("A")], return_type: Box::new(generic("B"))})
+        ], return_type: ListType{list_type: Box::new(generic("B"))} });
+        builtins.insert("pfilter".to_string(), BuiltinMeta { params: vec![
+            ("l".to_string(), ListType{list_type: Box::new(generic("A"))}),
+            ("f".to_string(), FuncType{param_types: vec![generic("A")], return_type: Box::new(BoolType)})
+        ], return_type: ListType{list_type: Box::new(generic("A"))} });
         builtins.insert("foldl".to_string(), BuiltinMeta { params: vec![
-            ("acc".to_string(), Any),
-            ("l".to_string(), ListType{list_type: Box::new(Any)}),
-            ("f".to_string(), FuncType{param_types: vec![Any, Any], return_type: Box::new(Any)})
-        ], return_type: Any });
+            ("acc".to_string(), generic("B")),
+            ("l".to_string(), ListType{list_type: Box::new(generic("A"))}),
+            ("f".to_string(), FuncType{param_types: vec![generic("B"), generic("A")], return_type: Box::new(generic("B"))})
+        ], return_type: generic("B") });
         builtins.insert("foldr".to_string(), BuiltinMeta { params: vec![
-            ("acc".to_string(), Any),
-            ("l".to_string(), ListType{list_type: Box::new(Any)}),
-            ("f".to_string(), FuncType{param_types: vec![Any, Any], return_type: Box::new(Any)})
-        ], return_type: Any });
+            ("acc".to_string(), generic("B")),
+            ("l".to_string(), ListType{list_type: Box::new(generic("A"))}),
+            ("f".to_string(), FuncType{param_types: vec![generic("B"), generic("A")], return_type: Box::new(generic("B"))})
+        ], return_type: generic("B") });
+        builtins.insert("seq".to_string(), BuiltinMeta { params: vec![
+            ("l".to_string(), ListType{list_type: Box::new(generic("A"))})
+        ], return_type: SeqType{seq_type: Box::new(generic("A"))} });
+        builtins.insert("take".to_string(), BuiltinMeta { params: vec![
+            ("s".to_string(), SeqType{seq_type: Box::new(generic("A"))}),
+            ("n".to_string(), IntType)
+        ], return_type: SeqType{seq_type: Box::new(generic("A"))} });
+        builtins.insert("toList".to_string(), BuiltinMeta { params: vec![
+            ("s".to_string(), SeqType{seq_type: Box::new(generic("A"))})
+        ], return_type: ListType{list_type: Box::new(generic("A"))} });
         builtins.insert("slice".to_string(), BuiltinMeta { params: vec![
-            ("l".to_string(), ListType{list_type: Box::new(Any)}),
+            ("l".to_string(), ListType{list_type: Box::new(generic("A"))}),
             ("s".to_string(), IntType),
             ("e".to_string(), IntType)
-        ], return_type: ListType{list_type: Box::new(Any)}});
+        ], return_type: ListType{list_type: Box::new(generic("A"))}});
         builtins.insert("substr".to_string(), BuiltinMeta { params: vec![
             ("str".to_string(), StringType),
             ("s".to_string(), IntType),
             ("e".to_string(), IntType)
         ], return_type: StringType});
         builtins.insert("zip".to_string(), BuiltinMeta { params: vec![
-            ("l1".to_string(), ListType{list_type: Box::new(Any)}),
-            ("l2".to_string(), ListType{list_type: Box::new(Any)})
-        ], return_type: ListType{list_type: Box::new(TupleType{tuple_types: vec![Any, Any]})} });
+            ("l1".to_string(), ListType{list_type: Box::new(generic("A"))}),
+            ("l2".to_string(), ListType{list_type: Box::new(generic("B"))})
+        ], return_type: ListType{list_type: Box::new(TupleType{tuple_types: vec![generic("A"), generic("B")]})} });
         builtins.insert("len".to_string(), BuiltinMeta { params: vec![("c".to_string(), Any)], return_type: IntType });
         builtins.insert("type".to_string(), BuiltinMeta { params: vec![("v".to_string(), Any)], return_type: StringType });
         builtins.insert("intToString".to_string(), BuiltinMeta { params: vec![("i".to_string(), IntType)], return_type: StringType });
         builtins.insert("stringToInt".to_string(), BuiltinMeta { params: vec![("s".to_string(), StringType)], return_type: IntType });
-        Builtin{builtins}
+        builtins.insert("assert".to_string(), BuiltinMeta { params: vec![
+            ("c".to_string(), BoolType),
+            ("m".to_string(), StringType)
+        ], return_type: NullType });
+        builtins.insert("param".to_string(), BuiltinMeta { params: vec![
+            ("name".to_string(), StringType),
+            ("default".to_string(), generic("A"))
+        ], return_type: generic("A") });
+        builtins.insert("requireParam".to_string(), BuiltinMeta { params: vec![("name".to_string(), StringType)], return_type: StringType });
+        builtins.insert("args".to_string(), BuiltinMeta { params: vec![], return_type: ListType{list_type: Box::new(StringType)} });
+        builtins.insert("validate".to_string(), BuiltinMeta { params: vec![
+            ("table".to_string(), ListType{list_type: Box::new(generic("A"))}),
+            ("schema".to_string(), Any)
+        ], return_type: ListType{list_type: Box::new(generic("A"))} });
+        Builtin{builtins, params: HashMap::new(), args: vec![]}
+    }
+
+    /// Script parameters and positional arguments given on the command line
+    pub fn with_script_args(mut self, params: Vec<(String, String)>, args: Vec<String>) -> Builtin {
+        self.params = params.into_iter().collect();
+        self.args = args;
+        self
     }
 
+    /// Scope nested in the builtins, so the builtins frame is shared rather than copied by each script binding
     pub fn load_builtins(&self, env: &Env) -> Env {
         let mut builtin_env = env.clone();
         self.builtins.keys().for_each(|k: &String| {
             let b = self.builtins.get(k).unwrap();
             builtin_env.insert(k.clone(), func_value(k, b.params.clone(), b.return_type.clone()));
         });
-        builtin_env
+        builtin_env.child()
     }
 
     fn get_meta(&self, ident: Keyword, env: &Env) -> (Vec<Value>, Type) {
@@ -146,122 +206,138 @@ impl Builtin {
         }
     }
 
-    pub fn interpret(&self, ident: Keyword, env: &Env, exp: &Exp, interpreter: Interpreter) -> Value {
+    pub fn interpret(&self, ident: Keyword, env: &Env, exp: &Exp, interpreter: Interpreter) -> Result<Value, RetlError> {
         let (args, rt): (Vec<Value>, Type) = self.get_meta(ident.clone(), env);
+        let args = match ident {
+            Keyword::Foldl | Keyword::Foldr | Keyword::Len | Keyword::ToList | Keyword::Pmap | Keyword::Pfilter => args.iter()
+                .map(|arg| { self.force(arg, exp, &interpreter) })
+                .collect::<Result<Vec<Value>, RetlError>>()?,
+            _ => args
+        };
 
         match ident {
             Keyword::Readln => {
                 let mut line = String::new();
-                io::stdin().read_line(&mut line).expect("Expected input");
-                Value{value: Val::StringValue{value: line}, val_type: rt}
+                match io::stdin().read_line(&mut line) {
+                    Ok(_) => Ok(Value{value: Val::StringValue{value: line.into()}, val_type: rt}),
+                    Err(e) => raise(ErrorKind::Io, &format!("Could not read input, {}", e), exp)
+                }
             },
-            Keyword::ReadCSV => self.read_csv(args, exp),
-            Keyword::WriteCSV => self.write_csv(args, exp),
+            Keyword::ReadCSV => self.read_csv(args, exp, &interpreter),
+            Keyword::WriteCSV => self.write_csv(args, exp, &interpreter),
             Keyword::Println => {
-                let str = value_to_string(&args[0].value);
-                match str {
-                    Some(str_val) => {
-                        println!("{}", str_val);
-                        let _ = io::stdout().flush();
-                        null_val()
-                    },
-                    _ => error("Invalid argument type for \"println\"", exp)
-                }
+                println!("{}", args[0].render());
+                let _ = io::stdout().flush();
+                Ok(null_val())
             },
             Keyword::Print => {
-                let str = value_to_string(&args[0].value);
-                match str {
-                    Some(str_val) => {
-                        print!("{}", str_val);
-                        let _ = io::stdout().flush();
-                        null_val()
-                    },
-                    _ => error("Invalid argument type for \"print\"", exp)
-                }
+                print!("{}", args[0].render());
+                let _ = io::stdout().flush();
+                Ok(null_val())
             },
             Keyword::Map => self.map(args, exp, interpreter),
             Keyword::Filter => self.filter(args, exp, interpreter),
+            Keyword::Pmap => self.parallel(args, exp, interpreter, false),
+            Keyword::Pfilter => self.parallel(args, exp, interpreter, true),
             Keyword::Foldl => self.fold(args, exp, interpreter, true),
             Keyword::Foldr => self.fold(args, exp, interpreter, false),
+            Keyword::Seq => Ok(self.seq(args)),
+            Keyword::Take => self.take(args, exp),
+            Keyword::ToList => Ok(args[0].clone()),
             Keyword::Slice => self.slice(args, exp),
             Keyword::Substr => self.substr(args, exp),
             Keyword::Zip => self.zip(args, exp),
             Keyword::Type => {
-                Value{value: Val::StringValue{value: args[0].val_type.as_string()}, val_type: StringType}
+                Ok(Value{value: Val::StringValue{value: args[0].val_type.as_string().into()}, val_type: StringType})
             },
             Keyword::Len => {
                 let size = match &args[0].value {
                     Val::StringValue{value} => Some(value.trim().chars().count()),
-                    Val::ListValue{values} => {
-                        type_conforms(&args[0].val_type, &ListType{list_type: Box::new(Any)}, &exp.token);
-                        Some(values.len())
-                    },
-                    Val::TupleValue{values} => {
-                        type_conforms(&args[0].val_type, &TupleType{tuple_types: vec![Any; values.len()]}, &exp.token);
-                        Some(values.len())
-                    },
-                    Val::DictValue{values} => {
-                        type_conforms(&args[0].val_type, &DictType{key_type: Box::new(Any), value_type: Box::new(Any)}, &exp.token);
-                        Some(values.len())
-                    },
+                    Val::ListValue{values} | Val::TupleValue{values} => Some(values.len()),
+                    Val::RangeValue{..} => Some(args[0].value.range_len()),
+                    Val::DictValue{values} => Some(values.len()),
                     _ => None
                 };
-                match size {
-                    Some(size) => Value{value: Val::IntValue{value: size as i32}, val_type: IntType},
-                    _ => {
-                        error("Invalid argument type for \"len\"", exp);
-                        Value{value: Val::IntValue{value: -1}, val_type: IntType}
-                    }
+                match size.map(i32::try_from) {
+                    Some(Ok(size)) => Ok(Value{value: Val::IntValue{value: size}, val_type: IntType}),
+                    Some(Err(_)) => raise(ErrorKind::Arithmetic, "Length does not fit in an int", exp),
+                    _ => raise(ErrorKind::Type, "Invalid argument type for \"len\"", exp)
                 }
             },
-            Keyword::IntToString => self.int_to_string(args),
-            Keyword::StringToInt => self.string_to_int(args),
-            _ => Value{value: Val::Error, val_type: UnknownType}
+            Keyword::IntToString => Ok(self.int_to_string(args)),
+            Keyword::StringToInt => self.string_to_int(args, exp),
+            Keyword::Assert => match &args[0].value {
+                Val::BoolValue{value: true} => Ok(null_val()),
+                _ => raise(ErrorKind::Assertion, &format!("Assertion failed: {}", args[1].render()), exp)
+            },
+            Keyword::Param => self.param(args, exp),
+            Keyword::RequireParam => match &args[0].value {
+                Val::StringValue{value} => match self.params.get(&**value) {
+                    Some(param) => Ok(Value{value: Val::StringValue{value: param.as_str().into()}, val_type: StringType}),
+                    _ => raise(ErrorKind::Reference, &format!("Missing required parameter \"{}\", pass it with --param {}=<value>",
+                        value, value), exp)
+                },
+                _ => raise(ErrorKind::Type, "Invalid argument type for \"name\" in \"requireParam\"", exp)
+            },
+            Keyword::Args => Ok(Value{
+                value: Val::ListValue{values: Arc::new(self.args.iter()
+                    .map(|arg| { Value{value: Val::StringValue{value: arg.as_str().into()}, val_type: StringType} })
+                    .collect())},
+                val_type: rt
+            }),
+            Keyword::Validate => match &args[0].value {
+                Val::ListValue{values} => {
+                    self.validate_rows(values, &args[1], exp, &interpreter)?;
+                    Ok(args[0].clone())
+                },
+                _ => raise(ErrorKind::Type, "Invalid argument type for \"table\" in \"validate\"", exp)
+            },
+            _ => error(&format!("Unknown builtin \"{}\"", ident), exp)
         }
     }
 
-    fn row_entry_to_value(&self, column_type: &Type, element: &str, exp: &Exp) -> Value {
+    fn row_entry_to_value(&self, column_type: &Type, element: &str) -> Option<Value> {
         match column_type {
-            IntType => Value{
-                value: Val::IntValue{value: element.parse::<i32>().unwrap()},
-                val_type: IntType
-            },
-            BoolType => Value{
-                value: Val::BoolValue{value:
-                    if element == "true" { true }
-                    else if element == "false" { false }
-                    else {
-                        error("Invalid value for boolean column type", exp);
-                        false
-                    }
-                },
-                val_type: BoolType
-            },
-            CharType => Value{
+            IntType => element.trim().parse::<i32>().ok().map(|value| {
+                Value{value: Val::IntValue{value}, val_type: IntType}
+            }),
+            BoolType => match element {
+                "true" => Some(true),
+                "false" => Some(false),
+                _ => None
+            }.map(|value| { Value{value: Val::BoolValue{value}, val_type: BoolType} }),
+            CharType if element.chars().count() == 1 => Some(Value{
                 value: Val::CharValue{value: element.to_string()},
                 val_type: CharType
-            },
-            StringType => Value{
-                value: Val::StringValue{value: element.to_string()},
+            }),
+            StringType => Some(Value{
+                value: Val::StringValue{value: element.into()},
                 val_type: StringType
+            }),
+            NullType if element.is_empty() => Some(null_val()),
+            UnionType{union_types} => {
+                union_types.iter()
+                    .find_map(|ut| { self.row_entry_to_value(ut, element) })
+                    .map(|v| { v.widen(column_type) })
             },
-            _ => error("Cannot convert value to column type", exp)
+            _ => None
         }
     }
 
-    fn read_csv(&self, args: Vec<Value>, exp: &Exp) -> Value {
+    fn read_csv(&self, args: Vec<Value>, exp: &Exp, interpreter: &Interpreter) -> Result<Value, RetlError> {
         match &args[0].value {
             Val::StringValue{value} => {
-                let path = value;
+                let path = &**value;
                 match &args[1].value {
-                    Val::SchemaValue{values} => {
+                    Val::SchemaValue{values, ..} => {
                         let schema = values;
-                        let csv_reader = csv::ReaderBuilder::new()
-                            .has_headers(true)
-                            .from_path(path);
+                        let csv_reader = match csv::ReaderBuilder::new().has_headers(true).from_path(path) {
+                            Ok(reader) => reader,
+                            Err(e) => return raise(ErrorKind::Io, &format!("Could not read CSV \"{}\", {}", path, e), exp)
+                        };
                         let mut row_values: Vec<Vec<Value>> = vec![];
 
-                        for (row_index, record) in csv_reader.expect("Could not read CSV").records().enumerate() {
+                        for (row_index, record) in csv_reader.into_records().enumerate() {
                             row_values.push(vec![]);
                             match record {
                                 Ok(row) => {
@@ -272,40 +348,41 @@ impl Builtin {
                                                     Some(schema_column) => schema_column.1.clone(),
                                                     _ => UnknownType
                                                 };
-                                                row_values[row_index].push(self.row_entry_to_value(&column_type, entry, exp))
+                                                let entry_value = match self.row_entry_to_value(&column_type, entry) {
+                                                    Some(value) => value,
+                                                    _ => return raise(ErrorKind::Conversion, &format!("Cannot convert \"{}\" to column type {}",
+                                                        entry, column_type.as_string()), exp)
+                                                };
+                                                row_values[row_index].push(entry_value)
                                             },
-                                            _ => {
-                                                error("Could not read row from CSV", exp);
-                                                return null_val()
-                                            }
+                                            _ => return raise(ErrorKind::Io, "Could not read row from CSV", exp)
                                         };
                                     }
                                 },
-                                _ => {
-                                    error("Could not read CSV row", exp);
-                                }
+                                Err(e) => return raise(ErrorKind::Io, &format!("Could not read CSV row, {}", e), exp)
                             }
                         };
 
                         let row_type = TupleType{tuple_types: schema.iter()
                             .map(|col| { col.1.clone() })
                             .collect()};
-                        let list_tuple_value = row_values.iter().map(|lv| {
+                        let list_tuple_value: Vec<Value> = row_values.iter().map(|lv| {
                             Value{
-                                value: Val::TupleValue{values: lv.clone()},
+                                value: Val::TupleValue{values: Arc::new(lv.clone())},
                                 val_type: row_type.clone()
                             }
                         }).collect();
+                        self.validate_rows(&list_tuple_value, &args[1], exp, interpreter)?;
 
-                        Value{
-                            value: Val::ListValue{values: list_tuple_value},
+                        Ok(Value{
+                            value: Val::ListValue{values: Arc::new(list_tuple_value)},
                             val_type: ListType{list_type: Box::new(row_type)}
-                        }
+                        })
                     },
-                    _ => error("Invalid argument type for \"schema\" in \"readCSV\"", exp)
+                    _ => raise(ErrorKind::Type, "Invalid argument type for \"schema\" in \"readCSV\"", exp)
                 }
             },
-            _ => error("Invalid argument type for \"path\" in \"readCSV\"", exp)
+            _ => raise(ErrorKind::Type, "Invalid argument type for \"path\" in \"readCSV\"", exp)
         }
     }
 
@@ -314,170 +391,381 @@ impl Builtin {
             Val::IntValue{value} => value.to_string(),
             Val::BoolValue{value} => if value { "true".to_string() } else { "false".to_string() },
             Val::CharValue{value} => value,
-            Val::StringValue{value} => value,
+            Val::StringValue{value} => value.to_string(),
             _ => "".to_string()
         }
     }
 
-    fn write_csv(&self, args: Vec<Value>, exp: &Exp) -> Value {
+    fn write_csv(&self, args: Vec<Value>, exp: &Exp, interpreter: &Interpreter) -> Result<Value, RetlError> {
         match &args[0].value {
             Val::StringValue{value} => {
-                let path = value;
+                let path = &**value;
                 match &args[1].value {
                     Val::ListValue{values} => {
                         let table = values;
                         match &args[2].value {
                             Val::SchemaValue{..} => {
+                                self.validate_rows(table, &args[2], exp, interpreter)?;
                                 let mut csv_writer = match csv::Writer::from_path(path) {
                                     Ok(writer) => writer,
-                                    _ => {
-                                        error("Could not open CSV with given path", exp);
-                                        return null_val()
-                                    }
+                                    _ => return raise(ErrorKind::Io, "Could not open CSV with given path", exp)
                                 };
-                                for row in table {
+                                for row in table.iter() {
                                     match row.value.clone() {
                                         Val::TupleValue{values} => {
                                             let converted_row_values: Vec<String> = values.iter().map(|v| {
                                                 self.value_to_row_entry(v)
                                             }).collect();
-                                            csv_writer.write_record(&converted_row_values).expect("Could not write row to CSV");
+                                            if csv_writer.write_record(&converted_row_values).is_err() {
+                                                return raise(ErrorKind::Io, "Could not write row to CSV", exp)
+                                            }
                                         },
                                         _ => {}
                                     }
                                 }
 
-                                null_val()
+                                Ok(null_val())
                             },
-                            _ => error("Invalid argument type for \"schema\" in \"writeCSV\"", exp)
+                            _ => raise(ErrorKind::Type, "Invalid argument type for \"schema\" in \"writeCSV\"", exp)
                         }
                     },
-                    _ => error("Invalid argument type for \"table\" in \"writeCSV\"", exp)
+                    _ => raise(ErrorKind::Type, "Invalid argument type for \"table\" in \"writeCSV\"", exp)
                 }
             },
-            _ => error("Invalid argument type for \"path\" in \"writeCSV\"", exp)
+            _ => raise(ErrorKind::Type, "Invalid argument type for \"path\" in \"writeCSV\"", exp)
+        }
+    }
+
+    /// List of the elements of a seq, running its stages, other values are returned as they are
+    fn force(&self, value: &Value, exp: &Exp, interpreter: &Interpreter) -> Result<Value, RetlError> {
+        match &value.value {
+            Val::SeqValue{..} => interpreter.clone().collect_seq(value, exp),
+            _ => Ok(value.clone())
+        }
+    }
+
+    /// Appends a stage to a seq, a list is first wrapped in a seq
+    fn add_stage(&self, collection: &Value, stage: SeqStage, seq_type: Type) -> Value {
+        let (source, mut stages) = match &collection.value {
+            Val::SeqValue{source, stages} => (source.clone(), stages.clone()),
+            _ => (Box::new(collection.clone()), vec![])
+        };
+        stages.push(stage);
+        Value{value: Val::SeqValue{source, stages}, val_type: seq_type}
+    }
+
+    fn seq(&self, args: Vec<Value>) -> Value {
+        match (&args[0].value, &args[0].val_type) {
+            (Val::SeqValue{..}, _) => args[0].clone(),
+            (_, ListType{list_type}) => Value{
+                value: Val::SeqValue{source: Box::new(args[0].clone()), stages: vec![]},
+                val_type: SeqType{seq_type: list_type.clone()}
+            },
+            _ => args[0].clone()
+        }
+    }
+
+    fn take(&self, args: Vec<Value>, exp: &Exp) -> Result<Value, RetlError> {
+        let count = match &args[1].value {
+            Val::IntValue{value} if *value >= 0 => *value as usize,
+            _ => return raise(ErrorKind::Index, "Invalid count for \"take\", less than 0", exp)
+        };
+        let seq_type = match &args[0].val_type {
+            ListType{list_type} => SeqType{seq_type: list_type.clone()},
+            t => t.clone()
+        };
+        Ok(self.add_stage(&args[0], SeqStage::Take{count}, seq_type))
+    }
+
+    /// Checks the rows of a table against the constraints of a schema, every violation is reported with its
+    /// 1-based row and column
+    fn validate_rows(&self, rows: &[Value], schema: &Value, exp: &Exp, interpreter: &Interpreter) -> Result<(), RetlError> {
+        let (columns, constraints, env) = match &schema.value {
+            Val::SchemaValue{values, constraints, env} => (values, constraints, env),
+            _ => return Ok(())
+        };
+        let cell = |row: &Value, column: &str| -> Value {
+            match (&row.value, columns.iter().position(|(ident, _)| { ident == column })) {
+                (Val::TupleValue{values}, Some(index)) => values.get(index).cloned().unwrap_or_else(null_val),
+                _ => null_val()
+            }
+        };
+        let mut violations: Vec<(usize, String)> = vec![];
+        for constraint in constraints.iter() {
+            match constraint {
+                Constraint::NotNull{column} => violations.extend(rows.iter().enumerate()
+                    .filter(|(_, row)| { cell(row, column).value == Val::NullValue })
+                    .map(|(i, _)| { (i, format!("row {}, column \"{}\": value is null", i + 1, column)) })),
+                Constraint::Unique{column} => {
+                    let mut seen: HashMap<String, usize> = HashMap::new();
+                    for (i, row) in rows.iter().enumerate() {
+                        let value = cell(row, column).render_nested();
+                        match seen.get(&value) {
+                            Some(first) => violations.push((i, format!("row {}, column \"{}\": duplicate value {} of row {}",
+                                i + 1, column, value, first + 1))),
+                            _ => { seen.insert(value, i); }
+                        }
+                    }
+                },
+                Constraint::Min{column, min} => violations.extend(rows.iter().enumerate()
+                    .filter(|(_, row)| { matches!(cell(row, column).value, Val::IntValue{value} if value < *min) })
+                    .map(|(i, row)| { (i, format!("row {}, column \"{}\": value {} is below min {}",
+                        i + 1, column, cell(row, column).render_nested(), min)) })),
+                Constraint::Max{column, max} => violations.extend(rows.iter().enumerate()
+                    .filter(|(_, row)| { matches!(cell(row, column).value, Val::IntValue{value} if value > *max) })
+                    .map(|(i, row)| { (i, format!("row {}, column \"{}\": value {} is above max {}",
+                        i + 1, column, cell(row, column).render_nested(), max)) })),
+                Constraint::Allowed{column, values} => violations.extend(rows.iter().enumerate()
+                    .filter(|(_, row)| {
+                        let value = cell(row, column);
+                        value.value != Val::NullValue && !values.iter().any(|literal| { literal_matches(&value.value, literal) })
+                    })
+                    .map(|(i, row)| { (i, format!("row {}, column \"{}\": value {} is not in the allowed set",
+                        i + 1, column, cell(row, column).render_nested())) })),
+                Constraint::Regex{column, regex} => {
+                    let compiled = match Regex::new(regex) {
+                        Ok(compiled) => compiled,
+                        Err(e) => return raise(ErrorKind::Validation, &format!("Invalid regex for column \"{}\", {}", column, e), exp).map(|_| ())
+                    };
+                    violations.extend(rows.iter().enumerate()
+                        .filter(|(_, row)| { matches!(&cell(row, column).value, Val::StringValue{value} if !compiled.is_match(value)) })
+                        .map(|(i, row)| { (i, format!("row {}, column \"{}\": value {} does not match ~\"{}\"",
+                            i + 1, column, cell(row, column).render_nested(), regex)) }))
+                },
+                Constraint::Check{condition} => {
+                    for (i, row) in rows.iter().enumerate() {
+                        let mut row_env = env.child();
+                        for (ident, _) in columns.iter() {
+                            row_env.insert(ident.clone(), cell(row, ident));
+                        }
+                        if let Val::BoolValue{value: false} = interpreter.clone().interpret(condition, &mut row_env, &BoolType)?.value {
+                            violations.push((i, format!("row {}: check failed", i + 1)))
+                        }
+                    }
+                }
+            }
+        }
+        if violations.is_empty() {
+            return Ok(())
+        }
+        violations.sort_by_key(|(i, _)| { *i });
+        let mut listed: Vec<String> = violations.iter().take(10).map(|(_, message)| { message.clone() }).collect();
+        if violations.len() > listed.len() {
+            listed.push(format!("and {} more", violations.len() - listed.len()))
         }
+        raise(ErrorKind::Validation, &format!("Schema validation failed with {} violation(s): {}",
+            violations.len(), listed.join("; ")), exp).map(|_| ())
     }
 
-    fn map(&self, args: Vec<Value>, exp: &Exp, interpreter: Interpreter) -> Value {
+    fn map(&self, args: Vec<Value>, exp: &Exp, mut interpreter: Interpreter) -> Result<Value, RetlError> {
         let collection = &args[0];
         let collection_iterator_type = match args[1].val_type.clone() {
             FuncType{return_type, ..} => *return_type,
             _ => NullType
         };
+        if let Val::SeqValue{..} = collection.value {
+            let seq_type = SeqType{seq_type: Box::new(collection_iterator_type)};
+            return Ok(self.add_stage(collection, SeqStage::Map{func: args[1].clone()}, seq_type))
+        }
         match &args[1].value.clone() {
-            Val::FuncValue{builtin_ident, parameters, body, env} => {
+            Val::FuncValue{builtin_ident, parameters, body, env, group} => {
                 match collection.value.clone() {
                     Val::ListValue{values} => {
                         let mapped_values: Vec<Value> = values.iter().map(|v: &Value| {
-                            let mut temp_body_env = env.clone();
-                            type_conforms(&parameters[0].1, &v.val_type, &exp.token);
-                            temp_body_env.insert(parameters[0].0.clone(), v.clone());
+                            conform(&parameters[0].1, &v.val_type, exp)?;
+                            let arg = v.clone().widen(&parameters[0].1);
                             match builtin_ident {
-                                Some(bi) => self.interpret(bi.clone(), &mut temp_body_env, body, interpreter.clone()),
-                                _ => interpreter.clone().interpret(body, &mut temp_body_env, &collection_iterator_type.clone())
+                                Some(bi) => {
+                                    let mut temp_body_env = closure_env(env, group)?;
+                                    temp_body_env.insert(parameters[0].0.clone(), arg);
+                                    self.interpret(bi.clone(), &temp_body_env, body, interpreter.clone())
+                                },
+                                _ => interpreter.run_function(body, parameters, vec![arg], env, group, &collection_iterator_type)
                             }
-                        }).collect();
-                        Value{value: Val::ListValue{values: mapped_values}, val_type: ListType{list_type: Box::new(collection_iterator_type)}}
+                        }).collect::<Result<Vec<Value>, RetlError>>()?;
+                        Ok(Value{value: Val::ListValue{values: Arc::new(mapped_values)}, val_type: ListType{list_type: Box::new(collection_iterator_type)}})
                     },
-                    _ => error("Invalid collection type for \"map\"", exp)
+                    _ => raise(ErrorKind::Type, "Invalid collection type for \"map\"", exp)
                 }
             },
-            _ => error("Invalid function type for \"map\"", exp)
+            _ => raise(ErrorKind::Type, "Invalid function type for \"map\"", exp)
         }
     }
 
-    fn filter(&self, args: Vec<Value>, exp: &Exp, interpreter: Interpreter) -> Value {
+    fn filter(&self, args: Vec<Value>, exp: &Exp, mut interpreter: Interpreter) -> Result<Value, RetlError> {
         let collection = &args[0];
         let collection_iterator_type = match args[1].val_type.clone() {
             FuncType{param_types, return_type} => {
-                type_conforms(&*return_type, &BoolType, &exp.token);
+                conform(&*return_type, &BoolType, exp)?;
                 param_types[0].clone()
             },
             _ => NullType
         };
+        if let Val::SeqValue{..} = collection.value {
+            return Ok(self.add_stage(collection, SeqStage::Filter{func: args[1].clone()}, collection.val_type.clone()))
+        }
         match &args[1].value.clone() {
-            Val::FuncValue{builtin_ident, parameters, body, env} => {
+            Val::FuncValue{builtin_ident, parameters, body, env, group} => {
                 match collection.value.clone() {
                     Val::ListValue{values} => {
                         let mut filtered_values: Vec<Value> = vec![];
-                        values.iter().for_each(|v: &Value| {
-                            let mut temp_body_env = env.clone();
-                            type_conforms(&parameters[0].1, &v.val_type, &exp.token);
-                            temp_body_env.insert(parameters[0].0.clone(), v.clone());
+                        for v in values.iter() {
+                            conform(&parameters[0].1, &v.val_type, exp)?;
+                            let arg = v.clone().widen(&parameters[0].1);
                             let result = match builtin_ident {
-                                Some(bi) => self.interpret(bi.clone(), &mut temp_body_env, body, interpreter.clone()),
-                                _ => interpreter.clone().interpret(body, &mut temp_body_env, &BoolType)
+                                Some(bi) => {
+                                    let mut temp_body_env = closure_env(env, group)?;
+                                    temp_body_env.insert(parameters[0].0.clone(), arg);
+                                    self.interpret(bi.clone(), &temp_body_env, body, interpreter.clone())?
+                                },
+                                _ => interpreter.run_function(body, parameters, vec![arg], env, group, &BoolType)?
                             };
                             match result.value {
                                 Val::BoolValue{value} => if value { filtered_values.push(v.clone()) },
                                 _ => {}
                             }
-                        });
-                        Value{value: Val::ListValue{values: filtered_values}, val_type: ListType{list_type: Box::new(collection_iterator_type)}}
+                        }
+                        Ok(Value{value: Val::ListValue{values: Arc::new(filtered_values)}, val_type: ListType{list_type: Box::new(collection_iterator_type)}})
                     },
-                    _ => error("Invalid collection type for \"filter\"", exp)
+                    _ => raise(ErrorKind::Type, "Invalid collection type for \"filter\"", exp)
                 }
             },
-            _ => error("Invalid function type for \"filter\"", exp)
+            _ => raise(ErrorKind::Type, "Invalid function type for \"filter\"", exp)
+        }
+    }
+
+    /// Maps or filters a list in contiguous chunks, the caller runs the first and the shared pool the rest with a
+    /// clone of the interpreter each, results are joined in input order and the first error wins
+    fn parallel(&self, args: Vec<Value>, exp: &Exp, mut interpreter: Interpreter, filter: bool) -> Result<Value, RetlError> {
+        let name = if filter { "pfilter" } else { "pmap" };
+        let (param_type, return_type) = match args[1].val_type.clone() {
+            FuncType{param_types, return_type} => (param_types[0].clone(), *return_type),
+            _ => return raise(ErrorKind::Type, &format!("Invalid function type for \"{}\"", name), exp)
+        };
+        if filter {
+            conform(&return_type, &BoolType, exp)?;
+        }
+        let values = match &args[0].value {
+            Val::ListValue{values} => values.clone(),
+            _ => return raise(ErrorKind::Type, &format!("Invalid collection type for \"{}\"", name), exp)
+        };
+        let func = &args[1];
+        let pool = Pool::global();
+        let chunk_count = (pool.size() + 1).min(values.len() / MIN_CHUNK).max(1);
+        let chunk_size = values.len().div_ceil(chunk_count).max(1);
+        let (sender, receiver) = mpsc::channel();
+        let site = Exp{exp: Expression::Empty, exp_type: exp.exp_type.clone(), token: exp.token.clone()};
+        for index in 1..chunk_count {
+            let (builtin, func, values, site, return_type) = (self.clone(), func.clone(), values.clone(), site.clone(), return_type.clone());
+            let (mut interpreter, sender) = (interpreter.clone(), sender.clone());
+            let job: Box<dyn FnOnce() + Send> = Box::new(move || {
+                let chunk = values.chunks(chunk_size).nth(index).unwrap_or(&[]);
+                let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                    builtin.apply_chunk(&func, chunk, &site, &mut interpreter, &return_type, filter)
+                })).unwrap_or_else(|_| {
+                    raise(ErrorKind::Runtime, &format!("Worker thread of \"{}\" panicked", name), &site).map(|_| { vec![] })
+                });
+                let _ = sender.send((index, result));
+            });
+            if let Err(job) = pool.submit(job) {
+                job()
+            }
+        }
+        drop(sender);
+        let first = self.apply_chunk(func, values.chunks(chunk_size).next().unwrap_or(&[]), exp, &mut interpreter, &return_type, filter);
+        let mut chunks: Vec<Option<Result<Vec<Value>, RetlError>>> = (0..chunk_count).map(|_| { None }).collect();
+        chunks[0] = Some(first);
+        for (index, result) in receiver {
+            chunks[index] = Some(result)
+        }
+        let mut results = vec![];
+        for chunk in chunks {
+            match chunk {
+                Some(chunk) => results.extend(chunk?),
+                _ => return error(&format!("Worker thread of \"{}\" stopped without a result", name), exp)
+            }
+        }
+        let list_type = if filter { param_type } else { return_type };
+        Ok(Value{value: Val::ListValue{values: Arc::new(results)}, val_type: ListType{list_type: Box::new(list_type)}})
+    }
+
+    /// Mapped values of a chunk of a list, or the elements kept by the filter
+    fn apply_chunk(&self, func: &Value, chunk: &[Value], exp: &Exp, interpreter: &mut Interpreter, return_type: &Type,
+                   filter: bool) -> Result<Vec<Value>, RetlError> {
+        let mut results = vec![];
+        for v in chunk {
+            let result = self.apply(func, v, exp, interpreter, return_type)?;
+            if !filter {
+                results.push(result)
+            } else if let Val::BoolValue{value: true} = result.value {
+                results.push(v.clone())
+            }
         }
+        Ok(results)
     }
 
-    fn fold(&self, args: Vec<Value>, exp: &Exp, interpreter: Interpreter, left: bool) -> Value {
+    /// Result of calling a single parameter function on a list element
+    fn apply(&self, func: &Value, v: &Value, exp: &Exp, interpreter: &mut Interpreter, return_type: &Type) -> Result<Value, RetlError> {
+        match &func.value {
+            Val::FuncValue{builtin_ident, parameters, body, env, group} => {
+                conform(&parameters[0].1, &v.val_type, exp)?;
+                let arg = v.clone().widen(&parameters[0].1);
+                match builtin_ident {
+                    Some(bi) => {
+                        let mut temp_body_env = closure_env(env, group)?;
+                        temp_body_env.insert(parameters[0].0.clone(), arg);
+                        self.interpret(bi.clone(), &temp_body_env, body, interpreter.clone())
+                    },
+                    _ => interpreter.run_function(body, parameters, vec![arg], env, group, return_type)
+                }
+            },
+            _ => raise(ErrorKind::Type, "Invalid function value", exp)
+        }
+    }
+
+    fn fold(&self, args: Vec<Value>, exp: &Exp, mut interpreter: Interpreter, left: bool) -> Result<Value, RetlError> {
         let mut acc = args[0].clone();
         let list = &args[1];
         let func_value = &args[2];
         match (list.val_type.clone(), func_value.val_type.clone()) {
-            (ListType{list_type}, FuncType{param_types, return_type}) => {
-                match *list_type {
-                    CharType => { // char -> string edge-case
-                        type_conforms(&acc.val_type, &StringType, &exp.token);
-                        type_conforms(&param_types[0], &StringType, &exp.token);
-                        type_conforms(&list_type, &param_types[1], &exp.token);
-                        type_conforms(&return_type, &StringType, &exp.token)
-                    },
-                    _ => {
-                        type_conforms(&acc.val_type, &list_type, &exp.token);
-                        type_conforms(&acc.val_type, &param_types[0], &exp.token);
-                        type_conforms(&list_type, &param_types[1], &exp.token);
-                        type_conforms(&list_type, &return_type, &exp.token)
-                    }
-                };
-
+            (ListType{..}, FuncType{return_type, ..}) => {
                 match list.value.clone() {
                     Val::ListValue{values} => {
-                        let mut fold_func = |v: &Value| {
+                        let mut fold_func = |v: &Value| -> Result<(), RetlError> {
                             acc = match func_value.value.clone() {
-                                Val::FuncValue{builtin_ident, parameters, body, env} => {
-                                    let mut temp_body_env = env.clone();
-                                    temp_body_env.insert(parameters[0].0.clone(), acc.clone());
-                                    temp_body_env.insert(parameters[1].0.clone(), v.clone());
+                                Val::FuncValue{builtin_ident, parameters, body, env, group} => {
+                                    let args = vec![acc.clone().widen(&parameters[0].1), v.clone().widen(&parameters[1].1)];
                                     match builtin_ident {
-                                        Some(bi) => self.interpret(bi.clone(), &mut temp_body_env, &body, interpreter.clone()),
-                                        _ => interpreter.clone().interpret(&body, &mut temp_body_env, &return_type)
+                                        Some(bi) => {
+                                            let mut temp_body_env = closure_env(&env, &group)?;
+                                            temp_body_env.extend(parameters.iter().map(|p| { p.0.clone() }).zip(args));
+                                            self.interpret(bi.clone(), &temp_body_env, &body, interpreter.clone())?
+                                        },
+                                        _ => interpreter.run_function(&body, &parameters, args, &env, &group, &return_type)?
                                     }
                                 },
-                                _ => error("Invalid function type for \"foldl\"", exp)
-                            }
+                                _ => return raise(ErrorKind::Type, "Invalid function type for \"foldl\"", exp).map(|_| ())
+                            };
+                            Ok(())
                         };
                         if left {
-                            values.iter().for_each(|v: &Value| fold_func(v))
+                            values.iter().try_for_each(|v: &Value| fold_func(v))?
                         } else {
-                            values.iter().rev().for_each(|v: &Value| fold_func(v))
+                            values.iter().rev().try_for_each(|v: &Value| fold_func(v))?
                         }
-                        acc
+                        Ok(acc)
                     },
-                    _ => error("Invalid list type for \"foldl\"", exp)
+                    _ => raise(ErrorKind::Type, "Invalid list type for \"foldl\"", exp)
                 }
             },
-            _ => error("Invalid list or function types for \"foldl\"", exp)
+            _ => raise(ErrorKind::Type, "Invalid list or function types for \"foldl\"", exp)
         }
     }
 
-    fn slice(&self, args: Vec<Value>, exp: &Exp) -> Value {
+    fn slice(&self, args: Vec<Value>, exp: &Exp) -> Result<Value, RetlError> {
         let list = match &args[0].value {
             Val::ListValue{values} => values.clone(),
-            _ => vec![]
+            _ => Arc::new(vec![])
         };
         let list_size = list.len();
         let start_index = match &args[1].value {
@@ -490,24 +778,24 @@ impl Builtin {
         };
 
         if start_index < 0 {
-            error("Invalid start index for \"slice\", less than 0", exp);
+            return raise(ErrorKind::Index, "Invalid start index for \"slice\", less than 0", exp)
         } else if start_index > end_index {
-            error("Start index is greater than end index for \"slice\"", exp);
+            return raise(ErrorKind::Index, "Start index is greater than end index for \"slice\"", exp)
         } else if end_index > list_size as i32 {
-            error("Invalid end index for \"slice\", greater than list size", exp);
+            return raise(ErrorKind::Index, "Invalid end index for \"slice\", greater than list size", exp)
         }
 
-        let sub = &list[start_index as usize..end_index as usize].to_vec();
-        Value{
-            value: Val::ListValue{values: sub.clone() },
+        let sub = list[start_index as usize..end_index as usize].to_vec();
+        Ok(Value{
+            value: Val::ListValue{values: Arc::new(sub)},
             val_type: args[0].val_type.clone()
-        }
+        })
     }
 
-    fn substr(&self, args: Vec<Value>, exp: &Exp) -> Value {
+    fn substr(&self, args: Vec<Value>, exp: &Exp) -> Result<Value, RetlError> {
         let str = match &args[0].value {
             Val::StringValue{value} => value.clone(),
-            _ => "".to_string()
+            _ => "".into()
         };
         let str_len = str.len();
         let start_index = match &args[1].value {
@@ -520,21 +808,23 @@ impl Builtin {
         };
 
         if start_index < 0 {
-            error("Invalid start index for \"substr\", less than 0", exp);
+            return raise(ErrorKind::Index, "Invalid start index for \"substr\", less than 0", exp)
         } else if start_index > end_index {
-            error("Start index is greater than end index for \"substr\"", exp);
+            return raise(ErrorKind::Index, "Start index is greater than end index for \"substr\"", exp)
         } else if end_index > str_len as i32 {
-            error("Invalid end index for \"substr\", greater than list size", exp);
+            return raise(ErrorKind::Index, "Invalid end index for \"substr\", greater than list size", exp)
         }
 
-        let sub = str[start_index as usize..end_index as usize].to_string();
-        Value{
-            value: Val::StringValue{value: sub},
-            val_type: StringType
+        match str.get(start_index as usize..end_index as usize) {
+            Some(sub) => Ok(Value{
+                value: Val::StringValue{value: sub.into()},
+                val_type: StringType
+            }),
+            _ => raise(ErrorKind::Index, "Indices for \"substr\" are not on character boundaries", exp)
         }
     }
 
-    fn zip(&self, args: Vec<Value>, exp: &Exp) -> Value {
+    fn zip(&self, args: Vec<Value>, exp: &Exp) -> Result<Value, RetlError> {
         let (list1, list1_type) = match &args[0] {
             Value{
                 value: Val::ListValue{values},
@@ -550,7 +840,7 @@ impl Builtin {
             _ => (None, UnknownType)
         };
         let error_list = Value{
-            value: Val::ListValue{values: vec![]},
+            value: Val::ListValue{values: Arc::new(vec![])},
             val_type: ListType{list_type: Box::new(NullType)}
         };
 
@@ -561,40 +851,56 @@ impl Builtin {
                     let mut zipped_values: Vec<Value> = vec![];
                     l1.iter().zip(l2.iter()).for_each(|v: (&Value, &Value)| {
                         zipped_values.push(Value{
-                            value: Val::TupleValue{values: vec![v.0.clone(), v.1.clone()]},
+                            value: Val::TupleValue{values: Arc::new(vec![v.0.clone(), v.1.clone()])},
                             val_type: tuple_type.clone()
                         })
                     });
-                    Value{
-                        value: Val::ListValue{values: zipped_values},
+                    Ok(Value{
+                        value: Val::ListValue{values: Arc::new(zipped_values)},
                         val_type: ListType{list_type: Box::new(tuple_type)}
-                    }
+                    })
                 } else {
-                    error("Different sized lists provided for \"zip\"", exp);
-                    error_list
+                    raise(ErrorKind::Index, "Different sized lists provided for \"zip\"", exp)
                 }
             },
-            _ => error_list
+            _ => Ok(error_list)
+        }
+    }
+
+    /// Value of a --param converted to the type of the default, the default when the param was not given
+    fn param(&self, args: Vec<Value>, exp: &Exp) -> Result<Value, RetlError> {
+        let name = match &args[0].value {
+            Val::StringValue{value} => &**value,
+            _ => return raise(ErrorKind::Type, "Invalid argument type for \"name\" in \"param\"", exp)
+        };
+        let default = &args[1];
+        match self.params.get(name) {
+            Some(param) => match self.row_entry_to_value(&default.val_type, param) {
+                Some(value) => Ok(value),
+                _ => raise(ErrorKind::Conversion, &format!("Cannot convert parameter \"{}\" value \"{}\" to {}",
+                    name, param, default.val_type.as_string()), exp)
+            },
+            _ => Ok(default.clone())
         }
     }
 
     fn int_to_string(&self, args: Vec<Value>) -> Value {
         Value{
             value: Val::StringValue{value: match &args[0].value {
-                Val::IntValue{value} => value.to_string(),
-                _ => "".to_string()
+                Val::IntValue{value} => value.to_string().into(),
+                _ => "".into()
             }},
             val_type: StringType
         }
     }
 
-    fn string_to_int(&self, args: Vec<Value>) -> Value {
-        Value{
-            value: Val::IntValue{value: match &args[0].value {
-                Val::StringValue{value} => value.parse::<i32>().unwrap(),
-                _ => -1
-            }},
-            val_type: IntType
+    fn string_to_int(&self, args: Vec<Value>, exp: &Exp) -> Result<Value, RetlError> {
+        match &args[0].value {
+            Val::StringValue{value} => match value.parse::<i32>() {
+                Ok(int_value) => Ok(Value{value: Val::IntValue{value: int_value}, val_type: IntType}),
+                _ => raise(ErrorKind::Conversion, &format!("Cannot convert \"{}\" to int", value), exp)
+            },
+            _ => raise(ErrorKind::Type, "Invalid argument type for \"stringToInt\"", exp)
         }
     }
 }
\ No newline at end of file
@@ -1 +1,2 @@
-pub(crate) mod builtin;
\ No newline at end of file
+pub(crate) mod builtin;
+pub(crate) mod pool;
\ No newline at end of file
@@ -0,0 +1,68 @@
+use std::cell::Cell;
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
+use std::thread;
+use log::trace;
+
+use crate::interpreter::interpreter::STACK_SIZE;
+
+/// Fewest list elements worth handing to a worker, smaller inputs are mapped on the calling thread
+pub const MIN_CHUNK: usize = 64;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+static POOL: OnceLock<Pool> = OnceLock::new();
+static WORKERS: OnceLock<usize> = OnceLock::new();
+
+thread_local! {
+    static IN_WORKER: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Worker threads shared by every pmap and pfilter call, started on first use and kept for the whole run
+pub struct Pool {
+    sender: mpsc::Sender<Job>,
+    size: usize
+}
+
+impl Pool {
+    pub fn global() -> &'static Pool {
+        POOL.get_or_init(Pool::start)
+    }
+
+    /// Sets the number of workers instead of deriving it from the cores, only before the pool starts
+    pub fn configure(workers: usize) {
+        let _ = WORKERS.set(workers);
+    }
+
+    /// One worker per core but the one the caller keeps for its own share of the work
+    fn start() -> Pool {
+        let cores = thread::available_parallelism().map(|n| { n.get() }).unwrap_or(1);
+        let workers = WORKERS.get().copied().unwrap_or(cores - 1);
+        trace!("start: {} cores, {} workers", cores, workers);
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let size = (1..=workers).filter(|index| {
+            let receiver = receiver.clone();
+            thread::Builder::new().name(format!("retl-worker-{}", index)).stack_size(STACK_SIZE).spawn(move || {
+                IN_WORKER.with(|in_worker| { in_worker.set(true) });
+                loop {
+                    let job = match receiver.lock().unwrap_or_else(|e| { e.into_inner() }).recv() {
+                        Ok(job) => job,
+                        _ => break
+                    };
+                    job()
+                }
+            }).is_ok()
+        }).count();
+        Pool{sender, size}
+    }
+
+    /// Workers a call can hand jobs to, none from inside a worker so nested calls never wait on each other
+    pub fn size(&self) -> usize {
+        if IN_WORKER.with(|in_worker| { in_worker.get() }) { 0 } else { self.size }
+    }
+
+    /// Queues a job, handing it back when no worker is left to run it
+    pub fn submit(&self, job: Job) -> Result<(), Job> {
+        self.sender.send(job).map_err(|e| { e.0 })
+    }
+}
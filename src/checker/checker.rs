@@ -0,0 +1,980 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use log::trace;
+use regex::Regex;
+
+use crate::defs::operator::Operator;
+use crate::defs::expression::{Exp, Expression, Literal, Pattern, Clause, Constraint};
+use crate::defs::retl_type::{Type, type_conforms_no_error, has_unknown_types, unify, substitute, is_union_type};
+use crate::checker::exhaustiveness::PatternSpace;
+use crate::builtin::builtin::seq_param_type;
+use crate::defs::keyword::Keyword;
+use crate::interpreter::value::{Val, slice_indices};
+use crate::interpreter::env::Env;
+use crate::interpreter::retl_error::error_type;
+use crate::scanner::token::Token;
+use crate::utils::diagnostic::{Diagnostic, Code, suggest};
+
+pub type TypeEnv = HashMap<String, Type>;
+
+pub fn literal_type(literal: &Literal) -> Type {
+    match literal {
+        Literal::IntLit{..} => Type::IntType,
+        Literal::BoolLit{..} => Type::BoolType,
+        Literal::CharLit{..} => Type::CharType,
+        Literal::StringLit{..} => Type::StringType,
+        Literal::NullLit => Type::NullType
+    }
+}
+
+pub fn is_unknown(t: &Type) -> bool {
+    match t {
+        Type::UnknownType | Type::Any | Type::GenericType{..} => true,
+        _ => false
+    }
+}
+
+/// Type of `[]`, joins with any other list type
+fn is_empty_list(t: &Type) -> bool {
+    matches!(t, Type::ListType{list_type} if **list_type == Type::UnknownType)
+}
+
+/// Key marking a binding in a type environment as an assignable var
+fn var_key(ident: &str) -> String {
+    format!("var${}", ident)
+}
+
+/// Key marking a var of an enclosing scope that a function reads but may not assign
+fn captured_key(ident: &str) -> String {
+    format!("captured${}", ident)
+}
+
+/// Binds an immutable name, hiding any var of the same name
+fn bind(type_env: &mut TypeEnv, ident: &str, t: Type) {
+    type_env.remove(&var_key(ident));
+    type_env.remove(&captured_key(ident));
+    type_env.insert(ident.to_string(), t);
+}
+
+/// Value of an int literal slice bound, unary minus included
+fn literal_index(exp: &Exp) -> Option<i32> {
+    match &exp.exp {
+        Expression::Lit{lit: Literal::IntLit{literal}} => Some(*literal),
+        Expression::Primitive{operator: Operator::Minus, left, right} => match (literal_index(left), literal_index(right)) {
+            (Some(l), Some(r)) => l.checked_sub(r),
+            _ => None
+        },
+        _ => None
+    }
+}
+
+pub struct Checker {
+    pub errors: Vec<Diagnostic>,
+    pub warnings: Vec<Diagnostic>,
+    type_env: TypeEnv,
+    nominal_types: HashMap<String, Type>,
+    module_types: HashMap<String, TypeEnv>,
+    loop_depth: usize, // loops around the current expression inside the current function
+    return_types: Vec<Type> // declared return types of the functions being checked, innermost last
+}
+
+impl Checker {
+    pub fn init(env: &Env) -> Checker {
+        let mut type_env = TypeEnv::new();
+        for (ident, value) in env.iter() {
+            if let Val::VarValue{..} = value.value {
+                type_env.insert(var_key(ident), value.val_type.clone());
+            }
+            type_env.insert(ident.clone(), value.val_type.clone());
+        }
+        Checker{
+            errors: vec![],
+            warnings: vec![],
+            type_env,
+            nominal_types: HashMap::new(),
+            module_types: HashMap::new(),
+            loop_depth: 0,
+            return_types: vec![]
+        }
+    }
+
+    pub fn check(&mut self, exp: &Exp) -> Type {
+        trace!("check");
+        let mut type_env = self.type_env.clone();
+        let result_type = self.infer(exp, &mut type_env);
+        for diagnostic in self.warnings.iter().chain(self.errors.iter()) {
+            diagnostic.emit()
+        }
+        result_type
+    }
+
+    fn error(&mut self, code: Code, msg: &str, token: &Token) -> Type {
+        self.report(Diagnostic::error(code, msg, token))
+    }
+
+    fn report(&mut self, diagnostic: Diagnostic) -> Type {
+        self.errors.push(diagnostic);
+        Type::UnknownType
+    }
+
+    fn conforms(&self, actual: &Type, expected: &Type, token: &Token) -> bool {
+        is_unknown(actual) || is_unknown(expected) ||
+            !has_unknown_types(&type_conforms_no_error(actual, expected, token))
+    }
+
+    fn join(&mut self, types: Vec<(Type, Token)>, msg: &str) -> Type {
+        let mut joined_type = Type::UnknownType;
+        let mut joined_token: Option<&Token> = None;
+        for (t, token) in types.iter() {
+            if is_unknown(&joined_type) || (is_empty_list(&joined_type) && matches!(t, Type::ListType{..})) {
+                joined_type = t.clone();
+                joined_token = Some(token)
+            } else if is_empty_list(t) && matches!(joined_type, Type::ListType{..}) {
+                continue
+            } else if self.conforms(t, &joined_type, token) {
+                if !is_unknown(t) {
+                    joined_type = type_conforms_no_error(&joined_type, t, token)
+                }
+            } else {
+                let mut diagnostic = Diagnostic::error(Code::TypeMismatch,
+                    &format!("{}, {} vs. {}", msg, t.as_string(), joined_type.as_string()), token);
+                if let Some(first_token) = joined_token {
+                    diagnostic = diagnostic.label(&format!("{} found here", joined_type.as_string()), first_token)
+                }
+                self.report(diagnostic);
+            }
+        }
+        joined_type
+    }
+
+    fn warning(&mut self, code: Code, msg: &str, token: &Token) {
+        self.warnings.push(Diagnostic::warning(code, msg, token))
+    }
+
+    pub(crate) fn resolve(&self, t: &Type) -> Type {
+        match t {
+            Type::RecordType{ident, fields} if fields.is_empty() => match self.nominal_types.get(ident) {
+                Some(nominal_type) => nominal_type.clone(),
+                _ => t.clone()
+            },
+            Type::EnumType{ident, variants} if variants.is_empty() => match self.nominal_types.get(ident) {
+                Some(nominal_type) => nominal_type.clone(),
+                _ => t.clone()
+            },
+            _ => t.clone()
+        }
+    }
+
+    fn nominal_member(&self, t: &Type, ident: &str) -> Option<Type> {
+        match self.resolve(t) {
+            Type::RecordType{ident: nominal_ident, ..} |
+            Type::EnumType{ident: nominal_ident, ..} if nominal_ident == ident => Some(self.resolve(t)),
+            Type::UnionType{union_types} => union_types.iter()
+                .find_map(|ut| { self.nominal_member(ut, ident) }),
+            _ => None
+        }
+    }
+
+    fn infer(&mut self, exp: &Exp, type_env: &mut TypeEnv) -> Type {
+        trace!("infer: {:?}", exp.exp);
+        match &exp.exp {
+            Expression::Lit{lit} => literal_type(lit),
+            Expression::Let{..} => self.infer_let(exp, type_env),
+            Expression::LetPattern{..} => self.infer_let_pattern(exp, type_env),
+            Expression::Var{..} => self.infer_var(exp, type_env),
+            Expression::Assign{..} => self.infer_assign(exp, type_env),
+            Expression::FnDef{..} => self.infer_fn_def(exp, type_env),
+            Expression::Import{..} => self.infer_import(exp, type_env),
+            Expression::Alias{ident, alias, after_alias_exp} => {
+                match alias {
+                    Type::RecordType{..} | Type::EnumType{..} => {
+                        self.nominal_types.insert(ident.clone(), alias.clone());
+                    },
+                    _ => ()
+                }
+                match &**after_alias_exp {
+                    Some(after_exp) => self.infer(after_exp, type_env),
+                    _ => Type::NullType
+                }
+            },
+            Expression::Lambda{..} => self.infer_lambda(exp, type_env),
+            Expression::Application{..} => self.infer_application(exp, type_env),
+            Expression::Match{..} => self.infer_match(exp, type_env),
+            Expression::Try{try_exp, catch_ident, catch_exp} => {
+                let try_type = self.infer(try_exp, type_env);
+                bind(type_env, catch_ident, error_type());
+                let catch_type = self.infer(catch_exp, type_env);
+                self.join(vec![(try_type, try_exp.token.clone()), (catch_type, catch_exp.token.clone())],
+                    "Try and catch expressions have different types")
+            },
+            Expression::Primitive{operator, left, right} => {
+                let left_type = self.infer(left, type_env);
+                let right_type = self.infer(right, type_env);
+                match operator.result_type(&left_type, &right_type) {
+                    Some(result_type) => result_type,
+                    _ => self.error(Code::InvalidOperation, &format!("Operator {} is not valid for types {} and {}",
+                        operator, left_type.as_string(), right_type.as_string()), &exp.token)
+                }
+            },
+            Expression::Reference{ident} => match type_env.get(ident) {
+                Some(ref_type) => ref_type.clone(),
+                _ => self.report(Diagnostic::error(Code::UnknownReference,
+                    &format!("Reference \"{}\" does not exist", ident), &exp.token)
+                    .help(suggest(ident, type_env.keys())))
+            },
+            Expression::Placeholder => self.error(Code::InvalidSyntax, "Placeholder \"_\" is only valid as a function argument", &exp.token),
+            Expression::Interpolation{parts} => {
+                for part in parts.iter() {
+                    self.infer(part, type_env);
+                }
+                Type::StringType
+            },
+            Expression::Branch{condition, if_branch, else_branch} => {
+                let condition_type = self.infer(condition, type_env);
+                if !self.conforms(&condition_type, &Type::BoolType, &condition.token) {
+                    self.error(Code::TypeMismatch, &format!("Condition must be of type bool, found {}", condition_type.as_string()), &condition.token);
+                }
+                let if_type = self.infer(if_branch, &mut type_env.clone());
+                match &**else_branch {
+                    Some(else_exp) => {
+                        let else_type = self.infer(else_exp, &mut type_env.clone());
+                        self.join(vec![(if_type, if_branch.token.clone()), (else_type, else_exp.token.clone())],
+                            "Branch types do not match")
+                    },
+                    _ => Type::NullType
+                }
+            },
+            Expression::Iter{iter, iter_exp} => {
+                let iter_type = self.infer(iter, type_env);
+                let elem_type = self.iter_element_type(iter_type, &iter.token);
+                let mut iter_env = type_env.clone();
+                bind(&mut iter_env, "__elem", elem_type);
+                self.infer_loop_body(iter_exp, &mut iter_env);
+                Type::NullType
+            },
+            Expression::While{condition, body} => {
+                let condition_type = self.infer(condition, type_env);
+                if !self.conforms(&condition_type, &Type::BoolType, &condition.token) {
+                    self.error(Code::TypeMismatch, &format!("Condition must be of type bool, found {}", condition_type.as_string()), &condition.token);
+                }
+                self.infer_loop_body(body, &mut type_env.clone());
+                Type::NullType
+            },
+            Expression::For{index, pattern, iter, body} => {
+                let iter_type = self.infer(iter, type_env);
+                let elem_type = self.iter_element_type(iter_type, &iter.token);
+                let mut body_env = type_env.clone();
+                if let Some(index_ident) = index {
+                    bind(&mut body_env, index_ident, Type::IntType);
+                }
+                self.check_pattern(pattern, &elem_type, &mut body_env, &exp.token);
+                self.infer_loop_body(body, &mut body_env);
+                Type::NullType
+            },
+            Expression::Break | Expression::Continue => {
+                if self.loop_depth == 0 {
+                    self.error(Code::InvalidControlFlow, &format!("\"{}\" outside of a loop",
+                        if exp.exp == Expression::Break { "break" } else { "continue" }), &exp.token);
+                }
+                Type::UnknownType
+            },
+            Expression::Return{value} => {
+                let value_type = match &**value {
+                    Some(value_exp) => self.infer(value_exp, type_env),
+                    _ => Type::NullType
+                };
+                match self.return_types.last().cloned() {
+                    Some(return_type) => if !self.conforms(&value_type, &return_type, &exp.token) {
+                        self.error(Code::TypeMismatch, &format!("Returned value of type {} does not conform to return type {}",
+                            value_type.as_string(), return_type.as_string()), &exp.token);
+                    },
+                    _ => { self.error(Code::InvalidControlFlow, "\"return\" outside of a function", &exp.token); }
+                }
+                Type::UnknownType
+            },
+            Expression::ListDef{values} => {
+                let value_types: Vec<(Type, Token)> = values.iter()
+                    .map(|value| { (self.infer(value, type_env), value.token.clone()) })
+                    .collect();
+                Type::ListType{list_type: Box::new(self.join(value_types, "List elements do not match"))}
+            },
+            Expression::Comprehension{..} => self.infer_comprehension(exp, type_env),
+            Expression::Slice{..} => self.error(Code::InvalidSyntax, "Slices are only valid in brackets, e.g. l[1:3]", &exp.token),
+            Expression::RangeDef{start, end, step, ..} => {
+                let start_type = self.infer(start, type_env);
+                let end_type = self.infer(end, type_env);
+                if let Some(step_exp) = &**step {
+                    let step_type = self.infer(step_exp, type_env);
+                    if !self.conforms(&step_type, &Type::IntType, &step_exp.token) {
+                        self.error(Code::TypeMismatch, &format!("Range step must be of type int, found {}", step_type.as_string()), &step_exp.token);
+                    }
+                }
+                let range_type = self.join(vec![(start_type, start.token.clone()), (end_type, end.token.clone())],
+                    "Range bounds do not match");
+                match range_type {
+                    Type::IntType | Type::CharType => Type::ListType{list_type: Box::new(range_type)},
+                    t if is_unknown(&t) => Type::ListType{list_type: Box::new(Type::UnknownType)},
+                    t => self.error(Code::TypeMismatch, &format!("Range bounds must be of type int or char, found {}", t.as_string()), &exp.token)
+                }
+            },
+            Expression::TupleDef{values} => Type::TupleType{
+                tuple_types: values.iter().map(|value| { self.infer(value, type_env) }).collect()
+            },
+            Expression::TupleAccess{ident, index} => {
+                match self.infer(ident, type_env) {
+                    Type::TupleType{tuple_types} => match tuple_types.get(*index) {
+                        Some(tuple_type) => tuple_type.clone(),
+                        _ => self.error(Code::UnknownField, &format!("Tuple index {} out of range for {}", index,
+                            Type::TupleType{tuple_types: tuple_types.clone()}.as_string()), &exp.token)
+                    },
+                    t if is_unknown(&t) => Type::UnknownType,
+                    t => self.error(Code::InvalidOperation, &format!("Type {} does not support tuple access", t.as_string()), &exp.token)
+                }
+            },
+            Expression::FieldAccess{ident, field} => {
+                let record_type = self.infer(ident, type_env);
+                match self.resolve(&record_type) {
+                    Type::RecordType{ident: record_ident, fields} => match fields.iter().find(|f| { f.0 == *field }) {
+                        Some((_, field_type)) => field_type.clone(),
+                        _ => self.report(Diagnostic::error(Code::UnknownField,
+                            &format!("Field \"{}\" does not exist on record {}", field, record_ident), &exp.token)
+                            .help(suggest(field, fields.iter().map(|f| { &f.0 }))))
+                    },
+                    t if is_unknown(&t) => Type::UnknownType,
+                    t => self.error(Code::InvalidOperation, &format!("Type {} does not support field access", t.as_string()), &exp.token)
+                }
+            },
+            Expression::RecordDef{fields, ..} => {
+                let field_types = match self.resolve(&exp.exp_type) {
+                    Type::RecordType{fields, ..} => fields,
+                    _ => vec![]
+                };
+                for ((_, field_exp), (field, field_type)) in fields.iter().zip(field_types) {
+                    let value_type = self.infer(field_exp, type_env);
+                    if !self.conforms(&value_type, &field_type, &field_exp.token) {
+                        self.error(Code::TypeMismatch, &format!("Field \"{}\" of type {} does not conform to {}",
+                            field, value_type.as_string(), field_type.as_string()), &field_exp.token);
+                    }
+                }
+                exp.exp_type.clone()
+            },
+            Expression::EnumDef{variant, values, ..} => {
+                let variant_types = match self.resolve(&exp.exp_type) {
+                    Type::EnumType{variants, ..} => match variants.into_iter().find(|v| { v.0 == *variant }) {
+                        Some((_, variant_types)) => variant_types,
+                        _ => vec![]
+                    },
+                    _ => vec![]
+                };
+                for (value, variant_type) in values.iter().zip(variant_types) {
+                    let value_type = self.infer(value, type_env);
+                    if !self.conforms(&value_type, &variant_type, &value.token) {
+                        self.error(Code::TypeMismatch, &format!("Variant \"{}\" value of type {} does not conform to {}",
+                            variant, value_type.as_string(), variant_type.as_string()), &value.token);
+                    }
+                }
+                exp.exp_type.clone()
+            },
+            Expression::DictDef{mapping} => {
+                let key_types: Vec<(Type, Token)> = mapping.iter()
+                    .map(|(key, value)| { (literal_type(key), value.token.clone()) })
+                    .collect();
+                let value_types: Vec<(Type, Token)> = mapping.iter()
+                    .map(|(_, value)| { (self.infer(value, type_env), value.token.clone()) })
+                    .collect();
+                Type::DictType{
+                    key_type: Box::new(self.join(key_types, "Dictionary keys do not match")),
+                    value_type: Box::new(self.join(value_types, "Dictionary values do not match"))
+                }
+            },
+            Expression::SchemaDef{..} => self.infer_schema_def(exp, type_env),
+            Expression::Empty => Type::NullType
+        }
+    }
+
+    fn iter_element_type(&mut self, iter_type: Type, token: &Token) -> Type {
+        match iter_type {
+            Type::IntType => Type::IntType,
+            Type::StringType => Type::CharType,
+            Type::ListType{list_type} => *list_type,
+            Type::SeqType{seq_type} => *seq_type,
+            Type::TupleType{..} | Type::UnknownType | Type::Any => Type::UnknownType,
+            t => self.error(Code::InvalidOperation, &format!("Type {} is not iterable", t.as_string()), token)
+        }
+    }
+
+    /// Each column constraint must fit its column's type and each check must be a bool over the columns
+    fn infer_schema_def(&mut self, exp: &Exp, type_env: &mut TypeEnv) -> Type {
+        trace!("infer_schema_def");
+        match &exp.exp {
+            Expression::SchemaDef{mapping, constraints} => {
+                let column_type = |column: &str| -> Type {
+                    mapping.iter().find(|(ident, _)| { ident == column }).map_or(Type::UnknownType, |(_, t)| { t.clone() })
+                };
+                for constraint in constraints.iter() {
+                    let (column, required_types, kind) = match constraint {
+                        Constraint::Min{column, ..} => (column, vec![Type::IntType], "min"),
+                        Constraint::Max{column, ..} => (column, vec![Type::IntType], "max"),
+                        Constraint::Regex{column, ..} => (column, vec![Type::StringType], "regex"),
+                        Constraint::Allowed{column, values} => (column, values.iter().map(literal_type).collect(), "in"),
+                        Constraint::Check{condition} => {
+                            let mut check_env = type_env.clone();
+                            for (ident, col_type) in mapping.iter() {
+                                bind(&mut check_env, ident, col_type.clone());
+                            }
+                            let condition_type = self.infer(condition, &mut check_env);
+                            if !self.conforms(&condition_type, &Type::BoolType, &condition.token) {
+                                self.error(Code::TypeMismatch, &format!("Schema check must be of type bool, found {}",
+                                    condition_type.as_string()), &condition.token);
+                            }
+                            continue
+                        },
+                        Constraint::NotNull{..} | Constraint::Unique{..} => continue
+                    };
+                    let col_type = column_type(column);
+                    for required_type in required_types.iter() {
+                        if !self.conforms(required_type, &col_type, &exp.token) {
+                            self.error(Code::TypeMismatch, &format!("Constraint \"{}\" on column \"{}\" needs type {}, column is {}",
+                                kind, column, required_type.as_string(), col_type.as_string()), &exp.token);
+                        }
+                    }
+                }
+                exp.exp_type.clone()
+            },
+            _ => Type::UnknownType
+        }
+    }
+
+    fn infer_comprehension(&mut self, exp: &Exp, type_env: &mut TypeEnv) -> Type {
+        trace!("infer_comprehension");
+        match &exp.exp {
+            Expression::Comprehension{key, value, clauses} => {
+                let mut comprehension_env = type_env.clone();
+                for clause in clauses.iter() {
+                    match clause {
+                        Clause::For{index, pattern, iter} => {
+                            let iter_type = self.infer(iter, &mut comprehension_env);
+                            let elem_type = self.iter_element_type(iter_type, &iter.token);
+                            if let Some(index_ident) = index {
+                                bind(&mut comprehension_env, index_ident, Type::IntType);
+                            }
+                            self.check_pattern(pattern, &elem_type, &mut comprehension_env, &iter.token);
+                        },
+                        Clause::If{condition} => {
+                            let condition_type = self.infer(condition, &mut comprehension_env);
+                            if !self.conforms(&condition_type, &Type::BoolType, &condition.token) {
+                                self.error(Code::TypeMismatch, &format!("Condition must be of type bool, found {}",
+                                    condition_type.as_string()), &condition.token);
+                            }
+                        }
+                    }
+                }
+                let value_type = self.infer(value, &mut comprehension_env);
+                match &**key {
+                    Some(key_exp) => Type::DictType{
+                        key_type: Box::new(self.infer(key_exp, &mut comprehension_env)),
+                        value_type: Box::new(value_type)
+                    },
+                    _ => Type::ListType{list_type: Box::new(value_type)}
+                }
+            },
+            _ => Type::UnknownType
+        }
+    }
+
+    fn infer_slice(&mut self, sliced_type: Type, slice: &Exp, type_env: &mut TypeEnv) -> Type {
+        trace!("infer_slice");
+        match &slice.exp {
+            Expression::Slice{start, end, step} => {
+                for bound in [&**start, &**end, &**step].iter().filter_map(|bound| { bound.as_ref() }) {
+                    let bound_type = self.infer(bound, type_env);
+                    if !self.conforms(&bound_type, &Type::IntType, &bound.token) {
+                        self.error(Code::TypeMismatch, &format!("Slice bounds must be of type int, found {}",
+                            bound_type.as_string()), &bound.token);
+                    }
+                }
+                match sliced_type {
+                    Type::StringType | Type::ListType{..} => sliced_type,
+                    Type::TupleType{tuple_types} => {
+                        let literal_bound = |bound: &Option<Exp>| { bound.as_ref().map(literal_index) };
+                        match (literal_bound(start), literal_bound(end), literal_bound(step)) {
+                            (Some(None), _, _) | (_, Some(None), _) | (_, _, Some(None)) =>
+                                self.error(Code::InvalidOperation, "Tuple slices need literal bounds", &slice.token),
+                            (_, _, Some(Some(0))) => self.error(Code::InvalidOperation, "Slice step cannot be 0", &slice.token),
+                            (start, end, step) => Type::TupleType{
+                                tuple_types: slice_indices(tuple_types.len(), start.flatten(), end.flatten(), step.flatten().unwrap_or(1))
+                                    .into_iter()
+                                    .map(|i| { tuple_types[i].clone() })
+                                    .collect()
+                            }
+                        }
+                    },
+                    t if is_unknown(&t) => Type::UnknownType,
+                    t => self.error(Code::InvalidOperation, &format!("Type {} cannot be sliced", t.as_string()), &slice.token)
+                }
+            },
+            _ => Type::UnknownType
+        }
+    }
+
+    fn infer_loop_body(&mut self, body: &Exp, type_env: &mut TypeEnv) {
+        self.loop_depth += 1;
+        self.infer(body, type_env);
+        self.loop_depth -= 1
+    }
+
+    fn infer_let(&mut self, exp: &Exp, type_env: &mut TypeEnv) -> Type {
+        trace!("infer_let");
+        match &exp.exp {
+            Expression::Let{ident, let_type, let_exp, after_let_exp} => {
+                let exp_type = self.infer(let_exp, type_env);
+                let binding_type = if is_unknown(let_type) {
+                    exp_type
+                } else {
+                    if !self.conforms(&exp_type, let_type, &let_exp.token) {
+                        self.report(Diagnostic::error(Code::TypeMismatch,
+                            &format!("Type {} does not conform to declared type {} of \"{}\"",
+                                exp_type.as_string(), let_type.as_string(), ident), &let_exp.token)
+                            .label("expected type declared here", &exp.token));
+                    }
+                    let_type.clone()
+                };
+                bind(type_env, ident, binding_type.clone());
+                match &**after_let_exp {
+                    Some(after_exp) => self.infer(after_exp, type_env),
+                    _ => binding_type
+                }
+            },
+            _ => Type::UnknownType
+        }
+    }
+
+    fn infer_let_pattern(&mut self, exp: &Exp, type_env: &mut TypeEnv) -> Type {
+        trace!("infer_let_pattern");
+        match &exp.exp {
+            Expression::LetPattern{pattern, let_type, let_exp, after_let_exp} => {
+                let exp_type = self.infer(let_exp, type_env);
+                let binding_type = if is_unknown(let_type) {
+                    exp_type
+                } else {
+                    if !self.conforms(&exp_type, let_type, &let_exp.token) {
+                        self.report(Diagnostic::error(Code::TypeMismatch,
+                            &format!("Type {} does not conform to declared type {}",
+                                exp_type.as_string(), let_type.as_string()), &let_exp.token)
+                            .label("expected type declared here", &exp.token));
+                    }
+                    let_type.clone()
+                };
+                self.check_pattern(pattern, &binding_type, type_env, &exp.token);
+                match &**after_let_exp {
+                    Some(after_exp) => self.infer(after_exp, type_env),
+                    _ => Type::NullType
+                }
+            },
+            _ => Type::UnknownType
+        }
+    }
+
+    fn infer_var(&mut self, exp: &Exp, type_env: &mut TypeEnv) -> Type {
+        trace!("infer_var");
+        match &exp.exp {
+            Expression::Var{ident, var_type, var_exp, after_var_exp} => {
+                let exp_type = self.infer(var_exp, type_env);
+                let binding_type = if is_unknown(var_type) {
+                    exp_type
+                } else {
+                    if !self.conforms(&exp_type, var_type, &var_exp.token) {
+                        self.report(Diagnostic::error(Code::TypeMismatch,
+                            &format!("Type {} does not conform to declared type {} of \"{}\"",
+                                exp_type.as_string(), var_type.as_string(), ident), &var_exp.token)
+                            .label("expected type declared here", &exp.token));
+                    }
+                    var_type.clone()
+                };
+                type_env.insert(ident.clone(), binding_type.clone());
+                type_env.insert(var_key(ident), binding_type.clone());
+                match &**after_var_exp {
+                    Some(after_exp) => self.infer(after_exp, type_env),
+                    _ => binding_type
+                }
+            },
+            _ => Type::UnknownType
+        }
+    }
+
+    fn infer_assign(&mut self, exp: &Exp, type_env: &mut TypeEnv) -> Type {
+        trace!("infer_assign");
+        match &exp.exp {
+            Expression::Assign{ident, value} => {
+                let value_type = self.infer(value, type_env);
+                match type_env.get(&var_key(ident)).cloned() {
+                    Some(var_type) => if !self.conforms(&value_type, &var_type, &value.token) {
+                        self.error(Code::TypeMismatch, &format!("Type {} does not conform to type {} of var \"{}\"",
+                            value_type.as_string(), var_type.as_string(), ident), &value.token);
+                    },
+                    _ if type_env.contains_key(&captured_key(ident)) => {
+                        self.report(Diagnostic::error(Code::ImmutableAssignment,
+                            &format!("Cannot assign to \"{}\", it is not a var of this function", ident), &exp.token)
+                            .help(Some("functions cannot assign vars from an enclosing scope".to_string())));
+                    },
+                    _ if type_env.contains_key(ident) => {
+                        self.report(Diagnostic::error(Code::ImmutableAssignment,
+                            &format!("Cannot assign to \"{}\", it is not a var", ident), &exp.token)
+                            .help(Some("declare it with \"var\" instead of \"let\"".to_string())));
+                    },
+                    _ => {
+                        self.report(Diagnostic::error(Code::UnknownReference,
+                            &format!("Reference \"{}\" does not exist", ident), &exp.token)
+                            .help(suggest(ident, type_env.keys())));
+                    }
+                }
+                Type::NullType
+            },
+            _ => Type::UnknownType
+        }
+    }
+
+    fn infer_import(&mut self, exp: &Exp, type_env: &mut TypeEnv) -> Type {
+        trace!("infer_import");
+        match &exp.exp {
+            Expression::Import{path, module, items, namespace, after_import_exp} => {
+                let exports = match self.module_types.get(path) {
+                    Some(exports) => exports.clone(),
+                    _ => {
+                        let mut module_env = self.type_env.clone();
+                        self.infer(module, &mut module_env);
+                        let exports: TypeEnv = module_env.into_iter()
+                            .filter(|(ident, t)| { !ident.contains('$') && self.type_env.get(ident) != Some(t) })
+                            .collect();
+                        self.module_types.insert(path.clone(), exports.clone());
+                        exports
+                    }
+                };
+                match (items, namespace) {
+                    (Some(selected), _) => for ident in selected.iter() {
+                        match exports.get(ident) {
+                            Some(t) => bind(type_env, ident, t.clone()),
+                            _ => {
+                                self.report(Diagnostic::error(Code::UnknownField,
+                                    &format!("Module \"{}\" has no export \"{}\"", path, ident), &exp.token)
+                                    .help(suggest(ident, exports.keys())));
+                            }
+                        }
+                    },
+                    (_, Some(ns)) => {
+                        let mut fields: Vec<(String, Type)> = exports.into_iter().collect();
+                        fields.sort_by(|a, b| { a.0.cmp(&b.0) });
+                        bind(type_env, ns, Type::RecordType{ident: ns.clone(), fields});
+                    },
+                    _ => for (ident, t) in exports {
+                        bind(type_env, &ident, t)
+                    }
+                }
+                match &**after_import_exp {
+                    Some(after) => self.infer(after, type_env),
+                    _ => Type::NullType
+                }
+            },
+            _ => Type::UnknownType
+        }
+    }
+
+    fn infer_fn_def(&mut self, exp: &Exp, type_env: &mut TypeEnv) -> Type {
+        trace!("infer_fn_def");
+        match &exp.exp {
+            Expression::FnDef{defs, after_fn_exp} => {
+                for (ident, lambda) in defs.iter() { // signatures first so the group can be mutually recursive
+                    bind(type_env, ident, lambda.exp_type.clone());
+                }
+                for (_, lambda) in defs.iter() {
+                    self.infer_lambda(lambda, type_env);
+                }
+                match &**after_fn_exp {
+                    Some(after) => self.infer(after, type_env),
+                    _ => Type::NullType
+                }
+            },
+            _ => Type::UnknownType
+        }
+    }
+
+    fn infer_lambda(&mut self, exp: &Exp, type_env: &mut TypeEnv) -> Type {
+        trace!("infer_lambda");
+        match &exp.exp {
+            Expression::Lambda{params, return_type, body} => {
+                let mut body_env = type_env.clone();
+                let captured: Vec<(String, Type)> = body_env.iter()
+                    .filter_map(|(key, t)| { key.strip_prefix("var$").map(|ident| { (captured_key(ident), t.clone()) }) })
+                    .collect();
+                body_env.retain(|ident, _| { !ident.starts_with("var$") }); // captured vars are read-only copies
+                body_env.extend(captured);
+                for param in params.iter() {
+                    bind(&mut body_env, &param.ident, param.param_type.clone());
+                }
+                let loop_depth = std::mem::replace(&mut self.loop_depth, 0);
+                self.return_types.push(return_type.clone());
+                let body_type = self.infer(body, &mut body_env);
+                self.return_types.pop();
+                self.loop_depth = loop_depth;
+                if !self.conforms(&body_type, return_type, &body.token) {
+                    self.report(Diagnostic::error(Code::TypeMismatch,
+                        &format!("Lambda body of type {} does not conform to return type {}",
+                            body_type.as_string(), return_type.as_string()), &body.token)
+                        .label("return type declared here", &exp.token));
+                }
+                Type::FuncType{
+                    param_types: params.iter().map(|p| { p.param_type.clone() }).collect(),
+                    return_type: Box::new(return_type.clone())
+                }
+            },
+            _ => Type::UnknownType
+        }
+    }
+
+    fn infer_application(&mut self, exp: &Exp, type_env: &mut TypeEnv) -> Type {
+        trace!("infer_application");
+        match &exp.exp {
+            Expression::Application{ident, args} => {
+                let ident_type = self.infer(ident, type_env);
+                if let [slice@Exp{exp: Expression::Slice{..}, ..}] = args.as_slice() {
+                    return self.infer_slice(ident_type, slice, type_env)
+                }
+                let is_partial = args.iter().any(|arg| { arg.exp == Expression::Placeholder });
+                if is_partial && !matches!(ident_type, Type::FuncType{..}) && !is_unknown(&ident_type) {
+                    return self.error(Code::InvalidSyntax, "Placeholder \"_\" is only valid in function applications", &exp.token)
+                }
+                let arg_types: Vec<Type> = args.iter()
+                    .map(|arg| { if arg.exp == Expression::Placeholder { Type::UnknownType } else { self.infer(arg, type_env) } })
+                    .collect();
+                let index_arg = |checker: &mut Checker, key_type: &Type, kind: &str| {
+                    if arg_types.len() != 1 {
+                        checker.error(Code::ArgumentCount, &format!("Argument count must be 1 for {}", kind), &exp.token);
+                    } else if !checker.conforms(&arg_types[0], key_type, &args[0].token) {
+                        checker.error(Code::TypeMismatch, &format!("Type {} is not valid for {}, expected {}",
+                            arg_types[0].as_string(), kind, key_type.as_string()), &args[0].token);
+                    }
+                };
+                match ident_type {
+                    Type::FuncType{param_types, return_type} => {
+                        if args.len() > param_types.len() || args.is_empty() && !param_types.is_empty() {
+                            return self.error(Code::ArgumentCount, &format!("Function expects {} argument(s), found {}",
+                                param_types.len(), args.len()), &exp.token)
+                        }
+                        let builtin_ident = match &ident.exp {
+                            Expression::Reference{ident} => Keyword::from_str(ident).ok().filter(|k| { k.is_builtin_function() }),
+                            _ => None
+                        };
+                        let (param_types, return_type) = match &builtin_ident {
+                            Some(builtin_ident) => {
+                                let seq_types: Vec<Type> = param_types.iter().enumerate()
+                                    .map(|(i, param_type)| { match arg_types.get(i) {
+                                        Some(arg_type) => seq_param_type(builtin_ident, param_type, arg_type),
+                                        _ => param_type.clone()
+                                    }})
+                                    .collect();
+                                let lazy = matches!(builtin_ident, Keyword::Map | Keyword::Filter) &&
+                                    matches!(arg_types.first(), Some(Type::SeqType{..}));
+                                match *return_type {
+                                    Type::ListType{list_type} if lazy => (seq_types, Box::new(Type::SeqType{seq_type: list_type})),
+                                    return_type => (seq_types, Box::new(return_type))
+                                }
+                            },
+                            _ => (param_types, return_type)
+                        };
+                        let mut type_bindings: HashMap<String, Type> = HashMap::new();
+                        for ((param_type, arg_type), arg) in param_types.iter().zip(arg_types.iter()).zip(args) {
+                            if !is_unknown(arg_type) && !unify(param_type, arg_type, &mut type_bindings, &arg.token) {
+                                let expected_type = substitute(param_type, &type_bindings).as_string();
+                                self.report(Diagnostic::error(Code::TypeMismatch,
+                                    &format!("Argument of type {} does not conform to parameter of type {}",
+                                        arg_type.as_string(), expected_type), &arg.token)
+                                    .label(&format!("function expects {} here", expected_type), &ident.token));
+                            }
+                        }
+                        if is_partial || args.len() < param_types.len() { // the open parameters become a new function
+                            let open_types = param_types.iter().enumerate()
+                                .filter(|(i, _)| { args.get(*i).is_none_or(|arg| { arg.exp == Expression::Placeholder }) })
+                                .map(|(_, param_type)| { substitute(param_type, &type_bindings) })
+                                .collect();
+                            Type::FuncType{param_types: open_types, return_type: Box::new(substitute(&return_type, &type_bindings))}
+                        } else {
+                            substitute(&return_type, &type_bindings)
+                        }
+                    },
+                    Type::StringType => {
+                        index_arg(self, &Type::IntType, "string indexing");
+                        Type::CharType
+                    },
+                    Type::ListType{list_type} => {
+                        index_arg(self, &Type::IntType, "list indexing");
+                        *list_type
+                    },
+                    Type::DictType{key_type, value_type} => {
+                        index_arg(self, &key_type, "dictionary access");
+                        *value_type
+                    },
+                    t if is_unknown(&t) => Type::UnknownType,
+                    t => self.error(Code::InvalidOperation, &format!("Type {} is invalid for application", t.as_string()), &exp.token)
+                }
+            },
+            _ => Type::UnknownType
+        }
+    }
+
+    fn infer_match(&mut self, exp: &Exp, type_env: &mut TypeEnv) -> Type {
+        trace!("infer_match");
+        match &exp.exp {
+            Expression::Match{match_exp, cases} => {
+                let match_type = self.infer(match_exp, type_env);
+                let mut case_types: Vec<(Type, Token)> = vec![];
+                for case in cases.iter() {
+                    let mut case_env = type_env.clone();
+                    self.check_pattern(&case.pattern, &match_type, &mut case_env, &case.case_exp.token);
+                    if let Some(guard) = &case.guard {
+                        let guard_type = self.infer(guard, &mut case_env);
+                        if !self.conforms(&guard_type, &Type::BoolType, &guard.token) {
+                            self.error(Code::TypeMismatch, &format!("Case guard must be of type bool, found {}",
+                                guard_type.as_string()), &guard.token);
+                        }
+                    }
+                    case_types.push((self.infer(&case.case_exp, &mut case_env), case.case_exp.token.clone()));
+                }
+                let pattern_space = PatternSpace::init(self);
+                let missing_case = pattern_space.missing_case(cases, &match_type);
+                let unreachable_cases = pattern_space.unreachable_cases(cases, &match_type);
+                if let Some(missing) = missing_case {
+                    self.error(Code::NonExhaustiveMatch, &format!("Match is not exhaustive, missing case {}", missing), &exp.token);
+                }
+                for index in unreachable_cases {
+                    self.warning(Code::UnreachableCase, "Unreachable case, earlier cases already cover it", &cases[index].token)
+                }
+                self.join(case_types, "Match case types do not match")
+            },
+            _ => Type::UnknownType
+        }
+    }
+
+    fn check_pattern(&mut self, pattern: &Pattern, match_type: &Type, type_env: &mut TypeEnv, token: &Token) {
+        trace!("check_pattern: {:?}", pattern);
+        let check_conforms = |checker: &mut Checker, pattern_type: &Type| {
+            if !checker.conforms(pattern_type, match_type, token) {
+                checker.error(Code::TypeMismatch, &format!("Pattern of type {} can never match value of type {}",
+                    pattern_type.as_string(), match_type.as_string()), token);
+            }
+        };
+        match pattern {
+            Pattern::TypePattern{ident, case_type} => {
+                check_conforms(self, case_type);
+                bind(type_env, ident, case_type.clone());
+            },
+            Pattern::Literal{literal} => check_conforms(self, &literal_type(literal)),
+            Pattern::MultiLiteral{literals} => literals.iter()
+                .for_each(|literal| { check_conforms(self, &literal_type(literal)) }),
+            Pattern::Range{start, ..} => check_conforms(self, &literal_type(start)),
+            Pattern::Regex{regex} => {
+                check_conforms(self, &Type::StringType);
+                if let Ok(compiled) = Regex::new(regex) {
+                    for name in compiled.capture_names().flatten() {
+                        bind(type_env, name, Type::StringType);
+                    }
+                }
+            },
+            Pattern::Variant{ident, variant, fields} => {
+                match self.nominal_member(match_type, ident) {
+                    Some(Type::EnumType{variants, ..}) => {
+                        let variant_types = match variants.into_iter().find(|v| { v.0 == *variant }) {
+                            Some((_, variant_types)) => variant_types,
+                            _ => vec![]
+                        };
+                        for (field, field_type) in fields.iter().zip(variant_types) {
+                            self.check_pattern(field, &field_type, type_env, token)
+                        }
+                    },
+                    _ => {
+                        check_conforms(self, &Type::EnumType{ident: ident.clone(), variants: vec![]});
+                        for field in fields.iter() {
+                            self.check_pattern(field, &Type::UnknownType, type_env, token)
+                        }
+                    }
+                }
+            },
+            Pattern::Record{ident, fields} => {
+                match self.nominal_member(match_type, ident) {
+                    Some(Type::RecordType{fields: field_types, ..}) => {
+                        for (field, field_pattern) in fields.iter() {
+                            let field_type = match field_types.iter().find(|f| { f.0 == *field }) {
+                                Some((_, field_type)) => field_type.clone(),
+                                _ => Type::UnknownType
+                            };
+                            self.check_pattern(field_pattern, &field_type, type_env, token)
+                        }
+                    },
+                    _ => {
+                        check_conforms(self, &Type::RecordType{ident: ident.clone(), fields: vec![]});
+                        for (_, field_pattern) in fields.iter() {
+                            self.check_pattern(field_pattern, &Type::UnknownType, type_env, token)
+                        }
+                    }
+                }
+            },
+            Pattern::Tuple{fields} => {
+                match self.resolve(match_type) {
+                    Type::TupleType{tuple_types} => {
+                        if tuple_types.len() != fields.len() {
+                            self.error(Code::TypeMismatch, &format!("Tuple pattern has {} field(s), value of type {} has {}",
+                                fields.len(), match_type.as_string(), tuple_types.len()), token);
+                        }
+                        for (field, field_type) in fields.iter().zip(tuple_types) {
+                            self.check_pattern(field, &field_type, type_env, token)
+                        }
+                    },
+                    t => {
+                        if !is_unknown(&t) && !is_union_type(&t) {
+                            self.error(Code::TypeMismatch, &format!("Tuple pattern can never match value of type {}", t.as_string()), token);
+                        }
+                        for field in fields.iter() {
+                            self.check_pattern(field, &Type::UnknownType, type_env, token)
+                        }
+                    }
+                }
+            },
+            Pattern::List{elems, rest} => {
+                let elem_type = match self.resolve(match_type) {
+                    Type::ListType{list_type} => *list_type,
+                    t => {
+                        if !is_unknown(&t) && !is_union_type(&t) {
+                            self.error(Code::TypeMismatch, &format!("List pattern can never match value of type {}", t.as_string()), token);
+                        }
+                        Type::UnknownType
+                    }
+                };
+                for elem in elems.iter() {
+                    self.check_pattern(elem, &elem_type, type_env, token)
+                }
+                if let Some(rest_pattern) = rest {
+                    self.check_pattern(rest_pattern, &Type::ListType{list_type: Box::new(elem_type)}, type_env, token)
+                }
+            },
+            Pattern::Dict{entries} => {
+                let (key_type, value_type) = match self.resolve(match_type) {
+                    Type::DictType{key_type, value_type} => (*key_type, *value_type),
+                    t => {
+                        if !is_unknown(&t) && !is_union_type(&t) {
+                            self.error(Code::TypeMismatch, &format!("Dict pattern can never match value of type {}", t.as_string()), token);
+                        }
+                        (Type::UnknownType, Type::UnknownType)
+                    }
+                };
+                for (key, entry_pattern) in entries.iter() {
+                    if !self.conforms(&literal_type(key), &key_type, token) {
+                        self.error(Code::TypeMismatch, &format!("Dict pattern key of type {} can never match key of type {}",
+                            literal_type(key).as_string(), key_type.as_string()), token);
+                    }
+                    self.check_pattern(entry_pattern, &value_type, type_env, token)
+                }
+            },
+            Pattern::Bind{ident} => bind(type_env, ident, match_type.clone()),
+            Pattern::Any => ()
+        }
+    }
+}
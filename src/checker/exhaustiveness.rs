@@ -0,0 +1,314 @@
+use log::trace;
+
+use crate::checker::checker::{Checker, is_unknown, literal_type};
+use crate::defs::expression::{Case, Literal, Pattern};
+use crate::defs::retl_type::{Type, type_conforms_no_error, has_unknown_types};
+use crate::scanner::token::make_empty_token;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Constructor {
+    Bool{value: bool},
+    Null,
+    Variant{ident: String, variant: String},
+    Member{member_type: Type},
+    Tuple,
+    Record{ident: String, fields: Vec<String>},
+    ListLength{length: usize},
+    ListAtLeast{length: usize}
+}
+
+type Row = Vec<Pattern>;
+
+fn literal_in_range(literal: &Literal, start: &Literal, end: &Literal, inclusive: bool) -> bool {
+    match (literal, start, end) {
+        (Literal::IntLit{literal: l}, Literal::IntLit{literal: s}, Literal::IntLit{literal: e}) =>
+            s <= l && (l < e || (inclusive && l == e)),
+        (Literal::CharLit{literal: l}, Literal::CharLit{literal: s}, Literal::CharLit{literal: e}) =>
+            s <= l && (l < e || (inclusive && l == e)),
+        _ => false
+    }
+}
+
+fn render(constructor: &Constructor, fields: &[String]) -> String {
+    match constructor {
+        Constructor::Bool{value} => value.to_string(),
+        Constructor::Null => "null".to_string(),
+        Constructor::Variant{ident, variant} if fields.is_empty() => format!("{}::{}", ident, variant),
+        Constructor::Variant{ident, variant} => format!("{}::{}({})", ident, variant, fields.join(", ")),
+        Constructor::Member{member_type} if fields[0] == "_" => format!("_: {}", member_type.as_string()),
+        Constructor::Member{..} => fields[0].clone(),
+        Constructor::Tuple => format!("({})", fields.join(", ")),
+        Constructor::Record{ident, fields: names} => format!("{} {{ {} }}", ident, names.iter().zip(fields)
+            .map(|(name, field)| { format!("{}: {}", name, field) })
+            .collect::<Vec<String>>()
+            .join(", ")),
+        Constructor::ListLength{..} => format!("[{}]", fields.join(", ")),
+        Constructor::ListAtLeast{..} => format!("[{}, ..]", fields.join(", "))
+    }
+}
+
+/// Pattern matrix analysis over the cases of a match expression.
+pub struct PatternSpace<'a> {
+    checker: &'a Checker
+}
+
+impl<'a> PatternSpace<'a> {
+    pub fn init(checker: &'a Checker) -> PatternSpace<'a> {
+        PatternSpace{checker}
+    }
+
+    /// Returns an example value that none of the unguarded cases match.
+    pub fn missing_case(&self, cases: &[Case], match_type: &Type) -> Option<String> {
+        trace!("missing_case: {:?}", match_type);
+        let rows: Vec<Row> = cases.iter()
+            .filter(|case| { case.guard.is_none() })
+            .map(|case| { vec![case.pattern.clone()] })
+            .collect();
+        self.missing(&rows, std::slice::from_ref(match_type)).map(|witness| { witness[0].clone() })
+    }
+
+    /// Returns the indices of cases already covered by the unguarded cases before them.
+    pub fn unreachable_cases(&self, cases: &[Case], match_type: &Type) -> Vec<usize> {
+        trace!("unreachable_cases: {:?}", match_type);
+        let mut rows: Vec<Row> = vec![];
+        let mut unreachable: Vec<usize> = vec![];
+        for (index, case) in cases.iter().enumerate() {
+            let row = vec![case.pattern.clone()];
+            if !self.useful(&rows, &row, std::slice::from_ref(match_type)) {
+                unreachable.push(index)
+            }
+            if case.guard.is_none() {
+                rows.push(row)
+            }
+        }
+        unreachable
+    }
+
+    fn members(&self, t: &Type) -> Vec<Type> {
+        match self.checker.resolve(t) {
+            Type::UnionType{union_types} => union_types.iter().map(|ut| { self.checker.resolve(ut) }).collect(),
+            resolved => vec![resolved]
+        }
+    }
+
+    fn type_covers(&self, case_type: &Type, t: &Type) -> bool {
+        match case_type {
+            Type::UnionType{union_types} => union_types.iter().any(|ut| { self.type_covers(ut, t) }),
+            _ if is_unknown(case_type) => true,
+            _ if is_unknown(t) => false,
+            _ => !has_unknown_types(&type_conforms_no_error(t, case_type, &make_empty_token()))
+        }
+    }
+
+    fn is_wildcard(&self, pattern: &Pattern, t: &Type) -> bool {
+        match pattern {
+            Pattern::Any | Pattern::Bind{..} => true,
+            Pattern::TypePattern{case_type, ..} => self.members(t).iter().all(|m| { self.type_covers(case_type, m) }),
+            Pattern::List{elems, rest: Some(rest)} if elems.is_empty() => match self.checker.resolve(t) {
+                Type::ListType{..} => self.is_wildcard(rest, t),
+                _ => false
+            },
+            _ => false
+        }
+    }
+
+    fn pattern_type(&self, pattern: &Pattern) -> Option<Type> {
+        match pattern {
+            Pattern::Literal{literal} => Some(literal_type(literal)),
+            Pattern::MultiLiteral{literals} => literals.first().map(literal_type),
+            Pattern::Range{start, ..} => Some(literal_type(start)),
+            Pattern::Regex{..} => Some(Type::StringType),
+            Pattern::Variant{ident, ..} => Some(Type::EnumType{ident: ident.clone(), variants: vec![]}),
+            Pattern::Record{ident, ..} => Some(Type::RecordType{ident: ident.clone(), fields: vec![]}),
+            _ => None
+        }
+    }
+
+    fn belongs_to(&self, pattern: &Pattern, member_type: &Type) -> bool {
+        match (pattern, member_type) {
+            (Pattern::Tuple{fields}, Type::TupleType{tuple_types}) => fields.len() == tuple_types.len(),
+            (Pattern::List{..}, Type::ListType{..}) => true,
+            (Pattern::Dict{..}, Type::DictType{..}) => true,
+            _ => match self.pattern_type(pattern) {
+                Some(pattern_type) => !is_unknown(member_type) && self.type_covers(member_type, &pattern_type),
+                _ => false
+            }
+        }
+    }
+
+    fn constructors(&self, t: &Type, heads: &[&Pattern]) -> Option<Vec<(Constructor, Vec<Type>)>> {
+        match self.checker.resolve(t) {
+            Type::BoolType => Some(vec![
+                (Constructor::Bool{value: true}, vec![]),
+                (Constructor::Bool{value: false}, vec![])
+            ]),
+            Type::NullType => Some(vec![(Constructor::Null, vec![])]),
+            Type::EnumType{ident, variants} if !variants.is_empty() => Some(variants.into_iter()
+                .map(|(variant, variant_types)| {
+                    (Constructor::Variant{ident: ident.clone(), variant}, variant_types)
+                })
+                .collect()),
+            Type::UnionType{..} => Some(self.members(t).into_iter()
+                .map(|m| { (Constructor::Member{member_type: m.clone()}, vec![m]) })
+                .collect()),
+            Type::TupleType{tuple_types} => Some(vec![(Constructor::Tuple, tuple_types)]),
+            Type::RecordType{ident, fields} if !fields.is_empty() => Some(vec![(
+                Constructor::Record{ident, fields: fields.iter().map(|f| { f.0.clone() }).collect()},
+                fields.into_iter().map(|f| { f.1 }).collect()
+            )]),
+            Type::ListType{list_type} => {
+                let longest = heads.iter().map(|head| {
+                    match head {
+                        Pattern::List{elems, ..} => elems.len(),
+                        _ => 0
+                    }
+                }).max().unwrap_or(0);
+                let mut lengths: Vec<(Constructor, Vec<Type>)> = (0..longest + 1)
+                    .map(|length| { (Constructor::ListLength{length}, vec![*list_type.clone(); length]) })
+                    .collect();
+                lengths.push((Constructor::ListAtLeast{length: longest + 1}, vec![*list_type; longest + 1]));
+                Some(lengths)
+            },
+            _ => None
+        }
+    }
+
+    fn specialize(&self, row: &Row, constructor: &Constructor, arity: usize, t: &Type) -> Option<Row> {
+        let head = &row[0];
+        let tail = row[1..].to_vec();
+        let pad = |mut fields: Row, tail: Row| -> Option<Row> {
+            fields.resize(arity, Pattern::Any);
+            fields.extend(tail);
+            Some(fields)
+        };
+        if self.is_wildcard(head, t) {
+            return pad(vec![], tail)
+        }
+        match (constructor, head) {
+            (Constructor::Bool{value}, Pattern::Literal{literal: Literal::BoolLit{literal}}) if value == literal => Some(tail),
+            (Constructor::Bool{value}, Pattern::MultiLiteral{literals})
+                if literals.contains(&Literal::BoolLit{literal: *value}) => Some(tail),
+            (Constructor::Null, Pattern::Literal{literal: Literal::NullLit}) => Some(tail),
+            (Constructor::Variant{variant, ..}, Pattern::Variant{variant: pattern_variant, fields, ..})
+                if variant == pattern_variant && fields.len() == arity => pad(fields.clone(), tail),
+            (Constructor::Member{member_type}, _) => {
+                if self.is_wildcard(head, member_type) {
+                    pad(vec![], tail)
+                } else if self.belongs_to(head, member_type) {
+                    pad(vec![head.clone()], tail)
+                } else {
+                    None
+                }
+            },
+            (Constructor::Tuple, Pattern::Tuple{fields}) if fields.len() == arity => pad(fields.clone(), tail),
+            (Constructor::Record{fields: names, ..}, Pattern::Record{fields, ..}) => pad(names.iter().map(|name| {
+                match fields.iter().find(|f| { f.0 == *name }) {
+                    Some((_, field_pattern)) => field_pattern.clone(),
+                    _ => Pattern::Any
+                }
+            }).collect(), tail),
+            (Constructor::ListLength{length}, Pattern::List{elems, rest: None}) if elems.len() == *length =>
+                pad(elems.clone(), tail),
+            (Constructor::ListLength{length}, Pattern::List{elems, rest: Some(rest)})
+            | (Constructor::ListAtLeast{length}, Pattern::List{elems, rest: Some(rest)})
+                if elems.len() <= *length && self.is_wildcard(rest, t) => pad(elems.clone(), tail),
+            _ => None
+        }
+    }
+
+    fn subsumes(&self, pattern: &Pattern, head: &Pattern, t: &Type) -> bool {
+        if self.is_wildcard(pattern, t) || pattern == head {
+            return true
+        }
+        match (pattern, head) {
+            (Pattern::MultiLiteral{literals}, Pattern::Literal{literal}) => literals.contains(literal),
+            (Pattern::Range{start, end, inclusive}, Pattern::Literal{literal}) =>
+                literal_in_range(literal, start, end, *inclusive),
+            (Pattern::Range{start, end, inclusive}, Pattern::Range{start: head_start, end: head_end, inclusive: head_inclusive}) =>
+                literal_in_range(head_start, start, end, *inclusive) &&
+                    (literal_in_range(head_end, start, end, *inclusive) || (!head_inclusive && head_end == end)),
+            _ => false
+        }
+    }
+
+    fn missing(&self, rows: &[Row], types: &[Type]) -> Option<Vec<String>> {
+        if types.is_empty() {
+            return if rows.is_empty() { Some(vec![]) } else { None }
+        }
+        let t = &types[0];
+        if is_unknown(&self.checker.resolve(t)) { // nothing to enumerate, assume covered
+            return None
+        }
+        let heads: Vec<&Pattern> = rows.iter().map(|row| { &row[0] }).collect();
+        match self.constructors(t, &heads) {
+            // a column of wildcards goes to the default matrix, which also keeps recursive types finite
+            Some(constructors) if !heads.iter().all(|head| { self.is_wildcard(head, t) }) => {
+                for (constructor, field_types) in constructors.iter() {
+                    let specialized: Vec<Row> = rows.iter()
+                        .filter_map(|row| { self.specialize(row, constructor, field_types.len(), t) })
+                        .collect();
+                    let specialized_types: Vec<Type> = field_types.iter().chain(types[1..].iter()).cloned().collect();
+                    if let Some(witness) = self.missing(&specialized, &specialized_types) {
+                        let (fields, rest) = witness.split_at(field_types.len());
+                        let mut missing_case = vec![render(constructor, fields)];
+                        missing_case.extend(rest.iter().cloned());
+                        return Some(missing_case)
+                    }
+                }
+                None
+            },
+            _ => {
+                let default: Vec<Row> = rows.iter()
+                    .filter(|row| { self.is_wildcard(&row[0], t) })
+                    .map(|row| { row[1..].to_vec() })
+                    .collect();
+                self.missing(&default, &types[1..]).map(|witness| {
+                    let mut missing_case = vec!["_".to_string()];
+                    missing_case.extend(witness);
+                    missing_case
+                })
+            }
+        }
+    }
+
+    fn useful(&self, rows: &[Row], row: &Row, types: &[Type]) -> bool {
+        if types.is_empty() {
+            return rows.is_empty()
+        }
+        let t = &types[0];
+        let head = &row[0];
+        let mut heads: Vec<&Pattern> = rows.iter().map(|r| { &r[0] }).collect();
+        heads.push(head);
+        match self.constructors(t, &heads) {
+            Some(constructors) if !heads.iter().all(|h| { self.is_wildcard(h, t) }) => constructors.iter().any(|(constructor, field_types)| {
+                match self.specialize(row, constructor, field_types.len(), t) {
+                    Some(specialized_row) => {
+                        let specialized: Vec<Row> = rows.iter()
+                            .filter_map(|r| { self.specialize(r, constructor, field_types.len(), t) })
+                            .collect();
+                        let specialized_types: Vec<Type> = field_types.iter().chain(types[1..].iter()).cloned().collect();
+                        self.useful(&specialized, &specialized_row, &specialized_types)
+                    },
+                    _ => false
+                }
+            }),
+            _ => match head {
+                Pattern::MultiLiteral{literals} => literals.iter().any(|literal| {
+                    let mut literal_row = vec![Pattern::Literal{literal: literal.clone()}];
+                    literal_row.extend(row[1..].iter().cloned());
+                    self.useful(rows, &literal_row, types)
+                }),
+                _ => {
+                    let wildcard = self.is_wildcard(head, t);
+                    let default: Vec<Row> = rows.iter()
+                        .filter(|r| {
+                            if wildcard { self.is_wildcard(&r[0], t) } else { self.subsumes(&r[0], head, t) }
+                        })
+                        .map(|r| { r[1..].to_vec() })
+                        .collect();
+                    self.useful(&default, &row[1..].to_vec(), &types[1..])
+                }
+            }
+        }
+    }
+}
@@ -63,8 +63,14 @@ pub enum Delimiter {
     CaseExp,
     #[strum(serialize = "|>")]
     Bird,
+    #[strum(serialize = ">>")]
+    Compose,
     #[strum(serialize = "$")]
-    SchemaStart
+    SchemaStart,
+    #[strum(serialize = "::")]
+    PathSep,
+    #[strum(serialize = "~")]
+    Regex
 }
 
 impl Delimiter {
@@ -82,6 +88,7 @@ impl Delimiter {
             Delimiter::Equal => Some(Operator::Equal),
             Delimiter::NotEqual => Some(Operator::NotEqual),
             Delimiter::ListConcat => Some(Operator::CollectionConcat),
+            Delimiter::Compose => Some(Operator::Compose),
             _ => None
         }
     }
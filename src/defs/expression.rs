@@ -1,8 +1,11 @@
+use std::ops::Deref;
+use std::sync::{Arc, OnceLock};
 use strum_macros::Display;
 
 use crate::scanner::token::Token;
 use crate::defs::retl_type::Type;
 use crate::defs::operator::Operator;
+use crate::interpreter::vm::Chunk;
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Exp {
@@ -11,6 +14,36 @@ pub struct Exp {
     pub token: Token
 }
 
+/// Body of a lambda, shared by every function value made from it and dropped with the last of them along
+/// with its bytecode
+#[derive(Debug)]
+pub struct Body {
+    pub exp: Exp,
+    pub code: OnceLock<Option<Chunk>> // compiled on the first VM call, None when the VM does not cover it
+}
+
+impl Body {
+    pub fn new(exp: Exp) -> Body {
+        Body{exp, code: OnceLock::new()}
+    }
+}
+
+impl Deref for Body {
+    type Target = Exp;
+
+    fn deref(&self) -> &Exp {
+        &self.exp
+    }
+}
+
+impl PartialEq for Body {
+    fn eq(&self, other: &Body) -> bool {
+        self.exp == other.exp
+    }
+}
+
+impl Eq for Body {}
+
 #[derive(Display, Debug, Clone, Hash, Eq, PartialEq)]
 pub enum Literal {
     IntLit{literal: i32},
@@ -29,35 +62,88 @@ pub struct Parameter {
 
 #[derive(Display, Debug, Clone, Eq, PartialEq)]
 pub enum Pattern {
-    TypePattern{ident: String, case_type: Type, predicate: Option<Exp>},
+    TypePattern{ident: String, case_type: Type},
     Literal{literal: Literal},
     MultiLiteral{literals: Vec<Literal>},
-    Range{range: Expression},
+    Range{start: Literal, end: Literal, inclusive: bool},
+    Regex{regex: String},
+    Variant{ident: String, variant: String, fields: Vec<Pattern>},
+    Record{ident: String, fields: Vec<(String, Pattern)>},
+    Tuple{fields: Vec<Pattern>},
+    List{elems: Vec<Pattern>, rest: Option<Box<Pattern>>},
+    Dict{entries: Vec<(Literal, Pattern)>}, // keys that must be present, other keys are ignored
+    Bind{ident: String},
     Any
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Case {
     pub pattern: Pattern,
-    pub case_exp: Exp
+    pub guard: Option<Exp>,
+    pub case_exp: Exp,
+    pub token: Token
+}
+
+/// One `for` or `if` clause of a comprehension, applied left to right
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Clause {
+    For{index: Option<String>, pattern: Pattern, iter: Exp},
+    If{condition: Exp}
+}
+
+/// A rule of a schema's data contract, checked against every row of a table
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Constraint {
+    NotNull{column: String},
+    Unique{column: String},
+    Min{column: String, min: i32},
+    Max{column: String, max: i32},
+    Allowed{column: String, values: Vec<Literal>},
+    Regex{column: String, regex: String},
+    Check{condition: Box<Exp>} // cross-column, the columns of the row are bound by name
 }
 
 #[derive(Display, Debug, Clone, Eq, PartialEq)]
 pub enum Expression {
     Lit{lit: Literal},
     Let{ident: String, let_type: Type, let_exp: Box<Exp>, after_let_exp: Box<Option<Exp>>},
+    LetPattern{pattern: Box<Pattern>, let_type: Type, let_exp: Box<Exp>, after_let_exp: Box<Option<Exp>>},
+    Var{ident: String, var_type: Type, var_exp: Box<Exp>, after_var_exp: Box<Option<Exp>>},
+    Assign{ident: String, value: Box<Exp>},
+    Import{
+        path: String,
+        module: Box<Exp>,
+        items: Option<Vec<String>>,
+        namespace: Option<String>,
+        after_import_exp: Box<Option<Exp>>
+    },
+    FnDef{defs: Vec<(String, Exp)>, after_fn_exp: Box<Option<Exp>>},
     Alias{ident: String, alias: Type, after_alias_exp: Box<Option<Exp>>},
-    Lambda{params: Vec<Parameter>, return_type: Type, body: Box<Exp>},
+    Lambda{params: Vec<Parameter>, return_type: Type, body: Arc<Body>}, // shared by every function value made from it
     Application{ident: Box<Exp>, args: Vec<Exp>},
     Match{match_exp: Box<Exp>, cases: Vec<Case>},
+    Try{try_exp: Box<Exp>, catch_ident: String, catch_exp: Box<Exp>},
     Primitive{operator: Operator, left: Box<Exp>, right: Box<Exp>},
     Reference{ident: String},
+    Interpolation{parts: Vec<Exp>},
+    Placeholder,
     Branch{condition: Box<Exp>, if_branch: Box<Exp>, else_branch: Box<Option<Exp>>},
     Iter{iter: Box<Exp>, iter_exp: Box<Exp>},
+    While{condition: Box<Exp>, body: Box<Exp>},
+    For{index: Option<String>, pattern: Box<Pattern>, iter: Box<Exp>, body: Box<Exp>},
+    Break,
+    Continue,
+    Return{value: Box<Option<Exp>>},
     ListDef{values: Vec<Exp>},
+    Comprehension{key: Box<Option<Exp>>, value: Box<Exp>, clauses: Vec<Clause>},
+    Slice{start: Box<Option<Exp>>, end: Box<Option<Exp>>, step: Box<Option<Exp>>},
+    RangeDef{start: Box<Exp>, end: Box<Exp>, step: Box<Option<Exp>>, inclusive: bool},
     TupleDef{values: Vec<Exp>},
     TupleAccess{ident: Box<Exp>, index: usize},
+    FieldAccess{ident: Box<Exp>, field: String},
+    RecordDef{ident: String, fields: Vec<(String, Exp)>},
+    EnumDef{ident: String, variant: String, values: Vec<Exp>},
     DictDef{mapping: Vec<(Literal, Exp)>},
-    SchemaDef{mapping: Vec<(String, Type)>},
+    SchemaDef{mapping: Vec<(String, Type)>, constraints: Vec<Constraint>},
     Empty
 }
\ No newline at end of file
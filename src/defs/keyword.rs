@@ -5,8 +5,22 @@ use crate::defs::operator::Operator;
 pub enum Keyword {
     #[strum(serialize = "let")]
     Let,
+    #[strum(serialize = "var")]
+    Var,
+    #[strum(serialize = "fn")]
+    Fn,
+    #[strum(serialize = "import")]
+    Import,
+    #[strum(serialize = "from")]
+    From,
+    #[strum(serialize = "as")]
+    As,
     #[strum(serialize = "alias")]
     Alias,
+    #[strum(serialize = "record")]
+    Record,
+    #[strum(serialize = "enum")]
+    Enum,
     #[strum(serialize = "int")]
     Int,
     #[strum(serialize = "bool")]
@@ -21,6 +35,8 @@ pub enum Keyword {
     Union,
     #[strum(serialize = "list")]
     List,
+    #[strum(serialize = "seq")]
+    Seq,
     #[strum(serialize = "dict")]
     Dict,
     #[strum(serialize = "tuple")]
@@ -45,8 +61,26 @@ pub enum Keyword {
     Else,
     #[strum(serialize = "iter")]
     Iter,
+    #[strum(serialize = "while")]
+    While,
+    #[strum(serialize = "for")]
+    For,
+    #[strum(serialize = "in")]
+    In,
+    #[strum(serialize = "break")]
+    Break,
+    #[strum(serialize = "continue")]
+    Continue,
+    #[strum(serialize = "return")]
+    Return,
+    #[strum(serialize = "step")]
+    Step,
     #[strum(serialize = "match")]
     Match,
+    #[strum(serialize = "try")]
+    Try,
+    #[strum(serialize = "catch")]
+    Catch,
     #[strum(serialize = "case")]
     Case,
     #[strum(serialize = "readln")]
@@ -63,6 +97,14 @@ pub enum Keyword {
     Map,
     #[strum(serialize = "filter")]
     Filter,
+    #[strum(serialize = "pmap")]
+    Pmap,
+    #[strum(serialize = "pfilter")]
+    Pfilter,
+    #[strum(serialize = "take")]
+    Take,
+    #[strum(serialize = "toList")]
+    ToList,
     #[strum(serialize = "zip")]
     Zip,
     #[strum(serialize = "foldl")]
@@ -80,7 +122,17 @@ pub enum Keyword {
     #[strum(serialize = "intToString")]
     IntToString,
     #[strum(serialize = "stringToInt")]
-    StringToInt
+    StringToInt,
+    #[strum(serialize = "assert")]
+    Assert,
+    #[strum(serialize = "validate")]
+    Validate,
+    #[strum(serialize = "param")]
+    Param,
+    #[strum(serialize = "requireParam")]
+    RequireParam,
+    #[strum(serialize = "args")]
+    Args
 }
 
 impl Keyword {
@@ -100,8 +152,13 @@ impl Keyword {
             Keyword::WriteCSV |
             Keyword::Print |
             Keyword::Println |
+            Keyword::Seq |
             Keyword::Map |
             Keyword::Filter |
+            Keyword::Pmap |
+            Keyword::Pfilter |
+            Keyword::Take |
+            Keyword::ToList |
             Keyword::Zip |
             Keyword::Foldl |
             Keyword::Foldr |
@@ -110,7 +167,12 @@ impl Keyword {
             Keyword::Len |
             Keyword::Type |
             Keyword::IntToString |
-            Keyword::StringToInt
+            Keyword::StringToInt |
+            Keyword::Assert |
+            Keyword::Validate |
+            Keyword::Param |
+            Keyword::RequireParam |
+            Keyword::Args
             => true,
             _ => false
         }
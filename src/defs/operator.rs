@@ -1,7 +1,27 @@
+use std::collections::HashMap;
+use std::sync::Arc;
 use strum_macros::Display;
 use crate::{Exp, Type, Value};
-use crate::interpreter::interpreter::error;
+use crate::interpreter::interpreter::{raise, composed_value};
+use crate::interpreter::retl_error::{RetlError, ErrorKind};
 use crate::interpreter::value::Val;
+use crate::defs::retl_type::{type_conforms_no_error, unify, substitute};
+use crate::scanner::token::make_empty_token;
+
+fn union_result_type(member_results: Vec<Option<Type>>) -> Option<Type> {
+    let mut result_types: Vec<Type> = vec![];
+    for member_result in member_results {
+        match member_result {
+            Some(t) => if !result_types.contains(&t) { result_types.push(t) },
+            None => return None
+        }
+    }
+    if result_types.len() == 1 {
+        result_types.pop()
+    } else {
+        Some(Type::UnionType{union_types: result_types})
+    }
+}
 
 #[derive(Display, Debug, Eq, PartialEq, Clone)]
 pub enum Operator {
@@ -34,7 +54,17 @@ pub enum Operator {
     #[strum(serialize = "or")] // bool
     Or,
     #[strum(serialize = "++")] // list, tuple, dict
-    CollectionConcat
+    CollectionConcat,
+    #[strum(serialize = ">>")] // func
+    Compose
+}
+
+/// Int value of checked arithmetic, None means the result does not fit in an int
+fn int_result(value: Option<i32>, exp: &Exp) -> Result<Value, RetlError> {
+    match value {
+        Some(value) => Ok(Value{value: Val::IntValue{value}, val_type: Type::IntType}),
+        _ => raise(ErrorKind::Arithmetic, "Integer overflow", exp)
+    }
 }
 
 impl Operator {
@@ -70,9 +100,16 @@ impl Operator {
         }
     }
 
+    pub fn is_function_op(&self) -> bool {
+        match *self {
+            Operator::Compose => true,
+            _ => false
+        }
+    }
+
     pub fn get_precedence(&self) -> i32 {
         match *self {
-            Operator::And | Operator::Or => 0,
+            Operator::And | Operator::Or | Operator::Compose => 0,
             Operator::Plus | Operator::Minus | Operator::CollectionConcat => 2,
             Operator::Multiply | Operator::Divide | Operator::Modulus => 3,
             _ => 1
@@ -80,77 +117,149 @@ impl Operator {
     }
 
     pub fn is_binary_op(&self, min: i32) -> bool {
-        (self.is_boolean_op() || self.is_arithmetic_op() || self.is_collection_op()) &&
+        (self.is_boolean_op() || self.is_arithmetic_op() || self.is_collection_op() || self.is_function_op()) &&
             self.get_precedence() >= min
     }
 
-    pub fn interpret(&self, left: &Value, right: &Value, exp: &Exp) -> Value {
-        match *self {
+    pub fn result_type(&self, left: &Type, right: &Type) -> Option<Type> {
+        match (left, right) {
+            (Type::UnionType{union_types}, _) => union_result_type(union_types.iter()
+                .map(|lt| { self.result_type(lt, right) })
+                .collect()),
+            (_, Type::UnionType{union_types}) => union_result_type(union_types.iter()
+                .map(|rt| { self.result_type(left, rt) })
+                .collect()),
+            (Type::Any, _) | (_, Type::Any) | (Type::UnknownType, _) | (_, Type::UnknownType) |
+            (Type::GenericType{..}, _) | (_, Type::GenericType{..}) =>
+                Some(Type::UnknownType),
+            _ => match *self {
+                Operator::Plus => match (left, right) {
+                    (Type::IntType, Type::IntType) => Some(Type::IntType),
+                    (Type::CharType | Type::StringType, Type::CharType | Type::StringType) => Some(Type::StringType),
+                    _ => None
+                },
+                Operator::Minus |
+                Operator::Multiply |
+                Operator::Divide |
+                Operator::Modulus => match (left, right) {
+                    (Type::IntType, Type::IntType) => Some(Type::IntType),
+                    _ => None
+                },
+                Operator::GreaterThan |
+                Operator::LessThan |
+                Operator::GreaterThanEqualTo |
+                Operator::LessThanEqualTo => match (left, right) {
+                    (Type::IntType, Type::IntType) => Some(Type::BoolType),
+                    _ => None
+                },
+                Operator::Equal |
+                Operator::NotEqual => match type_conforms_no_error(left, right, &make_empty_token()) {
+                    Type::UnknownType => None,
+                    _ => Some(Type::BoolType)
+                },
+                Operator::Not |
+                Operator::And |
+                Operator::Or => match (left, right) {
+                    (Type::BoolType, Type::BoolType) => Some(Type::BoolType),
+                    _ => None
+                },
+                Operator::CollectionConcat => match (left, right) {
+                    (Type::ListType{..}, Type::ListType{..}) => {
+                        match type_conforms_no_error(left, right, &make_empty_token()) {
+                            Type::UnknownType => None,
+                            t => Some(t)
+                        }
+                    },
+                    _ => None
+                },
+                Operator::Compose => match (left, right) {
+                    (Type::FuncType{param_types, return_type: first_return},
+                     Type::FuncType{param_types: second_params, return_type}) if second_params.len() == 1 => {
+                        let mut type_bindings: HashMap<String, Type> = HashMap::new();
+                        if unify(&second_params[0], first_return, &mut type_bindings, &make_empty_token()) {
+                            Some(Type::FuncType{
+                                param_types: param_types.clone(),
+                                return_type: Box::new(substitute(return_type, &type_bindings))
+                            })
+                        } else {
+                            None
+                        }
+                    },
+                    _ => None
+                }
+            }
+        }
+    }
+
+    pub fn interpret(&self, left: &Value, right: &Value, exp: &Exp) -> Result<Value, RetlError> {
+        Ok(match *self {
             Operator::Plus => match (left.value.clone(), right.value.clone()) {
                 (Val::IntValue{value: v1}, Val::IntValue{value: v2}) => {
-                    Value{value: Val::IntValue{value: v1 + v2}, val_type: Type::IntType}
+                    int_result(v1.checked_add(v2), exp)?
                 },
                 (Val::CharValue{value: v1}, Val::CharValue{value: v2}) => {
-                    Value{value: Val::StringValue{value: v1 + &*v2 }, val_type: Type::StringType}
+                    Value{value: Val::StringValue{value: (v1.to_string() + &v2).into()}, val_type: Type::StringType}
                 },
                 (Val::StringValue{value: v1}, Val::StringValue{value: v2}) => {
-                    Value{value: Val::StringValue{value: v1 + &*v2 }, val_type: Type::StringType}
+                    Value{value: Val::StringValue{value: (v1.to_string() + &v2).into()}, val_type: Type::StringType}
                 },
                 (Val::CharValue{value: v1}, Val::StringValue{value: v2}) => {
-                    Value{value: Val::StringValue{value: v1 + &*v2 }, val_type: Type::StringType}
+                    Value{value: Val::StringValue{value: (v1.to_string() + &v2).into()}, val_type: Type::StringType}
                 },
                 (Val::StringValue{value: v1}, Val::CharValue{value: v2}) => {
-                    Value{value: Val::StringValue{value: v1 + &*v2 }, val_type: Type::StringType}
+                    Value{value: Val::StringValue{value: (v1.to_string() + &v2).into()}, val_type: Type::StringType}
                 },
-                _ => error("Invalid types for operand \'+\'", exp)
+                _ => return raise(ErrorKind::Type, "Invalid types for operand \'+\'", exp)
             },
             Operator::Minus => match (left.value.clone(), right.value.clone()) {
                 (Val::IntValue{value: v1}, Val::IntValue{value: v2}) => {
-                    Value{value: Val::IntValue{value: v1 - v2}, val_type: Type::IntType}
+                    int_result(v1.checked_sub(v2), exp)?
                 },
-                _ => error("Invalid types for operand \'-\'", exp)
+                _ => return raise(ErrorKind::Type, "Invalid types for operand \'-\'", exp)
             },
             Operator::Multiply => match (left.value.clone(), right.value.clone()) {
                 (Val::IntValue{value: v1}, Val::IntValue{value: v2}) => {
-                    Value{value: Val::IntValue{value: v1 * v2}, val_type: Type::IntType}
+                    int_result(v1.checked_mul(v2), exp)?
                 },
-                _ => error("Invalid types for operand \'*\'", exp)
+                _ => return raise(ErrorKind::Type, "Invalid types for operand \'*\'", exp)
             },
             Operator::Divide => match (left.value.clone(), right.value.clone()) {
+                (Val::IntValue{..}, Val::IntValue{value: 0}) => return raise(ErrorKind::Arithmetic, "Division by zero", exp),
                 (Val::IntValue{value: v1}, Val::IntValue{value: v2}) => {
-                    Value{value: Val::IntValue{value: v1 / v2}, val_type: Type::IntType}
+                    int_result(v1.checked_div(v2), exp)?
                 },
-                _ => error("Invalid types for operand \'/\'", exp)
+                _ => return raise(ErrorKind::Type, "Invalid types for operand \'/\'", exp)
             },
             Operator::Modulus => match (left.value.clone(), right.value.clone()) {
+                (Val::IntValue{..}, Val::IntValue{value: 0}) => return raise(ErrorKind::Arithmetic, "Modulus by zero", exp),
                 (Val::IntValue{value: v1}, Val::IntValue{value: v2}) => {
-                    Value{value: Val::IntValue{value: v1 % v2}, val_type: Type::IntType}
+                    int_result(v1.checked_rem(v2), exp)?
                 },
-                _ => error("Invalid types for operand \'%\'", exp)
+                _ => return raise(ErrorKind::Type, "Invalid types for operand \'%\'", exp)
             },
             Operator::GreaterThan => match (left.value.clone(), right.value.clone()) {
                 (Val::IntValue{value: v1}, Val::IntValue{value: v2}) => {
                     Value{value: Val::BoolValue{value: v1 > v2}, val_type: Type::BoolType}
                 },
-                _ => error("Invalid types for operand \'>\'", exp)
+                _ => return raise(ErrorKind::Type, "Invalid types for operand \'>\'", exp)
             },
             Operator::LessThan => match (left.value.clone(), right.value.clone()) {
                 (Val::IntValue{value: v1}, Val::IntValue{value: v2}) => {
                     Value{value: Val::BoolValue{value: v1 < v2}, val_type: Type::BoolType}
                 },
-                _ => error("Invalid types for operand \'<\'", exp)
+                _ => return raise(ErrorKind::Type, "Invalid types for operand \'<\'", exp)
             },
             Operator::GreaterThanEqualTo => match (left.value.clone(), right.value.clone()) {
                 (Val::IntValue{value: v1}, Val::IntValue{value: v2}) => {
                     Value{value: Val::BoolValue{value: v1 >= v2}, val_type: Type::BoolType}
                 },
-                _ => error("Invalid types for operand \'>=\'", exp)
+                _ => return raise(ErrorKind::Type, "Invalid types for operand \'>=\'", exp)
             },
             Operator::LessThanEqualTo => match (left.value.clone(), right.value.clone()) {
                 (Val::IntValue{value: v1}, Val::IntValue{value: v2}) => {
                     Value{value: Val::BoolValue{value: v1 <= v2}, val_type: Type::BoolType}
                 },
-                _ => error("Invalid types for operand \'<=\'", exp)
+                _ => return raise(ErrorKind::Type, "Invalid types for operand \'<=\'", exp)
             },
             Operator::Equal => match (left.value.clone(), right.value.clone()) {
                 (Val::IntValue{value: v1}, Val::IntValue{value: v2}) => {
@@ -168,9 +277,9 @@ impl Operator {
                 (Val::ListValue{values: v1}, Val::ListValue{values: v2}) => {
                     if v1.len() == v2.len() {
                         Value{
-                            value: Val::BoolValue{value: v1.iter().zip(v2.clone()).all(|(l1, l2)| {
-                                match self.interpret(l1, &l2, exp).value {
-                                    Val::BoolValue{value} => value,
+                            value: Val::BoolValue{value: v1.iter().zip(v2.iter()).all(|(l1, l2)| {
+                                match self.interpret(l1, l2, exp).map(|v| { v.value }) {
+                                    Ok(Val::BoolValue{value}) => value,
                                     _ => false
                                 }
                             })},
@@ -181,9 +290,9 @@ impl Operator {
                 (Val::TupleValue{values: v1}, Val::TupleValue{values: v2}) => {
                     if v1.len() == v2.len() {
                         Value{
-                            value: Val::BoolValue{value: v1.iter().zip(v2.clone()).all(|(t1, t2)| {
-                                match self.interpret(t1, &t2, exp).value {
-                                    Val::BoolValue{value} => value,
+                            value: Val::BoolValue{value: v1.iter().zip(v2.iter()).all(|(t1, t2)| {
+                                match self.interpret(t1, t2, exp).map(|v| { v.value }) {
+                                    Ok(Val::BoolValue{value}) => value,
                                     _ => false
                                 }
                             })},
@@ -191,7 +300,11 @@ impl Operator {
                         }
                     } else { Value{value: Val::BoolValue{value: false}, val_type: Type::BoolType} }
                 },
-                _ => error("Invalid types for operand \'==\'", exp)
+                (v1@Val::RecordValue{..}, v2@Val::RecordValue{..}) |
+                (v1@Val::EnumValue{..}, v2@Val::EnumValue{..}) => {
+                    Value{value: Val::BoolValue{value: v1 == v2}, val_type: Type::BoolType}
+                },
+                _ => return raise(ErrorKind::Type, "Invalid types for operand \'==\'", exp)
             },
             Operator::NotEqual => match (left.value.clone(), right.value.clone()) {
                 (Val::IntValue{value: v1}, Val::IntValue{value: v2}) => {
@@ -208,9 +321,9 @@ impl Operator {
                 },
                 (Val::ListValue{values: v1}, Val::ListValue{values: v2}) => {
                     Value{
-                        value: Val::BoolValue{value: !(v1.iter().zip(v2.clone()).all(|(l1, l2)| {
-                            match self.interpret(l1, &l2, exp).value {
-                                Val::BoolValue{value} => value,
+                        value: Val::BoolValue{value: !(v1.iter().zip(v2.iter()).all(|(l1, l2)| {
+                            match self.interpret(l1, l2, exp).map(|v| { v.value }) {
+                                Ok(Val::BoolValue{value}) => value,
                                 _ => false
                             }
                         }))},
@@ -219,46 +332,54 @@ impl Operator {
                 },
                 (Val::TupleValue{values: v1}, Val::TupleValue{values: v2}) => {
                     Value{
-                        value: Val::BoolValue{value: !(v1.iter().zip(v2.clone()).all(|(t1, t2)| {
-                            match self.interpret(t1, &t2, exp).value {
-                                Val::BoolValue{value} => value,
+                        value: Val::BoolValue{value: !(v1.iter().zip(v2.iter()).all(|(t1, t2)| {
+                            match self.interpret(t1, t2, exp).map(|v| { v.value }) {
+                                Ok(Val::BoolValue{value}) => value,
                                 _ => false
                             }
                         }))},
                         val_type: Type::BoolType
                     }
                 },
-                _ => error("Invalid types for operand \'==\'", exp)
+                (v1@Val::RecordValue{..}, v2@Val::RecordValue{..}) |
+                (v1@Val::EnumValue{..}, v2@Val::EnumValue{..}) => {
+                    Value{value: Val::BoolValue{value: v1 != v2}, val_type: Type::BoolType}
+                },
+                _ => return raise(ErrorKind::Type, "Invalid types for operand \'==\'", exp)
             },
             Operator::And => match (left.value.clone(), right.value.clone()) {
                 (Val::BoolValue{value: v1}, Val::BoolValue{value: v2}) => {
                     Value{value: Val::BoolValue{value: v1 && v2}, val_type: Type::BoolType}
                 },
-                _ => error("Invalid types for operand \'and\'", exp)
+                _ => return raise(ErrorKind::Type, "Invalid types for operand \'and\'", exp)
             },
             Operator::Or => match (left.value.clone(), right.value.clone()) {
                 (Val::BoolValue{value: v1}, Val::BoolValue{value: v2}) => {
                     Value{value: Val::BoolValue{value: v1 || v2}, val_type: Type::BoolType}
                 },
-                _ => error("Invalid types for operand \'or\'", exp)
+                _ => return raise(ErrorKind::Type, "Invalid types for operand \'or\'", exp)
             },
             Operator::Not => match (left.value.clone(), right.value.clone()) {
                 (Val::BoolValue{value: v1}, Val::BoolValue{value: v2}) => {
                     Value{value: Val::BoolValue{value: v1 == v2}, val_type: Type::BoolType}
                 },
-                _ => error("Invalid types for operand \'not\'", exp)
+                _ => return raise(ErrorKind::Type, "Invalid types for operand \'not\'", exp)
             },
             Operator::CollectionConcat => match (left.value.clone(), right.value.clone()) {
                 (Val::ListValue{values: v1}, Val::ListValue{values: v2}) => {
-                    let mut concat_list = v1.clone();
-                    concat_list.append(&mut v2.clone());
+                    let mut concat_list = v1;
+                    Arc::make_mut(&mut concat_list).extend(v2.iter().cloned());
                     Value{
                         value: Val::ListValue{values: concat_list},
                         val_type: left.val_type.clone()
                     }
                 },
-                _ => error("Invalid types for operand \'++\'", exp)
+                _ => return raise(ErrorKind::Type, "Invalid types for operand \'++\'", exp)
+            },
+            Operator::Compose => match (&left.value, &right.value) {
+                (Val::FuncValue{..}, Val::FuncValue{..}) => composed_value(left, right, exp)?,
+                _ => return raise(ErrorKind::Type, "Invalid types for operand \'>>\'", exp)
             }
-        }
+        })
     }
 }
\ No newline at end of file
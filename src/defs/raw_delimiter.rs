@@ -48,4 +48,6 @@ pub enum RawDelimiter {
     At,
     #[strum(serialize = "$")]
     DollarSign,
+    #[strum(serialize = "~")]
+    Tilde,
 }
\ No newline at end of file
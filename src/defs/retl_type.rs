@@ -1,6 +1,7 @@
-use log::{error, trace};
+use std::collections::HashMap;
+use log::trace;
 use strum_macros::Display;
-use crate::scanner::token::{Token, get_fp_from_token};
+use crate::scanner::token::Token;
 
 #[derive(Display, Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Type {
@@ -11,11 +12,15 @@ pub enum Type {
     NullType,
     UnionType{union_types: Vec<Type>},
     ListType{list_type: Box<Type>},
+    SeqType{seq_type: Box<Type>},
     TupleType{tuple_types: Vec<Type>},
     DictType{key_type: Box<Type>, value_type: Box<Type>},
     SchemaType{col_types: Vec<Type>},
     TableType{schema: Box<Type>},
     FuncType{param_types: Vec<Type>, return_type: Box<Type>},
+    RecordType{ident: String, fields: Vec<(String, Type)>},
+    EnumType{ident: String, variants: Vec<(String, Vec<Type>)>},
+    GenericType{ident: String},
     UnknownType,
     Any
 }
@@ -32,6 +37,9 @@ fn well_formed(t: &Type) -> Type {
         Type::ListType{list_type} => Type::ListType{
             list_type: Box::new(well_formed(&**list_type))
         },
+        Type::SeqType{seq_type} => Type::SeqType{
+            seq_type: Box::new(well_formed(&**seq_type))
+        },
         Type::TupleType{tuple_types} => {
             let mut tts: Vec<Type> = vec![];
             for tt in tuple_types.iter() {
@@ -70,15 +78,24 @@ fn _type_conforms(t1: &Type, t2: &Type, token: &Token) -> Type {
     trace!("t1: {:?}, t2: {:?}, token: {:?}", t1, t2, token);
     match (t1, t2) {
         (_, _) if t1 == t2 => well_formed(t1),
+        (Type::RecordType{ident: i1, fields: f1}, Type::RecordType{ident: i2, ..}) if i1 == i2 => {
+            if f1.is_empty() { t2.clone() } else { t1.clone() }
+        },
+        (Type::EnumType{ident: i1, variants: v1}, Type::EnumType{ident: i2, ..}) if i1 == i2 => {
+            if v1.is_empty() { t2.clone() } else { t1.clone() }
+        },
         (Type::Any, t) => well_formed(t),
         (t, Type::Any) => well_formed(t),
+        (Type::GenericType{..}, t) => well_formed(t),
+        (t, Type::GenericType{..}) => well_formed(t),
         (Type::UnionType{union_types: uts1}, Type::UnionType{union_types: uts2})
-        if !uts1.is_empty() && !uts2.is_empty() && uts1.len() == uts2.len() => {
-            let mut uts: Vec<Type> = vec![];
-            for (ut1, ut2) in uts1.iter().zip(uts2) {
-                uts.push(_type_conforms(ut1, ut2, token))
-            }
-            Type::UnionType{union_types: uts}
+        if !uts1.is_empty() && !uts2.is_empty() => {
+            let all_members_conform = uts1.iter().all(|ut1| {
+                uts2.iter().any(|ut2| {
+                    !has_unknown_types(&_type_conforms(ut1, ut2, token))
+                })
+            });
+            if all_members_conform { well_formed(t2) } else { Type::UnknownType }
         },
         (Type::UnionType{union_types: uts}, _) => {
             match uts.iter().find(|ut| -> bool {
@@ -105,6 +122,9 @@ fn _type_conforms(t1: &Type, t2: &Type, token: &Token) -> Type {
         (Type::ListType{list_type: l1}, Type::ListType{list_type: l2}) => {
             Type::ListType{list_type: Box::new(_type_conforms(&**l1, &**l2, token))}
         },
+        (Type::SeqType{seq_type: s1}, Type::SeqType{seq_type: s2}) => {
+            Type::SeqType{seq_type: Box::new(_type_conforms(&**s1, &**s2, token))}
+        },
         (Type::TupleType{tuple_types: tts1}, Type::TupleType{tuple_types: tts2})
         if !tts1.is_empty() && !tts2.is_empty() && tts1.len() == tts2.len() => {
             let mut tts: Vec<Type> = vec![];
@@ -156,7 +176,7 @@ fn type_is_unknown(t: &Type) -> bool {
     }
 }
 
-fn has_unknown_types(t: &Type) -> bool {
+pub fn has_unknown_types(t: &Type) -> bool {
     match t {
         Type::UnionType{union_types} => {
             match union_types.iter().find(|ut| { type_is_unknown(ut) }) {
@@ -167,6 +187,9 @@ fn has_unknown_types(t: &Type) -> bool {
         Type::ListType{list_type} => {
             type_is_unknown(list_type)
         },
+        Type::SeqType{seq_type} => {
+            type_is_unknown(seq_type)
+        },
         Type::TupleType{tuple_types} => {
             match tuple_types.iter().find(|tt| { type_is_unknown(tt) }) {
                 Some(_) => true,
@@ -187,16 +210,12 @@ fn has_unknown_types(t: &Type) -> bool {
     }
 }
 
-pub fn type_conforms(t1: &Type, t2: &Type, token: &Token) -> Type {
+pub fn type_conforms(t1: &Type, t2: &Type, token: &Token) -> Result<Type, String> {
     let resolved_type = _type_conforms(t1, t2, token);
-    if has_unknown_types(&resolved_type) {
-        error!("Type mismatch, {:?} vs. {:?}: {}",
-            t1.as_string(),
-            t2.as_string(),
-            get_fp_from_token(&token));
-        Type::UnknownType
+    if has_unknown_types(&resolved_type) && !type_is_unknown(t1) && !type_is_unknown(t2) && t1 != t2 {
+        Err(format!("Type mismatch, {:?} vs. {:?}", t1.as_string(), t2.as_string()))
     } else {
-        resolved_type
+        Ok(resolved_type)
     }
 }
 
@@ -204,6 +223,80 @@ pub fn type_conforms_no_error(t1: &Type, t2: &Type, token: &Token) -> Type {
     _type_conforms(t1, t2, token)
 }
 
+pub fn unify(param_type: &Type, arg_type: &Type, bindings: &mut HashMap<String, Type>, token: &Token) -> bool {
+    trace!("unify: {:?}, {:?}, bindings: {:?}", param_type, arg_type, bindings);
+    match (param_type, arg_type) {
+        (Type::GenericType{ident}, _) => {
+            let bound_type = match bindings.get(ident) {
+                Some(bound) => {
+                    let bound_type = _type_conforms(bound, arg_type, token);
+                    let conflicting = type_is_unknown(&bound_type) ||
+                        (has_unknown_types(&bound_type) && !has_unknown_types(bound) && !has_unknown_types(arg_type));
+                    if conflicting && !type_is_unknown(arg_type) {
+                        return false
+                    }
+                    bound_type
+                },
+                _ => arg_type.clone()
+            };
+            bindings.insert(ident.clone(), bound_type);
+            true
+        },
+        (_, Type::GenericType{..}) => true,
+        (Type::Any, _) => true,
+        (Type::ListType{list_type: l1}, Type::ListType{list_type: l2}) => unify(l1, l2, bindings, token),
+        (Type::SeqType{seq_type: s1}, Type::SeqType{seq_type: s2}) => unify(s1, s2, bindings, token),
+        (Type::TupleType{tuple_types: tts1}, Type::TupleType{tuple_types: tts2}) => {
+            tts1.len() == tts2.len() &&
+                tts1.iter().zip(tts2).all(|(tt1, tt2)| { unify(tt1, tt2, bindings, token) })
+        },
+        (Type::DictType{key_type: k1, value_type: v1},
+            Type::DictType{key_type: k2, value_type: v2}) => {
+            unify(k1, k2, bindings, token) && unify(v1, v2, bindings, token)
+        },
+        (Type::FuncType{param_types: pts1, return_type: r1},
+            Type::FuncType{param_types: pts2, return_type: r2}) => {
+            pts1.len() == pts2.len() &&
+                pts1.iter().zip(pts2).all(|(pt1, pt2)| { unify(pt1, pt2, bindings, token) }) &&
+                unify(r1, r2, bindings, token)
+        },
+        _ => !has_unknown_types(&_type_conforms(param_type, arg_type, token))
+    }
+}
+
+pub fn substitute(t: &Type, bindings: &HashMap<String, Type>) -> Type {
+    match t {
+        Type::GenericType{ident} => match bindings.get(ident) {
+            Some(bound) => bound.clone(),
+            _ => t.clone()
+        },
+        Type::UnionType{union_types} => Type::UnionType{
+            union_types: union_types.iter().map(|ut| { substitute(ut, bindings) }).collect()
+        },
+        Type::ListType{list_type} => Type::ListType{list_type: Box::new(substitute(list_type, bindings))},
+        Type::SeqType{seq_type} => Type::SeqType{seq_type: Box::new(substitute(seq_type, bindings))},
+        Type::TupleType{tuple_types} => Type::TupleType{
+            tuple_types: tuple_types.iter().map(|tt| { substitute(tt, bindings) }).collect()
+        },
+        Type::DictType{key_type, value_type} => Type::DictType{
+            key_type: Box::new(substitute(key_type, bindings)),
+            value_type: Box::new(substitute(value_type, bindings))
+        },
+        Type::FuncType{param_types, return_type} => Type::FuncType{
+            param_types: param_types.iter().map(|pt| { substitute(pt, bindings) }).collect(),
+            return_type: Box::new(substitute(return_type, bindings))
+        },
+        _ => t.clone()
+    }
+}
+
+pub fn is_union_type(t: &Type) -> bool {
+    match t {
+        Type::UnionType{..} => true,
+        _ => false
+    }
+}
+
 fn type_list_as_string(ts: &Vec<Type>) -> String {
     let mut type_str = String::from("");
 
@@ -230,6 +323,9 @@ impl Type {
             Type::ListType{list_type } => {
                 "list[".to_owned() + &list_type.clone().as_string() + "]"
             },
+            Type::SeqType{seq_type} => {
+                "seq[".to_owned() + &seq_type.clone().as_string() + "]"
+            },
             Type::TupleType{tuple_types} => {
                 "tuple(".to_owned() + &*type_list_as_string(&tuple_types) + ")"
             },
@@ -246,6 +342,9 @@ impl Type {
                 "lambda[".to_owned() + &*type_list_as_string(param_types) +
                     "->" + &return_type.clone().as_string() + "]"
             },
+            Type::RecordType{ident, ..} => ident.clone(),
+            Type::EnumType{ident, ..} => ident.clone(),
+            Type::GenericType{ident} => ident.clone(),
             Type::UnknownType => String::from("unknown"),
             Type::Any => String::from("any")
         }
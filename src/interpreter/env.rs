@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::iter::FromIterator;
+use std::sync::Arc;
+
+use crate::interpreter::value::{Value, Val};
+
+/// Bindings of a single scope
+type Frame = HashMap<String, Value>;
+
+/// Chain of scopes, innermost first. Frames are reference counted and shared, so cloning or capturing an
+/// environment is O(1) and a frame is only copied when a shared one is written to
+#[derive(Debug, Clone, Default)]
+pub struct Env {
+    frame: Arc<Frame>,
+    parent: Option<Arc<Env>>,
+    has_vars: bool // a var is bound somewhere in the chain, closures then need a snapshot
+}
+
+impl Env {
+    pub fn new() -> Env {
+        Env::default()
+    }
+
+    /// Empty scope nested in this one, its bindings shadow the enclosing ones and disappear with it
+    pub fn child(&self) -> Env {
+        Env{frame: Arc::new(Frame::new()), parent: Some(Arc::new(self.clone())), has_vars: self.has_vars}
+    }
+
+    pub fn get(&self, ident: &str) -> Option<&Value> {
+        let mut scope = self;
+        loop {
+            if let Some(value) = scope.frame.get(ident) {
+                return Some(value)
+            }
+            match &scope.parent {
+                Some(parent) => scope = parent,
+                _ => return None
+            }
+        }
+    }
+
+    /// Binds in the innermost scope
+    pub fn insert(&mut self, ident: String, value: Value) {
+        if let Val::VarValue{..} = value.value {
+            self.has_vars = true
+        }
+        Arc::make_mut(&mut self.frame).insert(ident, value);
+    }
+
+    pub fn extend<I: IntoIterator<Item = (String, Value)>>(&mut self, bindings: I) {
+        for (ident, value) in bindings {
+            self.insert(ident, value)
+        }
+    }
+
+    /// Visible bindings, an inner binding hides an outer one of the same name
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Value)> {
+        let mut visible: HashMap<&String, &Value> = HashMap::new();
+        let mut scope = Some(self);
+        while let Some(env) = scope {
+            for (ident, value) in env.frame.iter() {
+                visible.entry(ident).or_insert(value);
+            }
+            scope = env.parent.as_deref();
+        }
+        visible.into_iter()
+    }
+
+    /// Copy with each var replaced by its current value, closures capture this so later assignments never
+    /// change what a function sees. Frames without vars stay shared
+    pub fn snapshot(&self) -> Env {
+        if !self.has_vars {
+            return self.clone()
+        }
+        let has_frame_vars = self.frame.values().any(|value| { matches!(value.value, Val::VarValue{..}) });
+        Env{
+            frame: if has_frame_vars {
+                Arc::new(self.frame.iter().map(|(ident, value)| { (ident.clone(), value.current()) }).collect())
+            } else {
+                self.frame.clone()
+            },
+            parent: self.parent.as_ref().map(|parent| { Arc::new(parent.snapshot()) }),
+            has_vars: false
+        }
+    }
+}
+
+impl FromIterator<(String, Value)> for Env {
+    fn from_iter<I: IntoIterator<Item = (String, Value)>>(bindings: I) -> Env {
+        let mut env = Env::new();
+        env.extend(bindings);
+        env
+    }
+}
+
+impl PartialEq for Env {
+    fn eq(&self, other: &Env) -> bool {
+        let bindings: HashMap<&String, &Value> = self.iter().collect();
+        bindings == other.iter().collect()
+    }
+}
+
+impl Eq for Env {}
@@ -1,173 +1,536 @@
-use log::{error, trace};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use log::trace;
+use regex::Regex;
 use crate::Builtin;
+use crate::builtin::builtin::seq_param_type;
 
-use crate::defs::expression::{Exp, Expression, Literal, Parameter, Pattern};
-use crate::defs::retl_type::{type_conforms, type_conforms_no_error};
+use crate::defs::keyword::Keyword;
+use crate::defs::expression::{Body, Exp, Expression, Literal, Parameter, Pattern, Clause, Constraint};
+use crate::defs::operator::Operator;
+use crate::defs::retl_type::{type_conforms, type_conforms_no_error, is_union_type, unify, substitute};
 use crate::defs::retl_type::Type;
-use crate::interpreter::value::{Value, Env, Val};
-use crate::scanner::token::get_fp_from_token;
+use crate::interpreter::value::{Value, Val, VarCell, SeqStage, slice_indices};
+use crate::interpreter::env::Env;
+use crate::interpreter::retl_error::{RetlError, ErrorKind};
+use crate::interpreter::vm::{self, Engine};
+use crate::scanner::token::Token;
+
+/// Deepest nesting of function calls, a script recursing further gets a runtime error instead of overflowing
+/// the native stack
+pub const MAX_CALL_DEPTH: usize = 5000;
+
+/// Native stack of the threads scripts run on, room for MAX_CALL_DEPTH calls even in a debug build
+pub const STACK_SIZE: usize = 512 * 1024 * 1024;
 
 #[derive(Clone)]
 pub struct Interpreter {
-    pub error: bool,
-    builtin: Builtin
+    builtin: Builtin,
+    regex_cache: HashMap<String, Regex>,
+    modules: Arc<Mutex<HashMap<String, Env>>>, // exports by module path, shared so each module runs once
+    engine: Engine,
+    depth: usize // calls in progress
 }
 
-pub fn error(message: &str, exp: &Exp) -> Value {
-    error!("{}: {}", message.to_string(), get_fp_from_token(&exp.token));
-    Value{value: Val::NullValue, val_type: Type::NullType}
+pub fn error(message: &str, exp: &Exp) -> Result<Value, RetlError> {
+    raise(ErrorKind::Runtime, message, exp)
+}
+
+pub fn raise(kind: ErrorKind, message: &str, exp: &Exp) -> Result<Value, RetlError> {
+    Err(RetlError{kind, message: message.to_string(), token: Box::new(exp.token.clone())})
+}
+
+pub fn literal_value(lit: &Literal) -> Value {
+    match lit {
+        Literal::IntLit{literal} => Value{value: Val::IntValue{value: *literal}, val_type: Type::IntType},
+        Literal::BoolLit{literal} => Value{value: Val::BoolValue{value: *literal}, val_type: Type::BoolType},
+        Literal::CharLit{literal} => Value{value: Val::CharValue{value: literal.clone()}, val_type: Type::CharType},
+        Literal::StringLit{literal} => Value{value: Val::StringValue{value: literal.as_str().into()}, val_type: Type::StringType},
+        Literal::NullLit => Value{value: Val::NullValue, val_type: Type::NullType}
+    }
+}
+
+pub fn literal_matches(val: &Val, literal: &Literal) -> bool {
+    match (val, literal) {
+        (Val::IntValue{value}, Literal::IntLit{literal}) => value == literal,
+        (Val::BoolValue{value}, Literal::BoolLit{literal}) => value == literal,
+        (Val::CharValue{value}, Literal::CharLit{literal}) => value == literal,
+        (Val::StringValue{value}, Literal::StringLit{literal}) => **value == **literal,
+        (Val::NullValue, Literal::NullLit) => true,
+        _ => false
+    }
+}
+
+pub fn func_value(lambda: &Exp, env: &Env, group: &Arc<Vec<(String, Exp)>>) -> Result<Value, RetlError> {
+    match &lambda.exp {
+        Expression::Lambda{params, return_type, body} => Ok(Value{
+            value: Val::FuncValue{
+                builtin_ident: None,
+                parameters: params.iter()
+                    .map(|p: &Parameter| {(p.ident.clone(), p.param_type.clone())})
+                    .collect(),
+                body: body.clone(),
+                env: env.snapshot(),
+                group: group.clone()
+            },
+            val_type: Type::FuncType{
+                param_types: params.iter().map(|p: &Parameter| {p.param_type.clone()}).collect(),
+                return_type: Box::new(return_type.clone())
+            }
+        }),
+        _ => invalid_exp_error(lambda)
+    }
+}
+
+fn reference(ident: &str, token: &Token) -> Exp {
+    Exp{exp: Expression::Reference{ident: ident.to_string()}, exp_type: Type::UnknownType, token: token.clone()}
+}
+
+/// Function value whose body calls `func` with `args`, the names the body refers to are bound in `env`
+fn call_value(func: &str, args: Vec<Exp>, parameters: Vec<(String, Type)>, return_type: Type, env: Env, token: &Token) -> Value {
+    Value{
+        val_type: Type::FuncType{
+            param_types: parameters.iter().map(|p| { p.1.clone() }).collect(),
+            return_type: Box::new(return_type.clone())
+        },
+        value: Val::FuncValue{
+            builtin_ident: None,
+            parameters,
+            body: Arc::new(Body::new(Exp{
+                exp: Expression::Application{ident: Box::new(reference(func, token)), args},
+                exp_type: return_type,
+                token: token.clone()
+            })),
+            env,
+            group: Arc::new(vec![])
+        }
+    }
 }
 
-pub fn invalid_exp_error(exp: &Exp) -> Value {
-    error!("!!!Invalid expression reached!!!: {}", get_fp_from_token(&exp.token));
+/// `first >> second`, a function taking the parameters of first and passing its result to second
+pub fn composed_value(first: &Value, second: &Value, exp: &Exp) -> Result<Value, RetlError> {
+    match (&first.value, &second.val_type) {
+        (Val::FuncValue{parameters, ..}, Type::FuncType{param_types, return_type}) if param_types.len() == 1 => {
+            let mut env = Env::new();
+            env.insert("compose$first".to_string(), first.clone());
+            env.insert("compose$second".to_string(), second.clone());
+            let first_call = Exp{
+                exp: Expression::Application{
+                    ident: Box::new(reference("compose$first", &exp.token)),
+                    args: parameters.iter().map(|(ident, _)| { reference(ident, &exp.token) }).collect()
+                },
+                exp_type: Type::UnknownType,
+                token: exp.token.clone()
+            };
+            Ok(call_value("compose$second", vec![first_call], parameters.clone(), *return_type.clone(), env, &exp.token))
+        },
+        _ => raise(ErrorKind::Type, "The right side of \'>>\' must take exactly one argument", exp)
+    }
+}
+
+pub fn null_value() -> Value {
     Value{value: Val::NullValue, val_type: Type::NullType}
 }
 
+/// Elements an iter or for loop visits, in order
+fn iter_elements<'a>(iter_value: &'a Value, exp: &Exp) -> Result<Box<dyn Iterator<Item = Value> + 'a>, RetlError> {
+    match &iter_value.value {
+        Val::IntValue{value} => Ok(Box::new((0..(*value).max(0)).map(|index| {
+            Value{value: Val::IntValue{value: index}, val_type: Type::IntType}
+        }))),
+        Val::StringValue{value} => Ok(Box::new(value.chars().map(|c| {
+            Value{value: Val::CharValue{value: c.to_string()}, val_type: Type::CharType}
+        }))),
+        Val::ListValue{values} | Val::TupleValue{values} => Ok(Box::new(values.iter().cloned())),
+        Val::RangeValue{..} => Ok(Box::new((0..iter_value.value.range_len()).map(move |index| {
+            iter_value.value.range_element(index).unwrap_or_else(null_value)
+        }))),
+        _ => Err(RetlError{kind: ErrorKind::Type, message: "Invalid iterator".to_string(), token: Box::new(exp.token.clone())})
+    }
+}
+
+/// Position of an index into a sequence of the given length, negative indices count from the end
+pub fn element_index(index: i32, len: usize) -> Option<usize> {
+    let position = if index < 0 { index as i64 + len as i64 } else { index as i64 };
+    if position >= 0 && position < len as i64 { Some(position as usize) } else { None }
+}
+
+/// Environment a function body runs in, with its fn group bound so the functions can call each other
+pub fn closure_env(env: &Env, group: &Arc<Vec<(String, Exp)>>) -> Result<Env, RetlError> {
+    let mut body_env = env.child();
+    for (ident, lambda) in group.iter() {
+        body_env.insert(ident.clone(), func_value(lambda, env, group)?);
+    }
+    Ok(body_env)
+}
+
+/// Applies a binary operator, union operands are narrowed to the member type they hold first
+pub fn primitive(operator: &Operator, left: Value, right: Value, exp: &Exp) -> Result<Value, RetlError> {
+    let left_value = left.materialize();
+    let right_value = right.materialize();
+    if (is_union_type(&left_value.val_type) || is_union_type(&right_value.val_type)) &&
+        operator.result_type(&left_value.val_type, &right_value.val_type).is_none() {
+        return raise(ErrorKind::Type, &format!("Operator \'{}\' is not valid for every member of {} and {}",
+            operator, left_value.val_type.as_string(), right_value.val_type.as_string()), exp)
+    }
+    operator.interpret(&left_value.narrow(), &right_value.narrow(), exp)
+}
+
+/// Runtime counterpart of type_conforms, a mismatch stops the script
+pub fn conform(t1: &Type, t2: &Type, exp: &Exp) -> Result<Type, RetlError> {
+    type_conforms(t1, t2, &exp.token)
+        .map_err(|message| { RetlError{kind: ErrorKind::Type, message, token: Box::new(exp.token.clone())} })
+}
+
+/// List of evaluated elements conformed to the element type of exp, an empty list takes the expected type
+pub fn list_value(list_values: Vec<Value>, expected_type: &Type, exp: &Exp) -> Result<Value, RetlError> {
+    let list_type = match &exp.exp_type {
+        Type::ListType{list_type} => *list_type.clone(),
+        _ => Type::UnknownType
+    };
+    for list_val in list_values.iter() {
+        conform(&list_val.val_type, &list_type, exp)?;
+    }
+    let expected_list_type = Type::ListType{
+        list_type: Box::new(match list_values.first() {
+            Some(value) => value.val_type.clone(),
+            _ => Type::UnknownType
+        })
+    };
+    let final_list_type = if list_values.is_empty() { // nothing to infer from
+        type_conforms_no_error(&expected_list_type, expected_type, &exp.token)
+    } else {
+        conform(&expected_list_type, expected_type, exp)?
+    };
+    Ok(Value{value: Val::ListValue{values: Arc::new(list_values)}, val_type: final_list_type})
+}
+
+/// Range over two int or two char bounds, the step defaults to 1
+pub fn range_value(start_value: Value, end_value: Value, step_value: Option<Value>, inclusive: bool,
+                   expected_type: &Type, exp: &Exp) -> Result<Value, RetlError> {
+    let start_value = start_value.narrow();
+    let end_value = end_value.narrow();
+    let step = match step_value.map(|step_value| { step_value.narrow().value }) {
+        Some(Val::IntValue{value}) if value != 0 => value,
+        Some(_) => return raise(ErrorKind::Arithmetic, "Range step must be a non-zero int", exp),
+        _ => 1
+    };
+    let char_code = |c: &String| -> Option<i32> { c.chars().next().map(|c| { c as i32 }) };
+    let (start, end, char_range) = match (&start_value.value, &end_value.value) {
+        (Val::IntValue{value: s}, Val::IntValue{value: e}) => (*s, *e, false),
+        (Val::CharValue{value: s}, Val::CharValue{value: e}) => match (char_code(s), char_code(e)) {
+            (Some(s), Some(e)) => (s, e, true),
+            _ => return error("Invalid char range bounds", exp)
+        },
+        _ => return error(&format!("Range bounds must both be int or char, found {} and {}",
+            start_value.val_type.as_string(), end_value.val_type.as_string()), exp)
+    };
+    let range_type = Type::ListType{
+        list_type: Box::new(if char_range { Type::CharType } else { Type::IntType })
+    };
+    Ok(Value{
+        value: Val::RangeValue{start, end, step, inclusive, char_range},
+        val_type: conform(&range_type, expected_type, exp)?
+    })
+}
+
+pub fn invalid_exp_error(exp: &Exp) -> Result<Value, RetlError> {
+    error("!!!Invalid expression reached!!!", exp)
+}
+
 impl Interpreter {
     pub fn init(builtin: &Builtin) -> Interpreter {
         Interpreter{
-            error: false,
-            builtin: builtin.clone()
+            builtin: builtin.clone(),
+            regex_cache: HashMap::new(),
+            modules: Arc::new(Mutex::new(HashMap::new())),
+            engine: Engine::Vm,
+            depth: 0
         }
     }
 
-    pub fn interpret(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Value {
+    pub fn with_engine(mut self, engine: Engine) -> Interpreter {
+        self.engine = engine;
+        self
+    }
+
+    pub fn interpret(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Result<Value, RetlError> {
         trace!("interpret: {:?}", exp);
         match &exp.exp {
             Expression::Lit{..} => self.interpret_literal(&exp, expected_type),
             Expression::Let{..} => self.interpret_let(&exp, env, expected_type),
+            Expression::LetPattern{..} => self.interpret_let_pattern(&exp, env, expected_type),
+            Expression::Var{..} => self.interpret_var(&exp, env, expected_type),
+            Expression::Assign{..} => self.interpret_assign(&exp, env, expected_type),
+            Expression::FnDef{..} => self.interpret_fn_def(&exp, env, expected_type),
+            Expression::Import{..} => self.interpret_import(&exp, env, expected_type),
             Expression::Alias{..} => self.interpret_alias(&exp, env, expected_type),
             Expression::Lambda{..} => self.interpret_lambda(&exp, env, expected_type),
             Expression::Application{..} => self.interpret_application(&exp, env, expected_type),
             Expression::Match{..} => self.interpret_match(&exp, env, expected_type),
+            Expression::Try{..} => self.interpret_try(&exp, env, expected_type),
             Expression::Primitive{..} => self.interpret_primitive(&exp, env, expected_type),
             Expression::Reference{..} => self.interpret_reference(&exp, env, expected_type),
+            Expression::Interpolation{..} => self.interpret_interpolation(&exp, env, expected_type),
+            Expression::Placeholder => error("Placeholder \"_\" is only valid as a function argument", exp),
             Expression::Branch{..} => self.interpret_branch(&exp, env, expected_type),
             Expression::Iter{..} => self.interpret_iter(&exp, env, expected_type),
+            Expression::While{..} => self.interpret_while(&exp, env, expected_type),
+            Expression::For{..} => self.interpret_for(&exp, env, expected_type),
+            Expression::Break => raise(ErrorKind::Break, "\"break\" outside of a loop", exp),
+            Expression::Continue => raise(ErrorKind::Continue, "\"continue\" outside of a loop", exp),
+            Expression::Return{value} => {
+                let return_value = match &**value {
+                    Some(value_exp) => self.interpret(value_exp, env, &Type::UnknownType)?,
+                    _ => null_value()
+                };
+                raise(ErrorKind::Return{value: Box::new(return_value)}, "\"return\" outside of a function", exp)
+            },
             Expression::ListDef{..} => self.interpret_list_def(&exp, env, expected_type),
+            Expression::Comprehension{..} => self.interpret_comprehension(&exp, env, expected_type),
+            Expression::Slice{..} => error("Slices are only valid in brackets", exp),
+            Expression::RangeDef{..} => self.interpret_range_def(&exp, env, expected_type),
             Expression::TupleDef{..} => self.interpret_tuple_def(&exp, env, expected_type),
             Expression::TupleAccess{..} => self.interpret_tuple_access(&exp, env, expected_type),
+            Expression::FieldAccess{..} => self.interpret_field_access(&exp, env, expected_type),
+            Expression::RecordDef{..} => self.interpret_record_def(&exp, env, expected_type),
+            Expression::EnumDef{..} => self.interpret_enum_def(&exp, env, expected_type),
             Expression::DictDef{..} => self.interpret_dict_def(&exp, env, expected_type),
-            Expression::SchemaDef{..} => self.interpret_schema_def(&exp, expected_type),
-            _ => invalid_exp_error(exp)
+            Expression::SchemaDef{..} => self.interpret_schema_def(&exp, env, expected_type),
+            Expression::Empty => Ok(null_value()) // block or script ending in ';'
         }
     }
 
-    fn interpret_literal(&mut self, exp: &Exp, expected_type: &Type) -> Value {
+    fn interpret_literal(&mut self, exp: &Exp, expected_type: &Type) -> Result<Value, RetlError> {
         trace!("interpret_literal: {:?}", exp);
-        type_conforms(&exp.exp_type, expected_type, &exp.token);
+        conform(&exp.exp_type, expected_type, exp)?;
         match &exp.exp {
-            Expression::Lit{lit} => {
-                match lit {
-                    Literal::IntLit{literal} =>
-                        Value{value: Val::IntValue{value: literal.clone()}, val_type: Type::IntType},
-                    Literal::BoolLit{literal} =>
-                        Value{value: Val::BoolValue{value: literal.clone()}, val_type: Type::BoolType},
-                    Literal::CharLit{literal} =>
-                        Value{value: Val::CharValue{value: literal.clone()}, val_type: Type::CharType},
-                    Literal::StringLit{literal} =>
-                        Value{value: Val::StringValue{value: literal.clone()}, val_type: Type::StringType},
-                    Literal::NullLit =>
-                        Value{value: Val::NullValue, val_type: Type::NullType}
-                }
-            },
+            Expression::Lit{lit} => Ok(literal_value(lit)),
             _ => invalid_exp_error(exp)
         }
     }
 
-    fn interpret_let(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Value {
+    fn interpret_let(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Result<Value, RetlError> {
         trace!("interpret_let: {:?}", exp);
         match &exp.exp {
             Expression::Let{ident, let_type, let_exp, after_let_exp} => {
-                let resolved_exp = self.interpret(let_exp, env, let_type);
+                let resolved_exp = self.interpret(let_exp, env, let_type)?.widen(let_type);
                 env.insert(ident.clone(), resolved_exp);
                 match &**after_let_exp {
                     Some(after) => self.interpret(after, env, expected_type),
-                    _ => Value{value: Val::NullValue, val_type: Type::NullType}
+                    _ => Ok(Value{value: Val::NullValue, val_type: Type::NullType})
+                }
+            },
+            _ => invalid_exp_error(exp)
+        }
+    }
+
+    fn interpret_let_pattern(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Result<Value, RetlError> {
+        trace!("interpret_let_pattern: {:?}", exp);
+        match &exp.exp {
+            Expression::LetPattern{pattern, let_type, let_exp, after_let_exp} => {
+                let resolved_exp = self.interpret(let_exp, env, let_type)?.widen(let_type);
+                if !self.match_pattern(pattern, &resolved_exp, env, &exp.token) {
+                    return raise(ErrorKind::Match, &format!("Value of type {} does not match let pattern",
+                        resolved_exp.val_type.as_string()), exp)
+                }
+                match &**after_let_exp {
+                    Some(after) => self.interpret(after, env, expected_type),
+                    _ => Ok(Value{value: Val::NullValue, val_type: Type::NullType})
+                }
+            },
+            _ => invalid_exp_error(exp)
+        }
+    }
+
+    fn interpret_var(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Result<Value, RetlError> {
+        trace!("interpret_var: {:?}", exp);
+        match &exp.exp {
+            Expression::Var{ident, var_type, var_exp, after_var_exp} => {
+                let value = self.interpret(var_exp, env, var_type)?.widen(var_type);
+                let declared_type = match var_type {
+                    Type::UnknownType => value.val_type.clone(),
+                    _ => var_type.clone()
+                };
+                env.insert(ident.clone(), Value{value: Val::VarValue{cell: Arc::new(VarCell::new(value))}, val_type: declared_type});
+                match &**after_var_exp {
+                    Some(after) => self.interpret(after, env, expected_type),
+                    _ => Ok(null_value())
+                }
+            },
+            _ => invalid_exp_error(exp)
+        }
+    }
+
+    fn interpret_assign(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Result<Value, RetlError> {
+        trace!("interpret_assign: {:?}", exp);
+        conform(&Type::NullType, expected_type, exp)?;
+        match &exp.exp {
+            Expression::Assign{ident, value} => {
+                match env.get(ident).cloned() {
+                    Some(Value{value: Val::VarValue{cell}, val_type}) => {
+                        let new_value = self.interpret(value, env, &val_type)?.widen(&val_type);
+                        cell.set(new_value);
+                        Ok(null_value())
+                    },
+                    Some(_) => raise(ErrorKind::Reference, &format!("Cannot assign to \"{}\", it is not a var", ident), exp),
+                    _ => raise(ErrorKind::Reference, &format!("Reference \"{}\" does not exist", ident), exp)
+                }
+            },
+            _ => invalid_exp_error(exp)
+        }
+    }
+
+    fn interpret_import(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Result<Value, RetlError> {
+        trace!("interpret_import: {:?}", exp);
+        match &exp.exp {
+            Expression::Import{path, module, items, namespace, after_import_exp} => {
+                let cached = self.modules.lock().unwrap_or_else(|e| { e.into_inner() }).get(path).cloned();
+                let exports = match cached {
+                    Some(exports) => exports,
+                    _ => {
+                        let builtins = self.builtin.load_builtins(&Env::new());
+                        let mut module_env = builtins.clone();
+                        self.interpret(module, &mut module_env, &Type::UnknownType)?;
+                        let exports: Env = module_env.snapshot().iter()
+                            .filter(|(ident, v)| { !ident.starts_with("dummy$") && builtins.get(ident) != Some(*v) })
+                            .map(|(ident, v)| { (ident.clone(), v.clone()) })
+                            .collect();
+                        self.modules.lock().unwrap_or_else(|e| { e.into_inner() }).insert(path.clone(), exports.clone());
+                        exports
+                    }
+                };
+                match (items, namespace) {
+                    (Some(selected), _) => for ident in selected.iter() {
+                        match exports.get(ident) {
+                            Some(value) => { env.insert(ident.clone(), value.clone()); },
+                            _ => return raise(ErrorKind::Reference, &format!("Module \"{}\" has no export \"{}\"", path, ident), exp)
+                        }
+                    },
+                    (_, Some(ns)) => {
+                        let mut fields: Vec<(String, Value)> = exports.iter()
+                            .map(|(ident, v)| { (ident.clone(), v.clone()) })
+                            .collect();
+                        fields.sort_by(|a, b| { a.0.cmp(&b.0) });
+                        let val_type = Type::RecordType{
+                            ident: ns.clone(),
+                            fields: fields.iter().map(|f| { (f.0.clone(), f.1.val_type.clone()) }).collect()
+                        };
+                        env.insert(ns.clone(), Value{value: Val::RecordValue{ident: ns.clone(), fields}, val_type});
+                    },
+                    _ => env.extend(exports.iter().map(|(ident, v)| { (ident.clone(), v.clone()) }))
+                }
+                match &**after_import_exp {
+                    Some(after) => self.interpret(after, env, expected_type),
+                    _ => Ok(Value{value: Val::NullValue, val_type: Type::NullType})
+                }
+            },
+            _ => invalid_exp_error(exp)
+        }
+    }
+
+    fn interpret_fn_def(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Result<Value, RetlError> {
+        trace!("interpret_fn_def: {:?}", exp);
+        match &exp.exp {
+            Expression::FnDef{defs, after_fn_exp} => {
+                let closure = env.clone();
+                let group = Arc::new(defs.clone());
+                for (ident, lambda) in defs.iter() {
+                    env.insert(ident.clone(), func_value(lambda, &closure, &group)?);
+                }
+                match &**after_fn_exp {
+                    Some(after) => self.interpret(after, env, expected_type),
+                    _ => Ok(Value{value: Val::NullValue, val_type: Type::NullType})
                 }
             },
             _ => invalid_exp_error(exp)
         }
     }
 
-    fn interpret_alias(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Value {
+    fn interpret_alias(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Result<Value, RetlError> {
         trace!("interpret_alias: {:?}", exp);
         match &exp.exp {
             Expression::Alias{after_alias_exp, ..} => {
                 match &**after_alias_exp {
                     Some(after_exp) => self.interpret(after_exp, env, &expected_type),
-                    _ => Value{value: Val::NullValue, val_type: Type::NullType}
+                    _ => Ok(Value{value: Val::NullValue, val_type: Type::NullType})
                 }
             },
             _ => invalid_exp_error(exp)
         }
     }
 
-    fn interpret_lambda(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Value {
+    fn interpret_lambda(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Result<Value, RetlError> {
         trace!("interpret_lambda: {:?}", exp);
         match &exp.exp {
-            Expression::Lambda{params, return_type, body} => {
-                let func_type = Type::FuncType{
-                    param_types: params.iter().map(|p: &Parameter| {p.param_type.clone()}).collect(),
-                    return_type: Box::new(return_type.clone())
-                };
-                type_conforms(&func_type, &expected_type, &exp.token);
-                Value{
-                    value: Val::FuncValue{
-                        builtin_ident: None,
-                        parameters: params.iter()
-                            .map(|p: &Parameter| {(p.ident.clone(), p.param_type.clone())})
-                            .collect(),
-                        body: *body.clone(),
-                        env: env.clone()
-                    },
-                    val_type: func_type
-                }
+            Expression::Lambda{..} => {
+                let lambda = func_value(exp, env, &Arc::new(vec![]))?;
+                conform(&lambda.val_type, expected_type, exp)?;
+                Ok(lambda)
             },
             _ => invalid_exp_error(exp)
         }
     }
 
-    fn interpret_application(&mut self, exp: &Exp, app_env: &mut Env, expected_type: &Type) -> Value {
+    fn interpret_application(&mut self, exp: &Exp, app_env: &mut Env, expected_type: &Type) -> Result<Value, RetlError> {
         trace!("interpret_application: {:?}", exp);
         match &exp.exp {
             Expression::Application{ident, args} => {
-                let ident_value = self.interpret(ident, app_env, &Type::UnknownType);
+                let ident_value = self.interpret(ident, app_env, &Type::UnknownType)?;
+                if let [slice@Exp{exp: Expression::Slice{..}, ..}] = args.as_slice() {
+                    return self.interpret_slice(&ident_value, slice, app_env, exp, expected_type)
+                }
                 match ident_value.value {
                     Val::StringValue{value} => {
                         if args.len() != 1 {
                             error("Argument count must be 1 for string indexing", exp)
                         } else {
-                            type_conforms(&ident_value.val_type, expected_type, &exp.token);
-                            let arg = self.interpret(args.get(0).unwrap(), app_env, &Type::IntType);
+                            conform(&Type::CharType, expected_type, exp)?;
+                            let arg = self.interpret(args.get(0).unwrap(), app_env, &Type::IntType)?;
                             let string_val = value.clone();
                             match arg.value {
                                 Val::IntValue{value} => {
-                                    match string_val.get(value as usize..(value + 1) as usize) {
-                                        Some(char) if char != "\"" => Value{
+                                    match element_index(value, string_val.chars().count()).and_then(|i| { string_val.chars().nth(i) }) {
+                                        Some(char) => Ok(Value{
                                             value: Val::CharValue{value: char.to_string()},
                                             val_type: Type::CharType
-                                        },
-                                        _ => error("Invalid value for string indexing", exp)
+                                        }),
+                                        _ => raise(ErrorKind::Index, "Invalid value for string indexing", exp)
                                     }
                                 }
                                 _ => invalid_exp_error(exp)
                             }
                         }
                     },
+                    Val::RangeValue{..} => {
+                        if args.len() != 1 {
+                            error("Argument count must be 1 for list indexing", exp)
+                        } else {
+                            let arg = self.interpret(args.get(0).unwrap(), app_env, &Type::IntType)?;
+                            match arg.value {
+                                Val::IntValue{value} => {
+                                    match element_index(value, ident_value.value.range_len()).and_then(|i| { ident_value.value.range_element(i) }) {
+                                        Some(element) => Ok(element),
+                                        _ => raise(ErrorKind::Index, "Invalid value for list indexing", exp)
+                                    }
+                                },
+                                _ => raise(ErrorKind::Index, "Invalid value for list indexing", exp)
+                            }
+                        }
+                    },
                     Val::ListValue{values} => {
                         if args.len() != 1 {
                             error("Argument count must be 1 for list indexing", exp)
                         } else {
                             match ident_value.val_type {
                                 Type::ListType{list_type} => {
-                                    type_conforms(&list_type, expected_type, &exp.token);
-                                    let arg = self.interpret(args.get(0).unwrap(), app_env, &Type::IntType);
+                                    conform(&list_type, expected_type, exp)?;
+                                    let arg = self.interpret(args.get(0).unwrap(), app_env, &Type::IntType)?;
                                     match arg.value {
                                         Val::IntValue{value} => {
-                                            match values.get(value as usize) {
-                                                Some(value) => value.clone(),
-                                                _ => error("Invalid value for list indexing", exp)
+                                            match element_index(value, values.len()).and_then(|i| { values.get(i) }) {
+                                                Some(value) => Ok(value.clone()),
+                                                _ => raise(ErrorKind::Index, "Invalid value for list indexing", exp)
                                             }
                                         }
                                         _ => invalid_exp_error(exp)
@@ -183,38 +546,31 @@ impl Interpreter {
                         } else {
                             match ident_value.val_type {
                                 Type::DictType{key_type, value_type} => {
-                                    type_conforms(&*value_type, expected_type, &exp.token);
-                                    let arg = self.interpret(args.get(0).unwrap(), app_env, &*key_type);
+                                    conform(&*value_type, expected_type, exp)?;
+                                    let arg = self.interpret(args.get(0).unwrap(), app_env, &*key_type)?;
                                     match values.iter().find(|v| {v.0 == arg}) {
-                                        Some(value) => value.clone().1,
-                                        _ => error("Key does not exist", exp)
+                                        Some(value) => Ok(value.clone().1),
+                                        _ => raise(ErrorKind::Key, "Key does not exist", exp)
                                     }
                                 },
                                 _ => invalid_exp_error(exp)
                             }
                         }
                     },
-                    Val::FuncValue{builtin_ident, parameters, body, env} => {
-                        if parameters.len() != args.len() {
+                    Val::FuncValue{ref parameters, ..} => {
+                        if args.len() > parameters.len() || args.is_empty() && !parameters.is_empty() {
                             error("Argument count does not match function parameter count", exp)
+                        } else if args.len() < parameters.len() || args.iter().any(|arg| { arg.exp == Expression::Placeholder }) {
+                            let partial = self.interpret_partial(&ident_value, args, app_env, exp)?;
+                            conform(&partial.val_type, expected_type, exp)?;
+                            Ok(partial)
                         } else {
-                            match ident_value.val_type {
-                                Type::FuncType{return_type, ..} => {
-                                    let mut body_env = env.clone();
-                                    parameters.iter().zip(args)
-                                        .for_each(|pa| {
-                                            let arg_value = self.interpret(&pa.1.clone(), app_env, &pa.0.1);
-                                            body_env.insert(pa.0.0.clone(), arg_value);
-                                        });
-                                    let result = match builtin_ident {
-                                        Some(ident) => self.builtin.interpret(ident.clone(), &mut body_env, exp, self.clone()),
-                                        _ => self.interpret(&body, &mut body_env, &*return_type)
-                                    };
-                                    type_conforms(&result.val_type, expected_type, &exp.token);
-                                    result
-                                },
-                                _ => invalid_exp_error(exp)
-                            }
+                            let arg_values = args.iter()
+                                .map(|arg| { self.interpret(arg, app_env, &Type::UnknownType) })
+                                .collect::<Result<Vec<Value>, RetlError>>()?;
+                            let result = self.call(&ident_value, arg_values, exp)?;
+                            conform(&result.val_type, expected_type, exp)?;
+                            Ok(result)
                         }
                     },
                     _ => error("Expression invalid for application", exp)
@@ -224,300 +580,833 @@ impl Interpreter {
         }
     }
 
-    fn interpret_match(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Value {
+    /// Function of the parameters left open by "_" placeholders or missing trailing arguments, the
+    /// given arguments are evaluated now
+    fn interpret_partial(&mut self, func: &Value, args: &[Exp], env: &mut Env, exp: &Exp) -> Result<Value, RetlError> {
+        trace!("interpret_partial: {:?}", exp);
+        let arg_values = args.iter()
+            .map(|arg| {
+                match arg.exp {
+                    Expression::Placeholder => Ok(None),
+                    _ => self.interpret(arg, env, &Type::UnknownType).map(Some)
+                }
+            })
+            .collect::<Result<Vec<Option<Value>>, RetlError>>()?;
+        self.partial(func, arg_values, exp)
+    }
+
+    /// Function of the parameters whose argument is None or missing, bound to the given argument values
+    pub fn partial(&mut self, func: &Value, args: Vec<Option<Value>>, exp: &Exp) -> Result<Value, RetlError> {
+        trace!("partial: {:?}", func);
+        match (&func.value, &func.val_type) {
+            (Val::FuncValue{parameters, ..}, Type::FuncType{return_type, ..}) => {
+                let mut partial_env = Env::new();
+                partial_env.insert("partial$func".to_string(), func.clone());
+                let mut type_bindings: HashMap<String, Type> = HashMap::new();
+                let mut open_parameters: Vec<(String, Type)> = vec![];
+                let mut call_args: Vec<Exp> = vec![];
+                for (i, (param_ident, param_type)) in parameters.iter().enumerate() {
+                    match args.get(i).cloned().flatten() {
+                        Some(arg_value) => {
+                            let arg_value = arg_value.widen(param_type);
+                            if !unify(param_type, &arg_value.val_type, &mut type_bindings, &exp.token) {
+                                return raise(ErrorKind::Type, &format!("Argument of type {} does not conform to parameter \"{}\" of type {}",
+                                    arg_value.val_type.as_string(),
+                                    param_ident,
+                                    substitute(param_type, &type_bindings).as_string()), exp)
+                            }
+                            let bound_ident = format!("partial${}", i);
+                            partial_env.insert(bound_ident.clone(), arg_value);
+                            call_args.push(reference(&bound_ident, &exp.token))
+                        },
+                        _ => {
+                            open_parameters.push((param_ident.clone(), param_type.clone()));
+                            call_args.push(reference(param_ident, &exp.token))
+                        }
+                    }
+                }
+                let open_parameters = open_parameters.into_iter()
+                    .map(|(ident, param_type)| { (ident, substitute(&param_type, &type_bindings)) })
+                    .collect();
+                Ok(call_value("partial$func", call_args, open_parameters, substitute(return_type, &type_bindings), partial_env, &exp.token))
+            },
+            _ => invalid_exp_error(exp)
+        }
+    }
+
+    fn interpret_match(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Result<Value, RetlError> {
         trace!("interpret_match: {:?}", exp);
         match &exp.exp {
             Expression::Match{match_exp, cases} => {
-                let match_val = self.interpret(match_exp, env, &Type::UnknownType);
-                match cases.iter().find(|case| {
-                    match case.pattern.clone() {
-                        Pattern::TypePattern{ident, case_type, predicate} => {
-                            match type_conforms_no_error(&match_val.val_type.clone(), &case_type, &case.case_exp.token) {
-                                Type::UnknownType => false,
-                                _ => {
-                                    env.insert(ident, match_val.clone());
-                                    match predicate {
-                                        Some(pred) => {
-                                            match self.interpret(&pred, env, &Type::BoolType).value {
-                                                Val::BoolValue{value} => value,
-                                                _ => false
-                                            }
-                                        },
-                                        _ => true
-                                    }
-                                }
-                            }
-                        },
-                        Pattern::Literal{literal} => {
-                            match (match_val.value.clone(), literal) {
-                                (Val::IntValue{value}, Literal::IntLit{literal}) => value == literal,
-                                (Val::BoolValue{value}, Literal::BoolLit{literal}) => value == literal,
-                                (Val::CharValue{value}, Literal::CharLit{literal}) => value == literal,
-                                (Val::StringValue{value}, Literal::StringLit{literal}) => value == literal,
-                                (Val::NullValue, Literal::NullLit) => true,
-                                _ => false
-                            }
-                        },
-                        Pattern::MultiLiteral{literals} => {
-                            literals.iter().any(|lit: &Literal| {
-                                match (match_val.value.clone(), lit) {
-                                    (Val::IntValue{value}, Literal::IntLit{literal}) => value == *literal,
-                                    (Val::BoolValue{value}, Literal::BoolLit{literal}) => value == *literal,
-                                    (Val::CharValue{value}, Literal::CharLit{literal}) => value == *literal,
-                                    (Val::StringValue{value}, Literal::StringLit{literal}) => value == *literal,
-                                    (Val::NullValue, Literal::NullLit) => true,
-                                    _ => false
-                                }
-                            })
-                        },
-                        Pattern::Range{range} => {
-                            match range {
-                                Expression::ListDef{values} => {
-                                    values.iter().any(|value: &Exp| {
-                                        match (match_val.value.clone(), value.exp.clone()) {
-                                            (Val::IntValue{value}, Expression::Lit{lit: Literal::IntLit{literal}}) => value == literal,
-                                            _ => false
-                                        }
-                                    })
-                                },
-                                _ => false
-                            }
-                        },
-                        Pattern::Any => true
+                let match_val = self.interpret(match_exp, env, &Type::UnknownType)?;
+                for case in cases.iter() {
+                    let case_env = &mut env.child();
+                    if self.match_pattern(&case.pattern, &match_val, case_env, &case.case_exp.token) &&
+                        self.match_guard(&case.guard, case_env)? {
+                        return self.interpret(&case.case_exp, case_env, expected_type)
                     }
-                }) {
-                    Some(value) => self.interpret(&value.case_exp, env, expected_type),
-                    _ => error("No patterns matched. Use the catch-all _ pattern", exp)
                 }
+                raise(ErrorKind::Match, "No patterns matched. Use the catch-all _ pattern", exp)
             },
             _ => invalid_exp_error(exp)
         }
     }
 
-    fn interpret_primitive(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Value {
+    /// Runs a function body, a return inside it ends the call with the returned value
+    pub fn interpret_body(&mut self, body: &Exp, env: &mut Env, return_type: &Type) -> Result<Value, RetlError> {
+        match self.interpret(body, env, return_type) {
+            Err(RetlError{kind: ErrorKind::Return{value}, ..}) => {
+                conform(&value.val_type, return_type, body)?;
+                Ok(*value)
+            },
+            result => result
+        }
+    }
+
+    fn interpret_try(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Result<Value, RetlError> {
+        trace!("interpret_try: {:?}", exp);
+        match &exp.exp {
+            Expression::Try{try_exp, catch_ident, catch_exp} => {
+                match self.interpret(try_exp, &mut env.child(), expected_type) {
+                    Err(e) if !e.kind.is_control_flow() => {
+                        let catch_env = &mut env.child();
+                        catch_env.insert(catch_ident.clone(), e.to_value());
+                        self.interpret(catch_exp, catch_env, expected_type)
+                    },
+                    result => result
+                }
+            },
+            _ => invalid_exp_error(exp)
+        }
+    }
+
+    fn match_guard(&mut self, guard: &Option<Exp>, env: &mut Env) -> Result<bool, RetlError> {
+        trace!("match_guard: {:?}", guard);
+        match guard {
+            Some(guard_exp) => match self.interpret(guard_exp, env, &Type::BoolType)?.value {
+                Val::BoolValue{value} => Ok(value),
+                _ => Ok(false)
+            },
+            _ => Ok(true)
+        }
+    }
+
+    fn match_regex(&mut self, regex: &str, match_val: &Value, env: &mut Env) -> bool {
+        trace!("match_regex: {:?}", regex);
+        let value = match &match_val.value {
+            Val::StringValue{value} => value,
+            _ => return false
+        };
+        let compiled = self.regex_cache.entry(regex.to_string())
+            .or_insert_with(|| { Regex::new(regex).unwrap() });
+        match compiled.captures(value) {
+            Some(captures) => {
+                for name in compiled.capture_names().flatten() {
+                    let capture = captures.name(name).map_or("", |c| { c.as_str() });
+                    env.insert(name.to_string(), Value{
+                        value: Val::StringValue{value: capture.into()},
+                        val_type: Type::StringType
+                    });
+                }
+                true
+            },
+            _ => false
+        }
+    }
+
+    pub fn match_pattern(&mut self, pattern: &Pattern, match_val: &Value, env: &mut Env, token: &Token) -> bool {
+        trace!("match_pattern: {:?}", pattern);
+        match pattern {
+            Pattern::TypePattern{ident, case_type} => {
+                let narrowed_val = match_val.narrow();
+                if !narrowed_val.value.conforms(case_type) {
+                    return false
+                }
+                match type_conforms_no_error(&narrowed_val.val_type, case_type, token) {
+                    Type::UnknownType => false,
+                    _ => {
+                        env.insert(ident.clone(), narrowed_val);
+                        true
+                    }
+                }
+            },
+            Pattern::Literal{literal} => literal_matches(&match_val.value, literal),
+            Pattern::MultiLiteral{literals} => {
+                literals.iter().any(|lit: &Literal| { literal_matches(&match_val.value, lit) })
+            },
+            Pattern::Range{start, end, inclusive} => {
+                match (&match_val.value, start, end) {
+                    (Val::IntValue{value}, Literal::IntLit{literal: s}, Literal::IntLit{literal: e}) =>
+                        s <= value && (value < e || (*inclusive && value == e)),
+                    (Val::CharValue{value}, Literal::CharLit{literal: s}, Literal::CharLit{literal: e}) =>
+                        s <= value && (value < e || (*inclusive && value == e)),
+                    _ => false
+                }
+            },
+            Pattern::Regex{regex} => self.match_regex(regex, match_val, env),
+            Pattern::Variant{ident, variant, fields} => {
+                match &match_val.value {
+                    Val::EnumValue{ident: enum_ident, variant: enum_variant, values}
+                    if enum_ident == ident && enum_variant == variant && values.len() == fields.len() => {
+                        fields.iter().zip(values.iter())
+                            .all(|(field, value)| { self.match_pattern(field, value, env, token) })
+                    },
+                    _ => false
+                }
+            },
+            Pattern::Record{ident, fields} => {
+                match &match_val.value {
+                    Val::RecordValue{ident: record_ident, fields: record_fields} if record_ident == ident => {
+                        fields.iter().all(|(field, field_pattern)| {
+                            match record_fields.iter().find(|rf| { rf.0 == *field }) {
+                                Some(record_field) => self.match_pattern(field_pattern, &record_field.1, env, token),
+                                _ => false
+                            }
+                        })
+                    },
+                    _ => false
+                }
+            },
+            Pattern::Tuple{fields} => {
+                match &match_val.value {
+                    Val::TupleValue{values} if values.len() == fields.len() => {
+                        fields.iter().zip(values.iter())
+                            .all(|(field, value)| { self.match_pattern(field, value, env, token) })
+                    },
+                    _ => false
+                }
+            },
+            Pattern::List{elems, rest} => {
+                let match_val = &match_val.materialize();
+                match &match_val.value {
+                    Val::ListValue{values} if values.len() == elems.len() ||
+                        (rest.is_some() && values.len() >= elems.len()) => {
+                        let elems_match = elems.iter().zip(values.iter())
+                            .all(|(elem, value)| { self.match_pattern(elem, value, env, token) });
+                        match rest {
+                            Some(rest_pattern) if elems_match => {
+                                let rest_val = Value{
+                                    value: Val::ListValue{values: Arc::new(values[elems.len()..].to_vec())},
+                                    val_type: match_val.val_type.clone()
+                                };
+                                self.match_pattern(rest_pattern, &rest_val, env, token)
+                            },
+                            _ => elems_match
+                        }
+                    },
+                    _ => false
+                }
+            },
+            Pattern::Dict{entries} => {
+                match &match_val.value {
+                    Val::DictValue{values} => entries.iter().all(|(key, entry_pattern)| {
+                        match values.iter().find(|v| { literal_matches(&v.0.value, key) }) {
+                            Some((_, value)) => self.match_pattern(entry_pattern, value, env, token),
+                            _ => false
+                        }
+                    }),
+                    _ => false
+                }
+            },
+            Pattern::Bind{ident} => {
+                env.insert(ident.clone(), match_val.clone());
+                true
+            },
+            Pattern::Any => true
+        }
+    }
+
+    fn interpret_primitive(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Result<Value, RetlError> {
         trace!("interpret_primitive: {:?}", exp);
         match &exp.exp {
             Expression::Primitive{operator, left, right} => {
-                let left_value = self.interpret(left, env, &Type::UnknownType);
-                let right_value = self.interpret(right, env, &Type::UnknownType);
-                let result = operator.interpret(&left_value, &right_value, exp);
-                type_conforms(&result.val_type, expected_type, &exp.token);
-                result
+                let left_value = self.interpret(left, env, &Type::UnknownType)?;
+                let right_value = self.interpret(right, env, &Type::UnknownType)?;
+                let result = primitive(operator, left_value, right_value, exp)?;
+                conform(&result.val_type, expected_type, exp)?;
+                Ok(result)
             },
             _ => invalid_exp_error(exp)
         }
     }
 
-    fn interpret_reference(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Value {
+    fn interpret_reference(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Result<Value, RetlError> {
         trace!("interpret_reference: {:?}", exp);
         match &exp.exp {
             Expression::Reference{ident} => {
-                let ref_value = env.get(ident).clone();
+                let ref_value = env.get(ident).map(|r| { r.current() });
                 match ref_value {
                     Some(r) => {
-                        type_conforms(&r.val_type, expected_type, &exp.token);
-                        r.clone()
+                        conform(&r.val_type, expected_type, exp)?;
+                        Ok(r)
                     },
-                    _ => error(&("Reference \"".to_string() + ident + "\" does not exist"), exp)
+                    _ => raise(ErrorKind::Reference, &("Reference \"".to_string() + ident + "\" does not exist"), exp)
                 }
             },
             _ => invalid_exp_error(exp)
         }
     }
 
-    fn interpret_branch(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Value {
+    fn interpret_branch(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Result<Value, RetlError> {
         trace!("interpret_branch: {:?}", exp);
         match &exp.exp {
             Expression::Branch{condition, if_branch, else_branch } => {
                 match &**else_branch {
-                    Some(else_exp) => type_conforms(&if_branch.exp_type, &else_exp.exp_type, &exp.token),
-                    _ => type_conforms(&if_branch.exp_type, &Type::NullType, &exp.token)
+                    Some(else_exp) => conform(&if_branch.exp_type, &else_exp.exp_type, exp)?,
+                    _ => conform(&if_branch.exp_type, &Type::NullType, exp)?
                 };
 
-                match self.interpret(&**condition, env, &Type::BoolType).value {
+                match self.interpret(&**condition, env, &Type::BoolType)?.value {
                     Val::BoolValue{value} => {
                         if value {
-                            self.interpret(&if_branch, env, expected_type)
+                            self.interpret(&if_branch, &mut env.child(), expected_type)
                         } else {
                             match &**else_branch {
-                                Some(else_exp) => self.interpret(&else_exp, env, expected_type),
-                                _ => Value{value: Val::NullValue, val_type: Type::NullType }
+                                Some(else_exp) => self.interpret(&else_exp, &mut env.child(), expected_type),
+                                _ => Ok(Value{value: Val::NullValue, val_type: Type::NullType})
                             }
                         }
                     },
-                    _ => error("Invalid branch condition", exp)
+                    _ => raise(ErrorKind::Type, "Invalid branch condition", exp)
                 }
             },
             _ => invalid_exp_error(exp)
         }
     }
 
-    fn get_iter_size(&mut self, exp: &Exp, env: &mut Env) -> usize {
-        trace!("get_iter_size: {:?}", exp);
-        let iter_value = self.interpret(exp, env, &Type::UnknownType);
-        match iter_value.value.clone() {
-            Val::IntValue{value} => value as usize,
-            Val::StringValue{value} => value.len(),
-            Val::ListValue{values} => values.len(),
-            Val::TupleValue{values} => values.len(),
-            _ => {
-                error("Invalid iterator", exp);
-                0
+    /// Runs one pass of a loop body, false when the body breaks out of the loop
+    fn interpret_loop_body(&mut self, body: &Exp, env: &mut Env) -> Result<bool, RetlError> {
+        match self.interpret(body, env, &Type::UnknownType) {
+            Err(RetlError{kind: ErrorKind::Break, ..}) => Ok(false),
+            Err(RetlError{kind: ErrorKind::Continue, ..}) | Ok(_) => Ok(true),
+            Err(e) => Err(e)
+        }
+    }
+
+    /// Calls a function value with already evaluated arguments for all of its parameters
+    pub fn call(&mut self, func: &Value, args: Vec<Value>, exp: &Exp) -> Result<Value, RetlError> {
+        trace!("call: {:?}", func);
+        match (&func.value, &func.val_type) {
+            (Val::FuncValue{builtin_ident, parameters, body, env, group}, Type::FuncType{return_type, ..}) => {
+                let mut type_bindings: HashMap<String, Type> = HashMap::new();
+                let mut arg_values: Vec<Value> = vec![];
+                for ((param_ident, param_type), arg_value) in parameters.iter().zip(args) {
+                    let mut arg_value = arg_value.widen(param_type);
+                    let param_type = &match builtin_ident {
+                        Some(ident) => seq_param_type(ident, param_type, &arg_value.val_type),
+                        _ => param_type.clone()
+                    };
+                    if let (Some(ident), Type::ListType{..}) = (&builtin_ident, param_type) {
+                        if *ident != Keyword::Seq {
+                            arg_value = arg_value.materialize() // builtins only operate on list values, a seq keeps its source lazy
+                        }
+                    }
+                    if !unify(param_type, &arg_value.val_type, &mut type_bindings, &exp.token) {
+                        return raise(ErrorKind::Type, &format!("Argument of type {} does not conform to parameter \"{}\" of type {}",
+                            arg_value.val_type.as_string(),
+                            param_ident,
+                            substitute(param_type, &type_bindings).as_string()), exp)
+                    }
+                    arg_values.push(arg_value);
+                }
+                let resolved_return_type = substitute(return_type, &type_bindings);
+                if self.depth >= MAX_CALL_DEPTH {
+                    return error(&format!("Maximum call depth of {} exceeded", MAX_CALL_DEPTH), exp)
+                }
+                self.depth += 1;
+                let result = match builtin_ident {
+                    Some(ident) => closure_env(env, group).and_then(|mut body_env| {
+                        body_env.extend(parameters.iter().map(|(param_ident, _)| { param_ident.clone() }).zip(arg_values));
+                        self.builtin.interpret(ident.clone(), &body_env, exp, self.clone())
+                    }),
+                    _ => self.run_function(body, parameters, arg_values, env, group, &resolved_return_type)
+                };
+                self.depth -= 1;
+                result
+            },
+            _ => raise(ErrorKind::Type, "Value is not a function", exp)
+        }
+    }
+
+    /// Runs the body of a user function with its parameters bound to conformed arguments, on the VM when
+    /// that engine is selected and the body compiles
+    pub fn run_function(&mut self, body: &Body, parameters: &[(String, Type)], args: Vec<Value>, env: &Env,
+                        group: &Arc<Vec<(String, Exp)>>, return_type: &Type) -> Result<Value, RetlError> {
+        if self.engine == Engine::Vm {
+            if let Some(chunk) = body.code.get_or_init(|| { vm::compile(body, parameters, group) }) {
+                return vm::run(self, chunk, args, env, group, return_type)
             }
         }
+        let mut body_env = closure_env(env, group)?;
+        body_env.extend(parameters.iter().map(|(param_ident, _)| { param_ident.clone() }).zip(args));
+        self.interpret_body(body, &mut body_env, return_type)
     }
 
-    fn get_iter_element(&mut self, index: usize, exp: &Exp, env: &mut Env) -> Value {
-        trace!("get_iter_element: {:?}", exp);
-        let iter_value = self.interpret(exp, env, &Type::UnknownType);
-        let iter_result = match iter_value.value.clone() {
-            Val::IntValue{..} =>
-                Some(Value{value: Val::IntValue{value: index as i32}, val_type: Type::IntType}),
-            Val::StringValue{value} =>
-                Some(Value{
-                    value: Val::CharValue{value: value.as_bytes()[index].clone().to_string() },
-                    val_type: Type::CharType
-                }),
-            Val::ListValue{values} => values.get(index).cloned(),
-            Val::TupleValue{values} => values.get(index).cloned(),
+    /// Feeds each element of an iterable value with its position to consume until it returns false, a seq
+    /// pulls one source element at a time through all of its stages and stops once a take is full
+    fn for_each_element(&mut self, iter_value: &Value, exp: &Exp,
+                        consume: &mut dyn FnMut(&mut Interpreter, usize, Value) -> Result<bool, RetlError>) -> Result<(), RetlError> {
+        trace!("for_each_element: {:?}", iter_value);
+        match &iter_value.value {
+            Val::SeqValue{source, stages} => {
+                let mut taken: Vec<usize> = vec![0; stages.len()];
+                let mut position = 0;
+                'pull: for mut element in iter_elements(source, exp)? {
+                    let is_full = stages.iter().zip(taken.iter()).any(|(stage, taken)| {
+                        matches!(stage, SeqStage::Take{count} if taken >= count)
+                    });
+                    if is_full {
+                        break
+                    }
+                    for (stage, taken) in stages.iter().zip(taken.iter_mut()) {
+                        match stage {
+                            SeqStage::Map{func} => element = self.call(func, vec![element], exp)?,
+                            SeqStage::Filter{func} => {
+                                if let Val::BoolValue{value: false} = self.call(func, vec![element.clone()], exp)?.value {
+                                    continue 'pull
+                                }
+                            },
+                            SeqStage::Take{count} => {
+                                if *taken >= *count {
+                                    continue 'pull
+                                }
+                                *taken += 1
+                            }
+                        }
+                    }
+                    if !consume(self, position, element)? {
+                        break
+                    }
+                    position += 1
+                }
+                Ok(())
+            },
             _ => {
-                error("Invalid iterator", exp);
-                None
+                for (index, element) in iter_elements(iter_value, exp)?.enumerate() {
+                    if !consume(self, index, element)? {
+                        break
+                    }
+                }
+                Ok(())
             }
-        };
-        match iter_result {
-            Some(value) => value,
-            _ => Value{value: Val::NullValue, val_type: Type::NullType}
         }
     }
 
-    fn interpret_iter(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Value {
+    /// Runs a seq to the end, giving a list of its elements
+    pub fn collect_seq(&mut self, seq: &Value, exp: &Exp) -> Result<Value, RetlError> {
+        trace!("collect_seq: {:?}", seq);
+        let mut values: Vec<Value> = vec![];
+        self.for_each_element(seq, exp, &mut |_, _, element| {
+            values.push(element);
+            Ok(true)
+        })?;
+        let list_type = match &seq.val_type {
+            Type::SeqType{seq_type} => *seq_type.clone(),
+            _ => Type::UnknownType
+        };
+        Ok(Value{value: Val::ListValue{values: Arc::new(values)}, val_type: Type::ListType{list_type: Box::new(list_type)}})
+    }
+
+    fn interpret_iter(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Result<Value, RetlError> {
         trace!("interpret_iter: {:?}", exp);
+        conform(&Type::NullType, expected_type, exp)?;
         match &exp.exp {
             Expression::Iter{iter, iter_exp } => {
-                let iterator_size = self.get_iter_size(iter, env);
-                let mut index: usize = 0;
-                while index < iterator_size {
-                    let iter_element = self.get_iter_element(index, iter, env);
-                    let mut iter_env = env.clone();
-                    iter_env.insert("__elem".to_string(), iter_element);
-                    self.interpret(&iter_exp, &mut iter_env, expected_type);
-                    index += 1;
-                };
-                Value{value: Val::NullValue, val_type: Type::NullType}
+                let iter_value = self.interpret(iter, env, &Type::UnknownType)?;
+                self.for_each_element(&iter_value, iter, &mut |interpreter, _, element| {
+                    let mut iter_env = env.child();
+                    iter_env.insert("__elem".to_string(), element);
+                    interpreter.interpret_loop_body(iter_exp, &mut iter_env)
+                })?;
+                Ok(null_value())
             },
             _ => invalid_exp_error(exp)
         }
     }
 
-    fn interpret_list_def(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Value {
+    fn interpret_while(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Result<Value, RetlError> {
+        trace!("interpret_while: {:?}", exp);
+        conform(&Type::NullType, expected_type, exp)?;
+        match &exp.exp {
+            Expression::While{condition, body} => {
+                loop {
+                    match self.interpret(condition, env, &Type::BoolType)?.value {
+                        Val::BoolValue{value: true} => (),
+                        Val::BoolValue{value: false} => break,
+                        _ => return raise(ErrorKind::Type, "Invalid loop condition", exp)
+                    }
+                    if !self.interpret_loop_body(body, &mut env.child())? {
+                        break
+                    }
+                }
+                Ok(null_value())
+            },
+            _ => invalid_exp_error(exp)
+        }
+    }
+
+    fn interpret_for(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Result<Value, RetlError> {
+        trace!("interpret_for: {:?}", exp);
+        conform(&Type::NullType, expected_type, exp)?;
+        match &exp.exp {
+            Expression::For{index, pattern, iter, body} => {
+                let iter_value = self.interpret(iter, env, &Type::UnknownType)?;
+                self.for_each_element(&iter_value, iter, &mut |interpreter, i, element| {
+                    let mut body_env = env.child();
+                    if let Some(index_ident) = index {
+                        body_env.insert(index_ident.clone(), Value{value: Val::IntValue{value: i as i32}, val_type: Type::IntType});
+                    }
+                    if !interpreter.match_pattern(pattern, &element, &mut body_env, &exp.token) {
+                        return Err(RetlError{kind: ErrorKind::Match, message: format!("Element of type {} does not match loop pattern",
+                            element.val_type.as_string()), token: Box::new(exp.token.clone())})
+                    }
+                    interpreter.interpret_loop_body(body, &mut body_env)
+                })?;
+                Ok(null_value())
+            },
+            _ => invalid_exp_error(exp)
+        }
+    }
+
+    fn interpret_list_def(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Result<Value, RetlError> {
         trace!("interpret_list_def: {:?}", exp);
         match &exp.exp {
             Expression::ListDef{values} => {
-                let list_type = match exp.exp_type.clone() {
-                    Type::ListType{list_type} => *list_type,
-                    _ => Type::UnknownType
+                let list_values = values.iter()
+                    .map(|e: &Exp| { self.interpret(e, env, &Type::UnknownType) })
+                    .collect::<Result<Vec<Value>, RetlError>>()?;
+                list_value(list_values, expected_type, exp)
+            },
+            _ => invalid_exp_error(exp)
+        }
+    }
+
+    fn interpret_comprehension(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Result<Value, RetlError> {
+        trace!("interpret_comprehension: {:?}", exp);
+        match &exp.exp {
+            Expression::Comprehension{key, value, clauses} => {
+                let mut entries: Vec<(Option<Value>, Value)> = vec![];
+                self.comprehend(clauses, key, value, env, &mut entries)?;
+                let result = match &exp.exp_type {
+                    Type::DictType{key_type, value_type} => {
+                        let mut dict_values: Vec<(Value, Value)> = vec![];
+                        for (entry_key, entry_value) in entries.into_iter() {
+                            let entry_key = entry_key.unwrap_or_else(null_value);
+                            match dict_values.iter().position(|(k, _)| { *k == entry_key }) {
+                                Some(i) => dict_values[i].1 = entry_value, // a later duplicate key wins
+                                _ => dict_values.push((entry_key, entry_value))
+                            }
+                        }
+                        let (key_type, value_type) = match dict_values.first() {
+                            Some((k, v)) => (k.val_type.clone(), v.val_type.clone()),
+                            _ => (*key_type.clone(), *value_type.clone())
+                        };
+                        Value{
+                            value: Val::DictValue{values: Arc::new(dict_values)},
+                            val_type: Type::DictType{key_type: Box::new(key_type), value_type: Box::new(value_type)}
+                        }
+                    },
+                    _ => {
+                        let list_values: Vec<Value> = entries.into_iter().map(|(_, v)| { v }).collect();
+                        let list_type = match (list_values.first(), &exp.exp_type) {
+                            (Some(first), _) => first.val_type.clone(),
+                            (_, Type::ListType{list_type}) => *list_type.clone(),
+                            _ => Type::UnknownType
+                        };
+                        Value{value: Val::ListValue{values: Arc::new(list_values)}, val_type: Type::ListType{list_type: Box::new(list_type)}}
+                    }
                 };
-                let list_values: Vec<Value> = values.iter().map(|e: &Exp| {
-                    let list_val = self.interpret(e, env, &Type::UnknownType);
-                    type_conforms(&list_val.val_type, &list_type, &exp.token);
-                    list_val
-                }).collect();
-                let expected_list_type = Type::ListType{
-                    list_type: Box::new(match list_values.clone().first() {
-                        Some(value) => value.val_type.clone(),
-                        _ => Type::UnknownType
+                let is_empty = matches!(&result.value, Val::ListValue{values} if values.is_empty()) ||
+                    matches!(&result.value, Val::DictValue{values} if values.is_empty());
+                let final_type = if is_empty { // nothing to infer from
+                    type_conforms_no_error(&result.val_type, expected_type, &exp.token)
+                } else {
+                    conform(&result.val_type, expected_type, exp)?
+                };
+                Ok(Value{value: result.value, val_type: final_type})
+            },
+            _ => invalid_exp_error(exp)
+        }
+    }
+
+    /// Runs the remaining clauses left to right, collecting a key and value for each binding that passes them all
+    fn comprehend(&mut self, clauses: &[Clause], key: &Option<Exp>, value: &Exp, env: &mut Env,
+                  entries: &mut Vec<(Option<Value>, Value)>) -> Result<(), RetlError> {
+        match clauses.split_first() {
+            Some((Clause::For{index, pattern, iter}, rest)) => {
+                let iter_value = self.interpret(iter, env, &Type::UnknownType)?;
+                self.for_each_element(&iter_value, iter, &mut |interpreter, i, element| {
+                    let mut clause_env = env.child();
+                    if let Some(index_ident) = index {
+                        clause_env.insert(index_ident.clone(), Value{value: Val::IntValue{value: i as i32}, val_type: Type::IntType});
+                    }
+                    if !interpreter.match_pattern(pattern, &element, &mut clause_env, &iter.token) {
+                        return Err(RetlError{kind: ErrorKind::Match, message: format!("Element of type {} does not match comprehension pattern",
+                            element.val_type.as_string()), token: Box::new(iter.token.clone())})
+                    }
+                    interpreter.comprehend(rest, key, value, &mut clause_env, entries)?;
+                    Ok(true)
+                })
+            },
+            Some((Clause::If{condition}, rest)) => {
+                match self.interpret(condition, env, &Type::BoolType)?.value {
+                    Val::BoolValue{value: true} => self.comprehend(rest, key, value, env, entries),
+                    Val::BoolValue{value: false} => Ok(()),
+                    _ => Err(RetlError{kind: ErrorKind::Type, message: "Invalid comprehension condition".to_string(), token: Box::new(condition.token.clone())})
+                }
+            },
+            None => {
+                let entry_key = match key {
+                    Some(key_exp) => Some(self.interpret(key_exp, env, &Type::UnknownType)?),
+                    _ => None
+                };
+                entries.push((entry_key, self.interpret(value, env, &Type::UnknownType)?));
+                Ok(())
+            }
+        }
+    }
+
+    fn interpret_slice(&mut self, sliced: &Value, slice: &Exp, env: &mut Env, exp: &Exp, expected_type: &Type) -> Result<Value, RetlError> {
+        trace!("interpret_slice: {:?}", slice);
+        match &slice.exp {
+            Expression::Slice{start, end, step} => {
+                let mut bounds: Vec<Option<i32>> = vec![];
+                for bound in [&**start, &**end, &**step].iter() {
+                    bounds.push(match bound {
+                        Some(bound_exp) => match self.interpret(bound_exp, env, &Type::IntType)?.narrow().value {
+                            Val::IntValue{value} => Some(value),
+                            _ => return raise(ErrorKind::Type, "Slice bounds must be ints", bound_exp)
+                        },
+                        _ => None
                     })
+                }
+                let step = bounds[2].unwrap_or(1);
+                if step == 0 {
+                    return raise(ErrorKind::Index, "Slice step cannot be 0", slice)
+                }
+                let select = |len: usize| { slice_indices(len, bounds[0], bounds[1], step) };
+                let sliced = sliced.materialize(); // ranges slice like lists
+                let result = match &sliced.value {
+                    Val::StringValue{value} => {
+                        let chars: Vec<char> = value.chars().collect();
+                        Value{
+                            value: Val::StringValue{value: select(chars.len()).into_iter().map(|i| { chars[i] }).collect::<String>().into()},
+                            val_type: Type::StringType
+                        }
+                    },
+                    Val::ListValue{values} => Value{
+                        value: Val::ListValue{values: Arc::new(select(values.len()).into_iter().map(|i| { values[i].clone() }).collect())},
+                        val_type: sliced.val_type.clone()
+                    },
+                    Val::TupleValue{values} => {
+                        let indices = select(values.len());
+                        let tuple_types = indices.iter().map(|i| { match &sliced.val_type {
+                            Type::TupleType{tuple_types} => tuple_types[*i].clone(),
+                            _ => values[*i].val_type.clone()
+                        }}).collect();
+                        Value{
+                            value: Val::TupleValue{values: Arc::new(indices.into_iter().map(|i| { values[i].clone() }).collect())},
+                            val_type: Type::TupleType{tuple_types}
+                        }
+                    },
+                    _ => return raise(ErrorKind::Type, &format!("Type {} cannot be sliced", sliced.val_type.as_string()), exp)
                 };
-                let final_list_type = type_conforms(&expected_list_type, expected_type, &exp.token);
-                Value{value: Val::ListValue{values: list_values}, val_type: final_list_type}
+                conform(&result.val_type, expected_type, exp)?;
+                Ok(result)
             },
             _ => invalid_exp_error(exp)
         }
     }
 
-    fn interpret_tuple_def(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Value {
+    fn interpret_range_def(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Result<Value, RetlError> {
+        trace!("interpret_range_def: {:?}", exp);
+        match &exp.exp {
+            Expression::RangeDef{start, end, step, inclusive} => {
+                let start_value = self.interpret(start, env, &Type::UnknownType)?;
+                let end_value = self.interpret(end, env, &Type::UnknownType)?;
+                let step_value = match &**step {
+                    Some(step_exp) => Some(self.interpret(step_exp, env, &Type::IntType)?),
+                    _ => None
+                };
+                range_value(start_value, end_value, step_value, *inclusive, expected_type, exp)
+            },
+            _ => invalid_exp_error(exp)
+        }
+    }
+
+    fn interpret_interpolation(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Result<Value, RetlError> {
+        trace!("interpret_interpolation: {:?}", exp);
+        conform(&exp.exp_type, expected_type, exp)?;
+        match &exp.exp {
+            Expression::Interpolation{parts} => {
+                let mut value = String::new();
+                for part in parts.iter() {
+                    value.push_str(&self.interpret(part, env, &Type::UnknownType)?.render());
+                }
+                Ok(Value{value: Val::StringValue{value: value.into()}, val_type: Type::StringType})
+            },
+            _ => invalid_exp_error(exp)
+        }
+    }
+
+    fn interpret_tuple_def(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Result<Value, RetlError> {
         trace!("interpret_tuple_def: {:?}", exp);
         match &exp.exp {
             Expression::TupleDef{values} => {
-                let expected_tuple_type = type_conforms(&exp.exp_type, expected_type, &exp.token);
+                let expected_tuple_type = conform(&exp.exp_type, expected_type, exp)?;
                 let expected_tuple_types = match expected_tuple_type {
                     Type::TupleType{tuple_types} => tuple_types,
                     _ => vec![Type::UnknownType; values.len()]
                 };
                 let tuple_values: Vec<Value> = values.iter().zip(expected_tuple_types)
                     .map(|(e, t): (&Exp, Type)| { self.interpret(e, env, &t) })
-                    .collect();
+                    .collect::<Result<Vec<Value>, RetlError>>()?;
                 let tuple_types: Vec<Type> = tuple_values.iter()
                     .map(|tv: &Value| { tv.val_type.clone() }).collect();
-                Value{
-                    value: Val::TupleValue{values: tuple_values},
+                Ok(Value{
+                    value: Val::TupleValue{values: Arc::new(tuple_values)},
                     val_type: Type::TupleType{tuple_types}
-                }
+                })
             },
             _ => invalid_exp_error(exp)
         }
     }
 
-    fn interpret_tuple_access(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Value {
+    fn interpret_tuple_access(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Result<Value, RetlError> {
         trace!("interpret_tuple_access: {:?}", exp);
         match &exp.exp {
             Expression::TupleAccess{ident, index} => {
-                let tuple_value = self.interpret(&**ident, env, &exp.exp_type);
+                let tuple_value = self.interpret(&**ident, env, &exp.exp_type)?;
                 match tuple_value.value {
                     Val::TupleValue{values} => {
                         let tuple_element = values[*index].clone();
-                        type_conforms(&tuple_element.val_type, expected_type, &exp.token);
-                        tuple_element
+                        conform(&tuple_element.val_type, expected_type, exp)?;
+                        Ok(tuple_element)
                     },
-                    _ => error("Not a valid tuple value", exp)
+                    _ => raise(ErrorKind::Type, "Not a valid tuple value", exp)
                 }
             },
             _ => invalid_exp_error(exp)
         }
     }
 
-    fn interpret_dict_def(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Value {
+    fn interpret_field_access(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Result<Value, RetlError> {
+        trace!("interpret_field_access: {:?}", exp);
+        match &exp.exp {
+            Expression::FieldAccess{ident, field} => {
+                let record_value = self.interpret(&**ident, env, &exp.exp_type)?;
+                match record_value.value {
+                    Val::RecordValue{fields, ..} => {
+                        match fields.into_iter().find(|f| { f.0 == *field }) {
+                            Some((_, field_value)) => {
+                                conform(&field_value.val_type, expected_type, exp)?;
+                                Ok(field_value)
+                            },
+                            _ => raise(ErrorKind::Reference, &("Field \"".to_string() + field + "\" does not exist"), exp)
+                        }
+                    },
+                    _ => raise(ErrorKind::Type, "Not a valid record value", exp)
+                }
+            },
+            _ => invalid_exp_error(exp)
+        }
+    }
+
+    fn interpret_record_def(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Result<Value, RetlError> {
+        trace!("interpret_record_def: {:?}", exp);
+        match &exp.exp {
+            Expression::RecordDef{ident, fields} => {
+                conform(&exp.exp_type, expected_type, exp)?;
+                let field_types = match &exp.exp_type {
+                    Type::RecordType{fields, ..} => fields.clone(),
+                    _ => vec![]
+                };
+                let record_fields: Vec<(String, Value)> = fields.iter().map(|(field, field_exp)| {
+                    let field_type = match field_types.iter().find(|ft| { ft.0 == *field }) {
+                        Some(ft) => ft.1.clone(),
+                        _ => Type::UnknownType
+                    };
+                    Ok((field.clone(), self.interpret(field_exp, env, &field_type)?))
+                }).collect::<Result<Vec<(String, Value)>, RetlError>>()?;
+                Ok(Value{
+                    value: Val::RecordValue{ident: ident.clone(), fields: record_fields},
+                    val_type: exp.exp_type.clone()
+                })
+            },
+            _ => invalid_exp_error(exp)
+        }
+    }
+
+    fn interpret_enum_def(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Result<Value, RetlError> {
+        trace!("interpret_enum_def: {:?}", exp);
+        match &exp.exp {
+            Expression::EnumDef{ident, variant, values} => {
+                conform(&exp.exp_type, expected_type, exp)?;
+                let variant_types = match &exp.exp_type {
+                    Type::EnumType{variants, ..} => match variants.iter().find(|v| { v.0 == *variant }) {
+                        Some(v) => v.1.clone(),
+                        _ => vec![]
+                    },
+                    _ => vec![]
+                };
+                let variant_values: Vec<Value> = values.iter().zip(variant_types)
+                    .map(|(e, t): (&Exp, Type)| { self.interpret(e, env, &t) })
+                    .collect::<Result<Vec<Value>, RetlError>>()?;
+                Ok(Value{
+                    value: Val::EnumValue{ident: ident.clone(), variant: variant.clone(), values: variant_values},
+                    val_type: exp.exp_type.clone()
+                })
+            },
+            _ => invalid_exp_error(exp)
+        }
+    }
+
+    fn interpret_dict_def(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Result<Value, RetlError> {
         trace!("interpret_dict_def: {:?}", exp);
         match &exp.exp {
             Expression::DictDef{mapping} => {
-                let expected_dict_type = type_conforms(&exp.exp_type, expected_type, &exp.token);
+                let expected_dict_type = conform(&exp.exp_type, expected_type, exp)?;
                 let (key_type, value_type): (Type, Type) = match expected_dict_type.clone() {
                     Type::DictType{key_type, value_type} => (*key_type, *value_type),
                     _ => (Type::UnknownType, Type::UnknownType)
                 };
                 let mut dict_values: Vec<(Value, Value)> = vec![];
-                let _ = mapping.iter().for_each(|value: &(Literal, Exp)| {
-                        let key = Exp {
-                            exp: Expression::Lit { lit: value.0.clone() },
-                            exp_type: key_type.clone(),
-                            token: exp.token.clone()
-                        };
-                        dict_values.push((self.interpret(&key, env, &key_type.clone()),
-                                          self.interpret(&value.1.clone(), env, &value_type.clone())));
-                    });
-                Value{
-                    value: Val::DictValue{values: dict_values},
-                    val_type: Type::DictType{key_type: Box::new(key_type), value_type: Box::new(value_type)}
+                for value in mapping.iter() {
+                    let key = Exp {
+                        exp: Expression::Lit { lit: value.0.clone() },
+                        exp_type: key_type.clone(),
+                        token: exp.token.clone()
+                    };
+                    dict_values.push((self.interpret(&key, env, &key_type.clone())?,
+                                      self.interpret(&value.1.clone(), env, &value_type.clone())?));
                 }
+                Ok(Value{
+                    value: Val::DictValue{values: Arc::new(dict_values)},
+                    val_type: Type::DictType{key_type: Box::new(key_type), value_type: Box::new(value_type)}
+                })
             },
             _ => invalid_exp_error(exp)
         }
     }
 
-    fn interpret_schema_def(&mut self, exp: &Exp, expected_type: &Type) -> Value {
+    fn interpret_schema_def(&mut self, exp: &Exp, env: &mut Env, expected_type: &Type) -> Result<Value, RetlError> {
         trace!("interpret_schema_def: {:?}", exp);
         match &exp.exp {
-            Expression::SchemaDef{mapping} => {
+            Expression::SchemaDef{mapping, constraints} => {
+                let has_checks = constraints.iter().any(|c| { matches!(c, Constraint::Check{..}) });
                 let schema_val = Value{
-                    value: Val::SchemaValue{values: mapping.clone()},
+                    value: Val::SchemaValue{
+                        values: mapping.clone(),
+                        constraints: constraints.clone(),
+                        env: if has_checks { env.snapshot() } else { Env::new() }
+                    },
                     val_type: Type::SchemaType{col_types: match &exp.exp_type {
                         Type::SchemaType{col_types} => col_types.clone(),
                         _ => vec![]
                     }}
                 };
-                type_conforms(&schema_val.val_type, expected_type, &exp.token);
-                schema_val
+                conform(&schema_val.val_type, expected_type, exp)?;
+                Ok(schema_val)
             },
             _ => invalid_exp_error(exp)
         }
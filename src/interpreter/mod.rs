@@ -1,2 +1,5 @@
 pub mod interpreter;
-pub mod value;
\ No newline at end of file
+pub mod env;
+pub mod value;
+pub mod retl_error;
+pub mod vm;
\ No newline at end of file
@@ -0,0 +1,100 @@
+use strum_macros::Display;
+
+use crate::defs::retl_type::Type;
+use crate::interpreter::value::{Value, Val};
+use crate::scanner::token::{Token, get_file_position};
+use crate::utils::diagnostic::{Diagnostic, Code};
+
+#[derive(Display, Debug, Clone, Eq, PartialEq)]
+pub enum ErrorKind {
+    #[strum(serialize = "type")]
+    Type,
+    #[strum(serialize = "reference")]
+    Reference,
+    #[strum(serialize = "index")]
+    Index,
+    #[strum(serialize = "key")]
+    Key,
+    #[strum(serialize = "match")]
+    Match,
+    #[strum(serialize = "arithmetic")]
+    Arithmetic,
+    #[strum(serialize = "conversion")]
+    Conversion,
+    #[strum(serialize = "io")]
+    Io,
+    #[strum(serialize = "runtime")]
+    Runtime,
+    #[strum(serialize = "assertion")]
+    Assertion,
+    #[strum(serialize = "validation")]
+    Validation,
+    // control flow signals, unwound to the enclosing loop or function rather than reported
+    #[strum(serialize = "break")]
+    Break,
+    #[strum(serialize = "continue")]
+    Continue,
+    #[strum(serialize = "return")]
+    Return{value: Box<Value>}
+}
+
+impl ErrorKind {
+    pub fn code(&self) -> Code {
+        match self {
+            ErrorKind::Type => Code::RuntimeType,
+            ErrorKind::Reference => Code::RuntimeReference,
+            ErrorKind::Index => Code::Index,
+            ErrorKind::Key => Code::Key,
+            ErrorKind::Match => Code::Match,
+            ErrorKind::Arithmetic => Code::Arithmetic,
+            ErrorKind::Conversion => Code::Conversion,
+            ErrorKind::Io => Code::Io,
+            ErrorKind::Assertion => Code::Assertion,
+            ErrorKind::Validation => Code::Validation,
+            ErrorKind::Runtime | ErrorKind::Break | ErrorKind::Continue | ErrorKind::Return{..} => Code::Runtime
+        }
+    }
+
+    /// Break, continue and return are not errors and cannot be caught
+    pub fn is_control_flow(&self) -> bool {
+        matches!(self, ErrorKind::Break | ErrorKind::Continue | ErrorKind::Return{..})
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RetlError {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub token: Box<Token> // boxed to keep results of the interpreter small
+}
+
+/// Type of the value bound by `catch`
+pub fn error_type() -> Type {
+    Type::RecordType{ident: "error".to_string(), fields: vec![
+        ("kind".to_string(), Type::StringType),
+        ("message".to_string(), Type::StringType),
+        ("line".to_string(), Type::IntType),
+        ("column".to_string(), Type::IntType)
+    ]}
+}
+
+impl RetlError {
+    pub fn log(&self) {
+        Diagnostic::error(self.kind.code(), &self.message, &self.token).emit()
+    }
+
+    pub fn to_value(&self) -> Value {
+        let fp = get_file_position(&self.token);
+        let string_value = |value: String| { Value{value: Val::StringValue{value: value.into()}, val_type: Type::StringType} };
+        let int_value = |value: usize| { Value{value: Val::IntValue{value: value as i32}, val_type: Type::IntType} };
+        Value{
+            value: Val::RecordValue{ident: "error".to_string(), fields: vec![
+                ("kind".to_string(), string_value(self.kind.to_string())),
+                ("message".to_string(), string_value(self.message.clone())),
+                ("line".to_string(), int_value(fp.line + 1)),
+                ("column".to_string(), int_value(fp.column + 1))
+            ]},
+            val_type: error_type()
+        }
+    }
+}
@@ -1,11 +1,10 @@
-use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use strum_macros::Display;
 
-use crate::defs::expression::Exp;
+use crate::defs::expression::{Body, Exp, Constraint};
 use crate::defs::keyword::Keyword;
 use crate::defs::retl_type::Type;
-
-pub type Env = HashMap<String, Value>;
+use crate::interpreter::env::Env;
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Value {
@@ -13,22 +12,254 @@ pub struct Value {
     pub val_type: Type
 }
 
+/// Contents of a var, behind a lock so values can be shared with the worker threads of pmap and pfilter
+#[derive(Debug)]
+pub struct VarCell {
+    value: Mutex<Value>
+}
+
+impl VarCell {
+    pub fn new(value: Value) -> VarCell {
+        VarCell{value: Mutex::new(value)}
+    }
+
+    pub fn get(&self) -> Value {
+        self.value.lock().unwrap_or_else(|e| { e.into_inner() }).clone()
+    }
+
+    pub fn set(&self, value: Value) {
+        *self.value.lock().unwrap_or_else(|e| { e.into_inner() }) = value
+    }
+}
+
+impl PartialEq for VarCell {
+    fn eq(&self, other: &VarCell) -> bool {
+        std::ptr::eq(self, other) || self.get() == other.get()
+    }
+}
+
+impl Eq for VarCell {}
+
+/// A step of a seq, run on each element only when the seq is consumed
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum SeqStage {
+    Map{func: Value},
+    Filter{func: Value},
+    Take{count: usize}
+}
+
+/// Strings and collections are reference counted and shared between values, cloning one is O(1) and a
+/// change builds a new one or copies on write with Arc::make_mut
 #[derive(Display, Debug, Clone, Eq, PartialEq)]
 pub enum Val {
     IntValue{value: i32},
     BoolValue{value: bool},
     CharValue{value: String},
-    StringValue{value: String},
+    StringValue{value: Arc<str>},
     NullValue,
-    ListValue{values: Vec<Value>},
-    TupleValue{values: Vec<Value>},
-    DictValue{values: Vec<(Value, Value)>},
-    SchemaValue{values: Vec<(String, Type)>},
+    ListValue{values: Arc<Vec<Value>>},
+    RangeValue{start: i32, end: i32, step: i32, inclusive: bool, char_range: bool},
+    SeqValue{source: Box<Value>, stages: Vec<SeqStage>}, // lazy, evaluated by whatever consumes it
+    TupleValue{values: Arc<Vec<Value>>},
+    DictValue{values: Arc<Vec<(Value, Value)>>},
+    SchemaValue{values: Vec<(String, Type)>, constraints: Vec<Constraint>, env: Env}, // env is only kept for check constraints
+    RecordValue{ident: String, fields: Vec<(String, Value)>},
+    EnumValue{ident: String, variant: String, values: Vec<Value>},
     FuncValue{
         builtin_ident: Option<Keyword>,
         parameters: Vec<(String, Type)>,
-        body: Exp,
-        env: Env
+        body: Arc<Body>,
+        env: Env,
+        group: Arc<Vec<(String, Exp)>> // named functions rebound on each call so they can recurse
     },
-    Error
+    VarValue{cell: Arc<VarCell>} // binding of a var, only ever stored in an Env
+}
+
+impl Val {
+    pub fn conforms(&self, t: &Type) -> bool {
+        match (self, t) {
+            (_, Type::Any) | (_, Type::UnknownType) | (_, Type::GenericType{..}) => true,
+            (_, Type::UnionType{union_types}) => union_types.iter().any(|ut| { self.conforms(ut) }),
+            (Val::IntValue{..}, Type::IntType) => true,
+            (Val::BoolValue{..}, Type::BoolType) => true,
+            (Val::CharValue{..}, Type::CharType) => true,
+            (Val::StringValue{..}, Type::StringType) => true,
+            (Val::NullValue, Type::NullType) => true,
+            (Val::ListValue{values}, Type::ListType{list_type}) => {
+                values.iter().all(|v| { v.value.conforms(list_type) })
+            },
+            (Val::RangeValue{char_range, ..}, Type::ListType{list_type}) => {
+                if *char_range { Val::CharValue{value: String::new()}.conforms(list_type) }
+                else { Val::IntValue{value: 0}.conforms(list_type) }
+            },
+            (Val::TupleValue{values}, Type::TupleType{tuple_types}) => {
+                values.len() == tuple_types.len() &&
+                    values.iter().zip(tuple_types).all(|(v, tt)| { v.value.conforms(tt) })
+            },
+            (Val::DictValue{values}, Type::DictType{key_type, value_type}) => {
+                values.iter().all(|(k, v)| { k.value.conforms(key_type) && v.value.conforms(value_type) })
+            },
+            (Val::SchemaValue{..}, Type::SchemaType{..}) => true,
+            (Val::RecordValue{ident, ..}, Type::RecordType{ident: record_ident, ..}) => ident == record_ident,
+            (Val::EnumValue{ident, ..}, Type::EnumType{ident: enum_ident, ..}) => ident == enum_ident,
+            (Val::FuncValue{..}, Type::FuncType{..}) => true,
+            (Val::SeqValue{..}, Type::SeqType{..}) => true,
+            (Val::VarValue{cell}, _) => cell.get().value.conforms(t),
+            _ => false
+        }
+    }
+
+    /// Number of elements in a range value
+    pub fn range_len(&self) -> usize {
+        match self {
+            Val::RangeValue{start, end, step, inclusive, ..} => {
+                let (distance, stride) = if *step > 0 {
+                    (*end as i64 - *start as i64, *step as i64)
+                } else {
+                    (*start as i64 - *end as i64, -(*step as i64))
+                };
+                let distance = if *inclusive { distance } else { distance - 1 };
+                if distance < 0 { 0 } else { (distance / stride + 1) as usize }
+            },
+            _ => 0
+        }
+    }
+
+    /// Element at an index of a range value, computed without materialising the range
+    pub fn range_element(&self, index: usize) -> Option<Value> {
+        match self {
+            Val::RangeValue{start, step, char_range, ..} if index < self.range_len() => {
+                let element = (*start as i64 + *step as i64 * index as i64) as i32; // in range as index < len
+                if *char_range {
+                    char::from_u32(element as u32).map(|c| {
+                        Value{value: Val::CharValue{value: c.to_string()}, val_type: Type::CharType}
+                    })
+                } else {
+                    Some(Value{value: Val::IntValue{value: element}, val_type: Type::IntType})
+                }
+            },
+            _ => None
+        }
+    }
+}
+
+/// Indices selected by a Python-style slice, negative bounds count from the end; the step must not be 0
+pub fn slice_indices(len: usize, start: Option<i32>, end: Option<i32>, step: i32) -> Vec<usize> {
+    let len = len as i64;
+    let resolve = |bound: i32, low: i64, high: i64| {
+        let bound = bound as i64;
+        (if bound < 0 { bound + len } else { bound }).clamp(low, high)
+    };
+    let mut indices: Vec<usize> = vec![];
+    if step > 0 {
+        let mut i = start.map(|s| { resolve(s, 0, len) }).unwrap_or(0);
+        let end = end.map(|e| { resolve(e, 0, len) }).unwrap_or(len);
+        while i < end {
+            indices.push(i as usize);
+            i += step as i64
+        }
+    } else {
+        let mut i = start.map(|s| { resolve(s, -1, len - 1) }).unwrap_or(len - 1);
+        let end = end.map(|e| { resolve(e, -1, len - 1) }).unwrap_or(-1);
+        while i > end {
+            indices.push(i as usize);
+            i += step as i64
+        }
+    }
+    indices
+}
+
+fn render_list(values: &[Value]) -> String {
+    values.iter().map(|v| { v.render_nested() }).collect::<Vec<String>>().join(", ")
+}
+
+impl Value {
+    /// Replaces a union type with the first member type the underlying value conforms to
+    pub fn narrow(&self) -> Value {
+        match &self.val_type {
+            Type::UnionType{union_types} => {
+                match union_types.iter().find(|ut| { self.value.conforms(ut) }) {
+                    Some(member_type) => Value{value: self.value.clone(), val_type: member_type.clone()},
+                    _ => self.clone()
+                }
+            },
+            _ => self.clone()
+        }
+    }
+
+    /// Value a var binding currently holds, other values are returned as they are
+    pub fn current(&self) -> Value {
+        match &self.value {
+            Val::VarValue{cell} => cell.get(),
+            _ => self.clone()
+        }
+    }
+
+    /// Expands a range value into the equivalent list value
+    pub fn materialize(&self) -> Value {
+        match &self.value {
+            Val::RangeValue{..} => Value{
+                value: Val::ListValue{
+                    values: Arc::new((0..self.value.range_len()).filter_map(|i| { self.value.range_element(i) }).collect())
+                },
+                val_type: self.val_type.clone()
+            },
+            _ => self.clone()
+        }
+    }
+
+    /// Gives a value bound to a union-typed declaration the declared union type
+    pub fn widen(self, declared_type: &Type) -> Value {
+        match declared_type {
+            Type::UnionType{..} if self.value.conforms(declared_type) =>
+                Value{value: self.value, val_type: declared_type.clone()},
+            _ => self
+        }
+    }
+
+    /// Text of a value as println and string interpolation show it
+    pub fn render(&self) -> String {
+        match &self.value {
+            Val::CharValue{value} => value.clone(),
+            Val::StringValue{value} => value.to_string(),
+            _ => self.render_nested()
+        }
+    }
+
+    /// Like render, but strings and chars inside collections keep their quotes
+    pub fn render_nested(&self) -> String {
+        match &self.value {
+            Val::IntValue{value} => value.to_string(),
+            Val::BoolValue{value} => value.to_string(),
+            Val::CharValue{value} => format!("'{}'", value),
+            Val::StringValue{value} => format!("\"{}\"", value),
+            Val::NullValue => "null".to_string(),
+            Val::ListValue{values} => format!("[{}]", render_list(values)),
+            Val::RangeValue{..} => self.materialize().render_nested(),
+            Val::TupleValue{values} => format!("({})", render_list(values)),
+            Val::DictValue{values} => {
+                let entries: Vec<String> = values.iter()
+                    .map(|(k, v)| { format!("{}: {}", k.render_nested(), v.render_nested()) })
+                    .collect();
+                format!("[{}]", entries.join(", "))
+            },
+            Val::SchemaValue{values, ..} => {
+                let columns: Vec<String> = values.iter()
+                    .map(|(ident, t)| { format!("{}: {}", ident, t.as_string()) })
+                    .collect();
+                format!("schema{{{}}}", columns.join(", "))
+            },
+            Val::RecordValue{ident, fields} => {
+                let fields: Vec<String> = fields.iter()
+                    .map(|(field, v)| { format!("{}: {}", field, v.render_nested()) })
+                    .collect();
+                format!("{}{{{}}}", ident, fields.join(", "))
+            },
+            Val::EnumValue{ident, variant, values} if values.is_empty() => format!("{}::{}", ident, variant),
+            Val::EnumValue{ident, variant, values} => format!("{}::{}({})", ident, variant, render_list(values)),
+            Val::FuncValue{..} => format!("<function {}>", self.val_type.as_string()),
+            Val::SeqValue{..} => format!("<{}>", self.val_type.as_string()),
+            Val::VarValue{cell} => cell.get().render_nested()
+        }
+    }
 }
\ No newline at end of file
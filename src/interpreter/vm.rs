@@ -0,0 +1,807 @@
+use std::ops::Range;
+use std::sync::Arc;
+use log::{debug, trace};
+use regex::Regex;
+
+use crate::defs::expression::{Exp, Expression, Pattern};
+use crate::defs::operator::Operator;
+use crate::defs::retl_type::{Type, type_conforms_no_error};
+use crate::scanner::token::{Token, get_file_position};
+use crate::interpreter::env::Env;
+use crate::interpreter::interpreter::{Interpreter, conform, element_index, error, func_value, invalid_exp_error,
+    list_value, literal_value, null_value, primitive, raise, range_value};
+use crate::interpreter::retl_error::{RetlError, ErrorKind};
+use crate::interpreter::value::{Value, Val, VarCell};
+
+/// Evaluator for function bodies, tree is the AST interpreter and stays the reference vm is checked against
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Engine {
+    Tree,
+    Vm
+}
+
+#[derive(Debug, Clone)]
+pub enum Instr {
+    Const{index: usize},
+    Load{slot: usize},
+    LoadGroup{index: usize}, // function of the fn group the body belongs to
+    LoadEnv{ident: String, site: usize}, // name bound outside the function
+    Store{slot: usize},
+    Bind{slot: usize, bind_type: Type, site: usize}, // store to an annotated let
+    NewVar{slot: usize, var_type: Type},
+    AssignSlot{slot: usize, ident: String, site: usize},
+    AssignEnv{ident: String, site: usize},
+    Pop,
+    Binary{operator: Operator, site: usize},
+    Jump{target: usize},
+    JumpUnless{target: usize, site: usize, message: &'static str},
+    Call{argc: usize, site: usize},
+    TupleGet{index: usize, site: usize},
+    FieldGet{field: String, site: usize},
+    MakeTuple{count: usize},
+    MakeList{count: usize, expected: Expected, site: usize},
+    MakeDict{count: usize, expected: Expected, site: usize}, // keys and values alternate on the stack
+    MakeRecord{ident: String, fields: Vec<String>, record_type: Type},
+    MakeEnum{ident: String, variant: String, count: usize, enum_type: Type},
+    MakeRange{inclusive: bool, stepped: bool, expected: Expected, site: usize},
+    Interpolate{count: usize},
+    Match{slot: usize, pattern: usize, binds: Vec<(String, usize)>, target: usize, site: usize}, // jumps to target unless it matches
+    Destructure{pattern: usize, binds: Vec<(String, usize)>, site: usize}, // raises unless it matches
+    NoMatch{site: usize},
+    Break{site: usize},
+    Continue{site: usize},
+    Return{site: usize}
+}
+
+impl Instr {
+    /// Change in stack height after the instruction runs
+    fn stack_effect(&self) -> isize {
+        match self {
+            Instr::Const{..} | Instr::Load{..} | Instr::LoadGroup{..} | Instr::LoadEnv{..} => 1,
+            Instr::Break{..} | Instr::Continue{..} | Instr::NoMatch{..} => 1, // stand in for the value of the expression they end
+            Instr::Store{..} | Instr::Bind{..} | Instr::NewVar{..} | Instr::Pop | Instr::Binary{..} |
+            Instr::JumpUnless{..} | Instr::Destructure{..} => -1,
+            Instr::Call{argc, ..} => -(*argc as isize),
+            Instr::MakeTuple{count} | Instr::Interpolate{count} | Instr::MakeList{count, ..} |
+            Instr::MakeEnum{count, ..} => 1 - *count as isize,
+            Instr::MakeDict{count, ..} => 1 - 2 * *count as isize,
+            Instr::MakeRecord{fields, ..} => 1 - fields.len() as isize,
+            Instr::MakeRange{stepped, ..} => if *stepped { -2 } else { -1 },
+            Instr::AssignSlot{..} | Instr::AssignEnv{..} | Instr::Jump{..} | Instr::TupleGet{..} | Instr::FieldGet{..} |
+            Instr::Match{..} | Instr::Return{..} => 0
+        }
+    }
+}
+
+/// Body of a while loop, a break or continue raised while running it resumes at exit or start
+#[derive(Debug)]
+struct Loop {
+    body: Range<usize>,
+    start: usize,
+    exit: usize,
+    depth: usize // stack height when the body starts
+}
+
+/// Bytecode of one function body, parameters and locals live in numbered slots instead of an Env
+#[derive(Debug, Default)]
+pub struct Chunk {
+    code: Vec<Instr>,
+    constants: Vec<Value>,
+    patterns: Vec<Pattern>,
+    sites: Vec<Exp>, // positions errors are reported at, with the type of the expression there
+    loops: Vec<Loop>, // innermost first
+    slot_count: usize
+}
+
+impl Chunk {
+    fn enclosing_loop(&self, pc: usize) -> Option<&Loop> {
+        self.loops.iter().find(|l| { l.body.contains(&pc) })
+    }
+}
+
+/// Type the tree interpreter evaluates an expression against, it decides the type of list, dict and range
+/// literals and is passed down to the expressions in tail position
+#[derive(Debug, Clone)]
+pub enum Expected {
+    Type(Type),
+    Return, // return type of the call running the chunk, generic functions resolve it per call
+    Part{outer: Box<Expected>, outer_type: Type, part: Part} // element of a dict or tuple literal expected as outer
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Part {
+    DictValue,
+    TupleElement{index: usize}
+}
+
+impl Expected {
+    fn unknown() -> Expected {
+        Expected::Type(Type::UnknownType)
+    }
+
+    /// Expected type of a part of a literal of outer_type, worked out now unless it depends on the call
+    fn part(&self, outer_type: &Type, part: Part, token: &Token) -> Expected {
+        match self {
+            Expected::Type(expected_type) => Expected::Type(part_type(outer_type, expected_type, part, token)),
+            _ => Expected::Part{outer: Box::new(self.clone()), outer_type: outer_type.clone(), part}
+        }
+    }
+}
+
+fn part_type(outer_type: &Type, expected_type: &Type, part: Part, token: &Token) -> Type {
+    match (type_conforms_no_error(outer_type, expected_type, token), part) {
+        (Type::DictType{value_type, ..}, Part::DictValue) => *value_type,
+        (Type::TupleType{tuple_types}, Part::TupleElement{index}) =>
+            tuple_types.get(index).cloned().unwrap_or(Type::UnknownType),
+        _ => Type::UnknownType
+    }
+}
+
+struct Compiler<'a> {
+    chunk: Chunk,
+    scope: Vec<(String, usize)>, // visible locals with their slots, innermost last
+    group: &'a [(String, Exp)],
+    depth: usize
+}
+
+/// Bytecode for a function body with the parameters in the first slots, None when the body needs the tree
+/// interpreter
+pub fn compile(body: &Exp, parameters: &[(String, Type)], group: &[(String, Exp)]) -> Option<Chunk> {
+    trace!("compile: {:?}", body);
+    let mut compiler = Compiler{chunk: Chunk::default(), scope: vec![], group, depth: 0};
+    for (ident, _) in parameters {
+        compiler.slot(ident);
+    }
+    compiler.compile_expecting(body, &Expected::Return)?;
+    let site = compiler.site(body);
+    compiler.emit(Instr::Return{site});
+    Some(compiler.chunk)
+}
+
+impl<'a> Compiler<'a> {
+    fn emit(&mut self, instr: Instr) -> usize {
+        self.depth = (self.depth as isize + instr.stack_effect()) as usize;
+        self.chunk.code.push(instr);
+        self.chunk.code.len() - 1
+    }
+
+    fn patch(&mut self, at: usize) {
+        let next = self.chunk.code.len();
+        if let Instr::Jump{target} | Instr::JumpUnless{target, ..} | Instr::Match{target, ..} = &mut self.chunk.code[at] {
+            *target = next
+        }
+    }
+
+    fn site(&mut self, exp: &Exp) -> usize {
+        self.chunk.sites.push(Exp{exp: Expression::Empty, exp_type: exp.exp_type.clone(), token: exp.token.clone()});
+        self.chunk.sites.len() - 1
+    }
+
+    fn constant(&mut self, value: Value) -> usize {
+        self.chunk.constants.push(value);
+        self.emit(Instr::Const{index: self.chunk.constants.len() - 1})
+    }
+
+    fn slot(&mut self, ident: &str) -> usize {
+        let slot = self.chunk.slot_count;
+        self.chunk.slot_count += 1;
+        self.scope.push((ident.to_string(), slot));
+        slot
+    }
+
+    fn local(&self, ident: &str) -> Option<usize> {
+        self.scope.iter().rev().find(|(local, _)| { local == ident }).map(|(_, slot)| { *slot })
+    }
+
+    /// Compiles an expression whose bindings end with it, like a branch or loop body
+    fn compile_scoped(&mut self, exp: &Exp, expected: &Expected) -> Option<()> {
+        let visible = self.scope.len();
+        self.compile_expecting(exp, expected)?;
+        self.scope.truncate(visible);
+        Some(())
+    }
+
+    fn compile_after(&mut self, after: &Option<Exp>, expected: &Expected) -> Option<()> {
+        match after {
+            Some(after) => self.compile_expecting(after, expected),
+            _ => {
+                self.constant(null_value());
+                Some(())
+            }
+        }
+    }
+
+    fn compile(&mut self, exp: &Exp) -> Option<()> {
+        self.compile_expecting(exp, &Expected::unknown())
+    }
+
+    fn compile_expecting(&mut self, exp: &Exp, expected: &Expected) -> Option<()> {
+        trace!("compile: {:?}", exp);
+        match &exp.exp {
+            Expression::Lit{lit} => {
+                self.constant(literal_value(lit));
+            },
+            Expression::Empty => {
+                self.constant(null_value());
+            },
+            Expression::Reference{ident} => {
+                let instr = match (self.local(ident), self.group.iter().position(|(member, _)| { member == ident })) {
+                    (Some(slot), _) => Instr::Load{slot},
+                    (_, Some(index)) => Instr::LoadGroup{index},
+                    _ => Instr::LoadEnv{ident: ident.clone(), site: self.site(exp)}
+                };
+                self.emit(instr);
+            },
+            Expression::Let{ident, let_type, let_exp, after_let_exp} => {
+                self.compile_expecting(let_exp, &Expected::Type(let_type.clone()))?;
+                let slot = self.slot(ident);
+                match let_type {
+                    Type::UnknownType => self.emit(Instr::Store{slot}),
+                    _ => {
+                        let site = self.site(let_exp);
+                        self.emit(Instr::Bind{slot, bind_type: let_type.clone(), site})
+                    }
+                };
+                self.compile_after(after_let_exp, expected)?
+            },
+            Expression::LetPattern{pattern, let_type, let_exp, after_let_exp} => {
+                self.compile_expecting(let_exp, &Expected::Type(let_type.clone()))?;
+                if *let_type != Type::UnknownType {
+                    let slot = self.slot("let$value");
+                    let site = self.site(let_exp);
+                    self.emit(Instr::Bind{slot, bind_type: let_type.clone(), site});
+                    self.emit(Instr::Load{slot});
+                }
+                let binds = self.pattern_slots(pattern);
+                self.chunk.patterns.push(*pattern.clone());
+                let site = self.site(exp);
+                self.emit(Instr::Destructure{pattern: self.chunk.patterns.len() - 1, binds, site});
+                self.compile_after(after_let_exp, expected)?
+            },
+            Expression::Var{ident, var_type, var_exp, after_var_exp} => {
+                self.compile_expecting(var_exp, &Expected::Type(var_type.clone()))?;
+                let slot = self.slot(ident);
+                self.emit(Instr::NewVar{slot, var_type: var_type.clone()});
+                self.compile_after(after_var_exp, expected)?
+            },
+            Expression::Assign{ident, value} => {
+                self.compile(value)?;
+                let site = self.site(exp);
+                match self.local(ident) {
+                    Some(slot) => self.emit(Instr::AssignSlot{slot, ident: ident.clone(), site}),
+                    _ => self.emit(Instr::AssignEnv{ident: ident.clone(), site})
+                };
+            },
+            Expression::Primitive{operator, left, right} => {
+                self.compile(left)?;
+                self.compile(right)?;
+                let site = self.site(exp);
+                self.emit(Instr::Binary{operator: operator.clone(), site});
+            },
+            Expression::Branch{condition, if_branch, else_branch} => {
+                self.compile(condition)?;
+                let site = self.site(exp);
+                let to_else = self.emit(Instr::JumpUnless{target: 0, site, message: "Invalid branch condition"});
+                self.compile_scoped(if_branch, expected)?;
+                let to_end = self.emit(Instr::Jump{target: 0});
+                self.depth -= 1; // the if branch value is not on the stack when the else branch starts
+                self.patch(to_else);
+                match &**else_branch {
+                    Some(else_exp) => self.compile_scoped(else_exp, expected)?,
+                    _ => {
+                        self.constant(null_value());
+                    }
+                }
+                self.patch(to_end)
+            },
+            Expression::While{condition, body} => {
+                let start = self.chunk.code.len();
+                self.compile(condition)?;
+                let site = self.site(exp);
+                let to_exit = self.emit(Instr::JumpUnless{target: 0, site, message: "Invalid loop condition"});
+                let body_start = self.chunk.code.len();
+                let depth = self.depth;
+                self.compile_scoped(body, &Expected::unknown())?;
+                self.emit(Instr::Pop);
+                self.emit(Instr::Jump{target: start});
+                let exit = self.chunk.code.len();
+                self.patch(to_exit);
+                self.constant(null_value());
+                self.chunk.loops.push(Loop{body: body_start..exit, start, exit, depth})
+            },
+            Expression::Break => {
+                let site = self.site(exp);
+                self.emit(Instr::Break{site});
+            },
+            Expression::Continue => {
+                let site = self.site(exp);
+                self.emit(Instr::Continue{site});
+            },
+            Expression::Return{value} => {
+                self.compile_after(value, &Expected::unknown())?;
+                let site = self.site(exp);
+                self.emit(Instr::Return{site});
+            },
+            Expression::Application{ident, args} => {
+                if args.iter().any(|arg| { matches!(arg.exp, Expression::Placeholder | Expression::Slice{..}) }) {
+                    return None
+                }
+                self.compile(ident)?;
+                for arg in args {
+                    self.compile(arg)?
+                }
+                let site = self.site(exp);
+                self.emit(Instr::Call{argc: args.len(), site});
+            },
+            Expression::TupleDef{values} => {
+                for (index, value) in values.iter().enumerate() {
+                    self.compile_expecting(value, &expected.part(&exp.exp_type, Part::TupleElement{index}, &exp.token))?
+                }
+                self.emit(Instr::MakeTuple{count: values.len()});
+            },
+            Expression::TupleAccess{ident, index} => {
+                self.compile_expecting(ident, &Expected::Type(exp.exp_type.clone()))?;
+                let site = self.site(exp);
+                self.emit(Instr::TupleGet{index: *index, site});
+            },
+            Expression::FieldAccess{ident, field} => {
+                self.compile_expecting(ident, &Expected::Type(exp.exp_type.clone()))?;
+                let site = self.site(exp);
+                self.emit(Instr::FieldGet{field: field.clone(), site});
+            },
+            Expression::Interpolation{parts} => {
+                for part in parts {
+                    self.compile(part)?
+                }
+                self.emit(Instr::Interpolate{count: parts.len()});
+            },
+            Expression::Match{match_exp, cases} => {
+                let visible = self.scope.len();
+                self.compile(match_exp)?;
+                let slot = self.slot("match$value");
+                self.emit(Instr::Store{slot});
+                let mut to_end = vec![];
+                for case in cases {
+                    let case_visible = self.scope.len();
+                    let binds = self.pattern_slots(&case.pattern);
+                    self.chunk.patterns.push(case.pattern.clone());
+                    let site = self.site(&case.case_exp);
+                    let to_next = self.emit(Instr::Match{slot, pattern: self.chunk.patterns.len() - 1, binds, target: 0, site});
+                    let guard_fails = match &case.guard {
+                        Some(guard) => {
+                            self.compile(guard)?;
+                            let site = self.site(guard);
+                            Some(self.emit(Instr::JumpUnless{target: 0, site, message: "Invalid guard"}))
+                        },
+                        _ => None
+                    };
+                    self.compile_expecting(&case.case_exp, expected)?;
+                    self.scope.truncate(case_visible);
+                    to_end.push(self.emit(Instr::Jump{target: 0}));
+                    self.depth -= 1; // the case value is not on the stack when the next case starts
+                    self.patch(to_next);
+                    if let Some(guard_fails) = guard_fails {
+                        self.patch(guard_fails)
+                    }
+                }
+                let site = self.site(exp);
+                self.emit(Instr::NoMatch{site});
+                for at in to_end {
+                    self.patch(at)
+                }
+                self.scope.truncate(visible)
+            },
+            Expression::ListDef{values} => {
+                for value in values {
+                    self.compile(value)?
+                }
+                let site = self.site(exp);
+                self.emit(Instr::MakeList{count: values.len(), expected: expected.clone(), site});
+            },
+            Expression::DictDef{mapping} => {
+                let value_expected = expected.part(&exp.exp_type, Part::DictValue, &exp.token);
+                for (key, value) in mapping {
+                    self.constant(literal_value(key));
+                    self.compile_expecting(value, &value_expected)?
+                }
+                let site = self.site(exp);
+                self.emit(Instr::MakeDict{count: mapping.len(), expected: expected.clone(), site});
+            },
+            Expression::RecordDef{ident, fields} => {
+                for (field, field_exp) in fields {
+                    let field_type = match &exp.exp_type {
+                        Type::RecordType{fields, ..} =>
+                            fields.iter().find(|ft| { ft.0 == *field }).map(|ft| { ft.1.clone() }),
+                        _ => None
+                    };
+                    self.compile_expecting(field_exp, &Expected::Type(field_type.unwrap_or(Type::UnknownType)))?
+                }
+                let fields = fields.iter().map(|(field, _)| { field.clone() }).collect();
+                self.emit(Instr::MakeRecord{ident: ident.clone(), fields, record_type: exp.exp_type.clone()});
+            },
+            Expression::EnumDef{ident, variant, values} => {
+                let variant_types = match &exp.exp_type {
+                    Type::EnumType{variants, ..} =>
+                        variants.iter().find(|v| { v.0 == *variant }).map(|v| { v.1.clone() }),
+                    _ => None
+                }.unwrap_or_default();
+                for (value, value_type) in values.iter().zip(variant_types) {
+                    self.compile_expecting(value, &Expected::Type(value_type))?
+                }
+                self.emit(Instr::MakeEnum{ident: ident.clone(), variant: variant.clone(), count: values.len(),
+                    enum_type: exp.exp_type.clone()});
+            },
+            Expression::RangeDef{start, end, step, inclusive} => {
+                self.compile(start)?;
+                self.compile(end)?;
+                if let Some(step) = &**step {
+                    self.compile(step)?
+                }
+                let site = self.site(exp);
+                self.emit(Instr::MakeRange{inclusive: *inclusive, stepped: step.is_some(), expected: expected.clone(),
+                    site});
+            },
+            _ => {
+                debug!("{} at line {} is not compiled, its function body runs on the tree interpreter", exp.exp,
+                    get_file_position(&exp.token).line + 1);
+                return None
+            }
+        }
+        Some(())
+    }
+
+    /// Slots for the names a pattern binds, visible until the scope they are made in ends
+    fn pattern_slots(&mut self, pattern: &Pattern) -> Vec<(String, usize)> {
+        let mut idents = vec![];
+        pattern_binds(pattern, &mut idents);
+        idents.into_iter().map(|ident| { let slot = self.slot(&ident); (ident, slot) }).collect()
+    }
+}
+
+/// Names a pattern binds when it matches, in the order they appear
+fn pattern_binds(pattern: &Pattern, binds: &mut Vec<String>) {
+    match pattern {
+        Pattern::TypePattern{ident, ..} | Pattern::Bind{ident} => binds.push(ident.clone()),
+        Pattern::Regex{regex} => if let Ok(compiled) = Regex::new(regex) {
+            binds.extend(compiled.capture_names().flatten().map(String::from))
+        },
+        Pattern::Variant{fields, ..} | Pattern::Tuple{fields} => for field in fields {
+            pattern_binds(field, binds)
+        },
+        Pattern::Record{fields, ..} => for (_, field) in fields {
+            pattern_binds(field, binds)
+        },
+        Pattern::Dict{entries} => for (_, entry) in entries {
+            pattern_binds(entry, binds)
+        },
+        Pattern::List{elems, rest} => {
+            for elem in elems {
+                pattern_binds(elem, binds)
+            }
+            if let Some(rest) = rest {
+                pattern_binds(rest, binds)
+            }
+        },
+        Pattern::Literal{..} | Pattern::MultiLiteral{..} | Pattern::Range{..} | Pattern::Any => ()
+    }
+}
+
+struct Vm<'a> {
+    interpreter: &'a mut Interpreter,
+    chunk: &'a Chunk,
+    env: &'a Env,
+    group: &'a Arc<Vec<(String, Exp)>>,
+    return_type: &'a Type,
+    slots: Vec<Value>,
+    stack: Vec<Value>,
+    pc: usize
+}
+
+/// Runs a compiled function body with the parameters bound to args, the result is checked against
+/// return_type once instead of at every expression
+pub fn run(interpreter: &mut Interpreter, chunk: &Chunk, args: Vec<Value>, env: &Env, group: &Arc<Vec<(String, Exp)>>,
+           return_type: &Type) -> Result<Value, RetlError> {
+    trace!("run: {:?}", args);
+    let mut slots = args;
+    slots.resize(chunk.slot_count, null_value());
+    let mut vm = Vm{interpreter, chunk, env, group, return_type, slots, stack: Vec::with_capacity(8), pc: 0};
+    loop {
+        match vm.step() {
+            Ok(Some(value)) => return Ok(value),
+            Ok(None) => (),
+            Err(e) => {
+                let resume = match (&e.kind, chunk.enclosing_loop(vm.pc - 1)) {
+                    (ErrorKind::Break, Some(l)) => Some((l.exit, l.depth)),
+                    (ErrorKind::Continue, Some(l)) => Some((l.start, l.depth)),
+                    _ => None
+                };
+                match resume {
+                    Some((target, depth)) => {
+                        vm.stack.truncate(depth);
+                        vm.pc = target
+                    },
+                    _ => return Err(e)
+                }
+            }
+        }
+    }
+}
+
+impl<'a> Vm<'a> {
+    fn pop(&mut self) -> Value {
+        self.stack.pop().unwrap_or_else(null_value)
+    }
+
+    fn pop_n(&mut self, count: usize) -> Vec<Value> {
+        self.stack.split_off(self.stack.len() - count)
+    }
+
+    /// Runs the instruction at pc, Some once the function returns
+    fn step(&mut self) -> Result<Option<Value>, RetlError> {
+        let chunk = self.chunk;
+        let instr = &chunk.code[self.pc];
+        self.pc += 1;
+        match instr {
+            Instr::Const{index} => self.stack.push(chunk.constants[*index].clone()),
+            Instr::Load{slot} => self.stack.push(self.slots[*slot].current()),
+            Instr::LoadGroup{index} => {
+                let func = func_value(&self.group[*index].1, self.env, self.group)?;
+                self.stack.push(func)
+            },
+            Instr::LoadEnv{ident, site} => match self.env.get(ident) {
+                Some(value) => self.stack.push(value.current()),
+                _ => return raise(ErrorKind::Reference, &("Reference \"".to_string() + ident + "\" does not exist"),
+                    &chunk.sites[*site]).map(|_| None)
+            },
+            Instr::Store{slot} => self.slots[*slot] = self.pop(),
+            Instr::Bind{slot, bind_type, site} => {
+                let value = self.pop();
+                conform(&value.val_type, bind_type, &chunk.sites[*site])?;
+                self.slots[*slot] = value.widen(bind_type)
+            },
+            Instr::NewVar{slot, var_type} => {
+                let value = self.pop().widen(var_type);
+                let declared_type = match var_type {
+                    Type::UnknownType => value.val_type.clone(),
+                    _ => var_type.clone()
+                };
+                self.slots[*slot] = Value{value: Val::VarValue{cell: Arc::new(VarCell::new(value))}, val_type: declared_type}
+            },
+            Instr::AssignSlot{slot, ident, site} => {
+                let value = self.pop();
+                assign(&self.slots[*slot], ident, value, &chunk.sites[*site])?;
+                self.stack.push(null_value())
+            },
+            Instr::AssignEnv{ident, site} => {
+                let value = self.pop();
+                match self.env.get(ident) {
+                    Some(target) => assign(target, ident, value, &chunk.sites[*site])?,
+                    _ => return raise(ErrorKind::Reference, &format!("Reference \"{}\" does not exist", ident),
+                        &chunk.sites[*site]).map(|_| None)
+                }
+                self.stack.push(null_value())
+            },
+            Instr::Pop => {
+                self.stack.pop();
+            },
+            Instr::Binary{operator, site} => {
+                let right = self.pop();
+                let left = self.pop();
+                let result = match (&left.value, &right.value) {
+                    (Val::IntValue{value: l}, Val::IntValue{value: r}) => int_op(operator, *l, *r),
+                    _ => None
+                };
+                let result = match result {
+                    Some(result) => result,
+                    _ => primitive(operator, left, right, &chunk.sites[*site])?
+                };
+                self.stack.push(result)
+            },
+            Instr::Jump{target} => self.pc = *target,
+            Instr::JumpUnless{target, site, message} => match self.pop() {
+                Value{value: Val::BoolValue{value}, ..} => if !value { self.pc = *target },
+                condition => {
+                    conform(&condition.val_type, &Type::BoolType, &chunk.sites[*site])?;
+                    return raise(ErrorKind::Type, message, &chunk.sites[*site]).map(|_| None)
+                }
+            },
+            Instr::Call{argc, site} => {
+                let args = self.pop_n(*argc);
+                let callee = self.pop();
+                let result = self.apply(callee, args, &chunk.sites[*site])?;
+                self.stack.push(result)
+            },
+            Instr::TupleGet{index, site} => match self.pop().value {
+                Val::TupleValue{values} => self.stack.push(values[*index].clone()),
+                _ => return raise(ErrorKind::Type, "Not a valid tuple value", &chunk.sites[*site]).map(|_| None)
+            },
+            Instr::FieldGet{field, site} => match self.pop().value {
+                Val::RecordValue{fields, ..} => match fields.into_iter().find(|f| { f.0 == *field }) {
+                    Some((_, field_value)) => self.stack.push(field_value),
+                    _ => return raise(ErrorKind::Reference, &("Field \"".to_string() + field + "\" does not exist"),
+                        &chunk.sites[*site]).map(|_| None)
+                },
+                _ => return raise(ErrorKind::Type, "Not a valid record value", &chunk.sites[*site]).map(|_| None)
+            },
+            Instr::MakeTuple{count} => {
+                let values = self.pop_n(*count);
+                let tuple_types = values.iter().map(|v| { v.val_type.clone() }).collect();
+                self.stack.push(Value{value: Val::TupleValue{values: Arc::new(values)}, val_type: Type::TupleType{tuple_types}})
+            },
+            Instr::MakeList{count, expected, site} => {
+                let values = self.pop_n(*count);
+                let expected_type = self.expected_type(expected, &chunk.sites[*site].token);
+                self.stack.push(list_value(values, &expected_type, &chunk.sites[*site])?)
+            },
+            Instr::MakeDict{count, expected, site} => {
+                let dict_exp = &chunk.sites[*site];
+                let expected_type = self.expected_type(expected, &dict_exp.token);
+                let dict_type = match conform(&dict_exp.exp_type, &expected_type, dict_exp)? {
+                    Type::DictType{key_type, value_type} => Type::DictType{key_type, value_type},
+                    _ => Type::DictType{key_type: Box::new(Type::UnknownType), value_type: Box::new(Type::UnknownType)}
+                };
+                let mut entries = self.pop_n(2 * *count).into_iter();
+                let mut values = Vec::with_capacity(*count);
+                while let (Some(key), Some(value)) = (entries.next(), entries.next()) {
+                    values.push((key, value))
+                }
+                self.stack.push(Value{value: Val::DictValue{values: Arc::new(values)}, val_type: dict_type})
+            },
+            Instr::MakeRecord{ident, fields, record_type} => {
+                let values = self.pop_n(fields.len());
+                let fields = fields.iter().cloned().zip(values).collect();
+                self.stack.push(Value{value: Val::RecordValue{ident: ident.clone(), fields},
+                    val_type: record_type.clone()})
+            },
+            Instr::MakeEnum{ident, variant, count, enum_type} => {
+                let values = self.pop_n(*count);
+                self.stack.push(Value{value: Val::EnumValue{ident: ident.clone(), variant: variant.clone(), values},
+                    val_type: enum_type.clone()})
+            },
+            Instr::MakeRange{inclusive, stepped, expected, site} => {
+                let step = if *stepped { Some(self.pop()) } else { None };
+                let end = self.pop();
+                let start = self.pop();
+                let expected_type = self.expected_type(expected, &chunk.sites[*site].token);
+                self.stack.push(range_value(start, end, step, *inclusive, &expected_type, &chunk.sites[*site])?)
+            },
+            Instr::Interpolate{count} => {
+                let value: String = self.pop_n(*count).iter().map(|part| { part.render() }).collect();
+                self.stack.push(Value{value: Val::StringValue{value: value.into()}, val_type: Type::StringType})
+            },
+            Instr::Match{slot, pattern, binds, target, site} => {
+                let mut bound = Env::new();
+                let value = self.slots[*slot].clone();
+                if self.interpreter.match_pattern(&chunk.patterns[*pattern], &value, &mut bound, &chunk.sites[*site].token) {
+                    for (ident, slot) in binds {
+                        if let Some(bound_value) = bound.get(ident) {
+                            self.slots[*slot] = bound_value.clone()
+                        }
+                    }
+                } else {
+                    self.pc = *target
+                }
+            },
+            Instr::Destructure{pattern, binds, site} => {
+                let mut bound = Env::new();
+                let value = self.pop();
+                let token = &chunk.sites[*site].token;
+                if !self.interpreter.match_pattern(&chunk.patterns[*pattern], &value, &mut bound, token) {
+                    return raise(ErrorKind::Match, &format!("Value of type {} does not match let pattern",
+                        value.val_type.as_string()), &chunk.sites[*site]).map(|_| None)
+                }
+                for (ident, slot) in binds {
+                    if let Some(bound_value) = bound.get(ident) {
+                        self.slots[*slot] = bound_value.clone()
+                    }
+                }
+            },
+            Instr::NoMatch{site} => return raise(ErrorKind::Match, "No patterns matched. Use the catch-all _ pattern",
+                &chunk.sites[*site]).map(|_| None),
+            Instr::Break{site} => return raise(ErrorKind::Break, "\"break\" outside of a loop", &chunk.sites[*site]).map(|_| None),
+            Instr::Continue{site} => return raise(ErrorKind::Continue, "\"continue\" outside of a loop", &chunk.sites[*site]).map(|_| None),
+            Instr::Return{site} => {
+                let value = self.pop();
+                conform(&value.val_type, self.return_type, &chunk.sites[*site])?;
+                return Ok(Some(value))
+            }
+        }
+        Ok(None)
+    }
+
+    fn expected_type(&self, expected: &Expected, token: &Token) -> Type {
+        match expected {
+            Expected::Type(expected_type) => expected_type.clone(),
+            Expected::Return => self.return_type.clone(),
+            Expected::Part{outer, outer_type, part} =>
+                part_type(outer_type, &self.expected_type(outer, token), *part, token)
+        }
+    }
+
+    /// Applies a function to evaluated arguments, or indexes a string, list, range or dict
+    fn apply(&mut self, callee: Value, mut args: Vec<Value>, exp: &Exp) -> Result<Value, RetlError> {
+        match &callee.value {
+            Val::FuncValue{parameters, ..} => {
+                if args.len() > parameters.len() || args.is_empty() && !parameters.is_empty() {
+                    error("Argument count does not match function parameter count", exp)
+                } else if args.len() < parameters.len() {
+                    self.interpreter.partial(&callee, args.into_iter().map(Some).collect(), exp)
+                } else {
+                    self.interpreter.call(&callee, args, exp)
+                }
+            },
+            Val::StringValue{value} => match args.as_slice() {
+                [Value{value: Val::IntValue{value: index}, ..}] => {
+                    match element_index(*index, value.chars().count()).and_then(|i| { value.chars().nth(i) }) {
+                        Some(char) => Ok(Value{value: Val::CharValue{value: char.to_string()}, val_type: Type::CharType}),
+                        _ => raise(ErrorKind::Index, "Invalid value for string indexing", exp)
+                    }
+                },
+                [_] => invalid_exp_error(exp),
+                _ => error("Argument count must be 1 for string indexing", exp)
+            },
+            Val::RangeValue{..} => match args.as_slice() {
+                [Value{value: Val::IntValue{value: index}, ..}] => {
+                    match element_index(*index, callee.value.range_len()).and_then(|i| { callee.value.range_element(i) }) {
+                        Some(element) => Ok(element),
+                        _ => raise(ErrorKind::Index, "Invalid value for list indexing", exp)
+                    }
+                },
+                [_] => raise(ErrorKind::Index, "Invalid value for list indexing", exp),
+                _ => error("Argument count must be 1 for list indexing", exp)
+            },
+            Val::ListValue{values} => match args.as_slice() {
+                [Value{value: Val::IntValue{value: index}, ..}] => {
+                    match element_index(*index, values.len()).and_then(|i| { values.get(i) }) {
+                        Some(element) => Ok(element.clone()),
+                        _ => raise(ErrorKind::Index, "Invalid value for list indexing", exp)
+                    }
+                },
+                [_] => invalid_exp_error(exp),
+                _ => error("Argument count must be 1 for list indexing", exp)
+            },
+            Val::DictValue{values} => {
+                if args.len() != 1 {
+                    return error("Argument count must be 1 for dictionary access", exp)
+                }
+                let key = args.remove(0);
+                match values.iter().find(|v| { v.0 == key }) {
+                    Some((_, value)) => Ok(value.clone()),
+                    _ => raise(ErrorKind::Key, "Key does not exist", exp)
+                }
+            },
+            _ => error("Expression invalid for application", exp)
+        }
+    }
+}
+
+/// Operators on two ints whose result fits in an int, anything else, overflow included, goes through
+/// primitive to be reported
+fn int_op(operator: &Operator, l: i32, r: i32) -> Option<Value> {
+    let int = |value: Option<i32>| { value.map(|value| { Value{value: Val::IntValue{value}, val_type: Type::IntType} }) };
+    let bool = |value: bool| { Some(Value{value: Val::BoolValue{value}, val_type: Type::BoolType}) };
+    match operator {
+        Operator::Plus => int(l.checked_add(r)),
+        Operator::Minus => int(l.checked_sub(r)),
+        Operator::Multiply => int(l.checked_mul(r)),
+        Operator::GreaterThan => bool(l > r),
+        Operator::LessThan => bool(l < r),
+        Operator::GreaterThanEqualTo => bool(l >= r),
+        Operator::LessThanEqualTo => bool(l <= r),
+        Operator::Equal => bool(l == r),
+        Operator::NotEqual => bool(l != r),
+        _ => None
+    }
+}
+
+/// Stores into the cell of a var binding, converting to its declared type
+fn assign(target: &Value, ident: &str, value: Value, exp: &Exp) -> Result<(), RetlError> {
+    match target {
+        Value{value: Val::VarValue{cell}, val_type} => {
+            conform(&value.val_type, val_type, exp)?;
+            cell.set(value.widen(val_type));
+            Ok(())
+        },
+        _ => raise(ErrorKind::Reference, &format!("Cannot assign to \"{}\", it is not a var", ident), exp).map(|_| ())
+    }
+}
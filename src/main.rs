@@ -4,19 +4,28 @@ mod defs;
 mod utils;
 mod interpreter;
 mod builtin;
+mod checker;
 
 use log::{error, trace};
 use std::error::Error;
+use std::fmt;
 use clap::Parser;
-use std::path::PathBuf;
-use std::{fs, io, panic};
+use std::path::{Path, PathBuf};
+use std::{fs, io, thread};
 use std::io::Write;
 use substring::Substring;
 use crate::builtin::builtin::Builtin;
+use crate::builtin::pool::Pool;
 
 use crate::scanner::scanner::Scanner;
 use crate::parser::parser::Parser as RetlParser;
-use crate::interpreter::interpreter::Interpreter;
+use crate::parser::module_loader::ModuleLoader;
+use crate::interpreter::interpreter::{Interpreter, STACK_SIZE};
+use crate::interpreter::retl_error::ErrorKind;
+use crate::interpreter::vm::Engine;
+use crate::utils::diagnostic::{Code, Diagnostic, DiagnosticFormat, Span, init_output, is_json_output};
+use crate::utils::file_position::FilePosition;
+use crate::checker::checker::Checker;
 
 use crate::defs::expression::Exp;
 use crate::defs::retl_type::Type;
@@ -36,13 +45,88 @@ struct RetlApp {
 
     /// Retl script file (optional)
     #[clap(long, short = 'f')]
-    file: Option<PathBuf>
+    file: Option<PathBuf>,
+
+    /// Directory searched for imported modules, may be repeated (optional)
+    #[clap(long = "include", short = 'I')]
+    include_paths: Vec<PathBuf>,
+
+    /// Format of error and warning output, json emits one object per line (optional)
+    #[clap(long = "error-format", value_enum, default_value_t = DiagnosticFormat::Human)]
+    error_format: DiagnosticFormat,
+
+    /// Evaluator for function bodies, vm runs compiled bytecode and tree the AST interpreter (optional)
+    #[clap(long, value_enum, default_value_t = Engine::Vm)]
+    engine: Engine,
+
+    /// Worker threads pmap and pfilter share, defaults to one less than the available cores (optional)
+    #[clap(long)]
+    workers: Option<usize>,
+
+    /// Script parameter as name=value, read with param or requireParam, may be repeated (optional)
+    #[clap(long = "param", short = 'p', value_parser = parse_param)]
+    params: Vec<(String, String)>,
+
+    /// Positional arguments for the script, read with args (optional)
+    args: Vec<String>
+}
+
+fn parse_param(param: &str) -> Result<(String, String), String> {
+    match param.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_string(), value.to_string())),
+        _ => Err(format!("expected name=value, got \"{}\"", param))
+    }
+}
+
+/// Stage a script failed in, used as the process exit code
+#[derive(Debug, Clone, Copy)]
+enum Stage {
+    Scan = 3,
+    Parse = 4,
+    Type = 5,
+    Runtime = 6,
+    Io = 7
+}
+
+#[derive(Debug)]
+struct StageError {
+    stage: Stage,
+    message: String
+}
+
+impl fmt::Display for StageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for StageError {}
+
+fn stage_error(stage: Stage, message: &str) -> Box<dyn Error> {
+    Box::new(StageError{stage, message: message.to_string()})
+}
+
+/// Errors without a stage come from reading files
+fn exit_code(e: &(dyn Error + 'static)) -> i32 {
+    match e.downcast_ref::<StageError>() {
+        Some(stage_error) => stage_error.stage as i32,
+        _ => Stage::Io as i32
+    }
+}
+
+/// Stage errors are reported as they happen, file errors only get a diagnostic of their own in json output
+fn report_file_error(e: &(dyn Error + 'static), file: &str) {
+    if e.downcast_ref::<StageError>().is_none() {
+        let fp = FilePosition{file: file.to_string(), line: 0, column: 0, line_text: "".to_string()};
+        Diagnostic::error_at(Code::Io, &e.to_string(), Span{fp, width: 0}).emit()
+    }
 }
 
 fn read_retl_file(path_buf: &PathBuf) -> Result<String, Box<dyn Error>> {
     let make_file_err = || -> Result<String, Box<dyn Error>> {
         let path = path_buf.display();
-        let err = format!("RETL script \"{path}\" requires extension \x1b[3m.retl\x1b[0m");
+        let ext = if is_json_output() { "\".retl\"" } else { "\x1b[3m.retl\x1b[0m" };
+        let err = format!("RETL script \"{path}\" requires extension {ext}");
         Err(err.into())
     };
 
@@ -55,49 +139,71 @@ fn read_retl_file(path_buf: &PathBuf) -> Result<String, Box<dyn Error>> {
     }
 }
 
-fn make_ast(script: &String) -> Result<Exp, Box<dyn Error>> {
-    let scanner = &mut Scanner::init();
+fn make_ast(script: &String, file: &str, loader: &mut ModuleLoader) -> Result<Exp, Box<dyn Error>> {
+    let scanner = &mut Scanner::init(file);
     scanner.scan(&script);
 
     if scanner.error {
         trace!("SCANNER ERROR");
-        return Err("One more errors occurred, exiting.".into())
+        return Err(stage_error(Stage::Scan, "One more errors occurred, exiting."))
     }
 
     let parser = &mut RetlParser::init();
+    parser.loader = std::mem::take(loader);
     parser.parse(&scanner.tokens);
+    *loader = std::mem::take(&mut parser.loader);
 
     if parser.error {
         trace!("PARSER ERROR");
-        Err("One more errors occurred, exiting.".into())
+        Err(stage_error(Stage::Parse, "One more errors occurred, exiting."))
     } else {
         Ok(parser.root_exp.clone())
     }
 }
 
-fn run_retl(script: &String) -> Result<(), Box<dyn Error>> {
-    let builtin = Builtin::init();
-    let mut env = builtin.load_builtins(&interpreter::value::Env::new());
-    let interpreter = &mut Interpreter::init(&builtin);
+fn check_ast(ast: &Exp, env: &interpreter::env::Env) -> Result<(), Box<dyn Error>> {
+    let checker = &mut Checker::init(env);
+    checker.check(ast);
+
+    if checker.errors.is_empty() {
+        Ok(())
+    } else {
+        trace!("CHECKER ERROR");
+        Err(stage_error(Stage::Type, &format!("{} type error(s) found, exiting.", checker.errors.len())))
+    }
+}
+
+fn run_retl(script: &String, path_buf: &Path, include_paths: &[PathBuf], builtin: Builtin, engine: Engine) -> Result<(), Box<dyn Error>> {
+    let mut env = builtin.load_builtins(&interpreter::env::Env::new());
+    let interpreter = &mut Interpreter::init(&builtin).with_engine(engine);
+    let loader = &mut ModuleLoader::init(path_buf.parent().unwrap_or(Path::new("")), include_paths);
+    loader.begin(&path_buf.canonicalize()?)?; // the script itself may not be imported back
+    let ast = make_ast(script, &path_buf.display().to_string(), loader)?;
+    check_ast(&ast, &env)?;
     let result = interpreter.interpret(
-        &make_ast(script)?,
+        &ast,
         &mut env,
         &Type::UnknownType
     );
 
-    if interpreter.error {
-        trace!("INTERPRETER ERROR");
-        Err("One more errors occurred, exiting.".into())
-    } else {
-        trace!("{:?}", result);
-        Ok(())
+    match result {
+        Err(e) => {
+            trace!("INTERPRETER ERROR");
+            e.log();
+            let stage = if e.kind == ErrorKind::Io { Stage::Io } else { Stage::Runtime };
+            Err(stage_error(stage, "One more errors occurred, exiting."))
+        },
+        Ok(value) => {
+            trace!("{:?}", value);
+            Ok(())
+        }
     }
 }
 
-fn run_retl_repl() -> Result<(), Box<dyn Error>> {
-    let builtin = Builtin::init();
-    let mut env = builtin.load_builtins(&interpreter::value::Env::new());
-    let interpreter = &mut Interpreter::init(&builtin);
+fn run_retl_repl(include_paths: &[PathBuf], builtin: Builtin, engine: Engine) -> Result<(), Box<dyn Error>> {
+    let loader = &mut ModuleLoader::init(&std::env::current_dir()?, include_paths);
+    let mut env = builtin.load_builtins(&interpreter::env::Env::new());
+    let interpreter = &mut Interpreter::init(&builtin).with_engine(engine);
     let mut repl_input = String::new();
     println!("Retl REPL\n=========");
     loop {
@@ -121,8 +227,15 @@ fn run_retl_repl() -> Result<(), Box<dyn Error>> {
             repl_input.push_str("\n")
         } else {
             repl_input.push_str(&*line);
-            let result = interpreter.interpret(&make_ast(&repl_input)?, &mut env, &Type::UnknownType);
-            trace!("{:?}", result);
+            let ast = make_ast(&repl_input, "", loader)?;
+            if let Err(e) = check_ast(&ast, &env) {
+                error!("{}", e.to_string())
+            } else {
+                match interpreter.interpret(&ast, &mut env, &Type::UnknownType) {
+                    Err(e) => e.log(),
+                    Ok(value) => trace!("{:?}", value)
+                }
+            }
             repl_input.clear()
         }
     }
@@ -146,20 +259,36 @@ fn main() {
             .format_target(false).format_timestamp(None).init();
     }
 
-    panic::catch_unwind(|| {
+    init_output(retl_args.error_format);
+    if let Some(workers) = retl_args.workers {
+        Pool::configure(workers)
+    }
+    let builtin = Builtin::init().with_script_args(retl_args.params.clone(), retl_args.args.clone());
+    // scripts run on a thread of their own so deep recursion has STACK_SIZE to grow into
+    let runner = thread::Builder::new().stack_size(STACK_SIZE).spawn(move || {
+        let file = retl_args.file.as_ref().map(|path_buf| { path_buf.display().to_string() }).unwrap_or_default();
         let result = match retl_args.file {
             Some(path_buf) => {
                 match read_retl_file(&path_buf) {
-                    Ok(script) => run_retl(&script),
+                    Ok(script) => run_retl(&script, &path_buf, &retl_args.include_paths, builtin, retl_args.engine),
                     Err(e) => Err(e)
                 }
             },
-            None => run_retl_repl()
+            None => run_retl_repl(&retl_args.include_paths, builtin, retl_args.engine)
         };
 
         match result {
-            Ok(_) => (),
-            Err(e) => error!("{}", e.to_string())
+            Ok(_) => 0,
+            Err(e) => {
+                if is_json_output() {
+                    report_file_error(e.as_ref(), &file)
+                } else {
+                    error!("{}", e.to_string())
+                }
+                exit_code(e.as_ref())
+            }
         }
-    }).expect("Fatal error occurred")
+    }).expect("Could not start the interpreter thread");
+    let code = runner.join().expect("Fatal error occurred");
+    std::process::exit(code)
 }
\ No newline at end of file
@@ -1 +1,2 @@
-pub mod parser;
\ No newline at end of file
+pub mod parser;
+pub mod module_loader;
@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use crate::defs::expression::Exp;
+use crate::defs::retl_type::Type;
+
+#[derive(Debug, Clone)]
+pub struct Module {
+    pub exp: Exp,
+    pub aliases: HashMap<String, Type>
+}
+
+/// Resolves import paths and caches parsed modules, shared by every parser of a script
+#[derive(Debug, Clone, Default)]
+pub struct ModuleLoader {
+    root_dir: PathBuf,
+    include_paths: Vec<PathBuf>,
+    loading: Vec<PathBuf>,
+    modules: HashMap<PathBuf, Module>
+}
+
+impl ModuleLoader {
+    pub fn init(root_dir: &Path, include_paths: &[PathBuf]) -> ModuleLoader {
+        let mut include_paths = include_paths.to_vec();
+        if let Some(retl_path) = env::var_os("RETL_PATH") {
+            include_paths.extend(env::split_paths(&retl_path))
+        }
+        ModuleLoader{
+            root_dir: root_dir.to_path_buf(),
+            include_paths,
+            loading: vec![],
+            modules: HashMap::new()
+        }
+    }
+
+    /// Searches the importing file's directory, then -I paths, then RETL_PATH
+    pub fn resolve(&self, import_path: &str) -> Result<PathBuf, Box<dyn Error>> {
+        let importer_dir = match self.loading.last() {
+            Some(importer) => importer.parent().map(|p| { p.to_path_buf() }).unwrap_or_default(),
+            _ => self.root_dir.clone()
+        };
+        std::iter::once(&importer_dir).chain(self.include_paths.iter())
+            .map(|dir| { dir.join(import_path) })
+            .find(|candidate| { candidate.is_file() })
+            .map(|found| { found.canonicalize().unwrap_or(found) })
+            .ok_or_else(|| { format!("Module \"{}\" not found in search paths", import_path).into() })
+    }
+
+    pub fn cached(&self, path: &Path) -> Option<Module> {
+        self.modules.get(path).cloned()
+    }
+
+    /// Marks a module as being loaded, failing if it is already part of the import chain
+    pub fn begin(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(start) = self.loading.iter().position(|p| { p == path }) {
+            let chain: Vec<String> = self.loading[start..].iter().chain(std::iter::once(&path.to_path_buf()))
+                .map(|p| { p.display().to_string() })
+                .collect();
+            return Err(format!("Import cycle detected: {}", chain.join(" -> ")).into())
+        }
+        self.loading.push(path.to_path_buf());
+        Ok(())
+    }
+
+    pub fn finish(&mut self, path: &Path, module: Module) {
+        self.loading.pop();
+        self.modules.insert(path.to_path_buf(), module);
+    }
+
+    pub fn abandon(&mut self) {
+        self.loading.pop();
+    }
+}
@@ -1,23 +1,37 @@
-use log::{debug, trace, error};
+use log::{debug, trace};
 use std::collections::HashMap;
-use substring::Substring;
+use std::sync::Arc;
+use regex::Regex;
 
-use crate::scanner::token::{Token, make_empty_token, get_fp_from_token};
+use crate::scanner::token::{Token, make_empty_token, get_file_position, offset_token};
+use crate::utils::diagnostic::{Diagnostic, Code, suggest};
 use crate::defs::keyword::Keyword;
 use crate::defs::delimiter::Delimiter;
-use crate::defs::expression::{Exp, Expression, Literal, Parameter, Case, Pattern};
+use crate::defs::expression::{Body, Exp, Expression, Literal, Parameter, Case, Pattern, Clause, Constraint};
 use crate::defs::expression::Literal::*;
 use crate::defs::operator::Operator;
-use crate::defs::retl_type::{Type, type_conforms_no_error};
+use crate::defs::retl_type::Type;
 use crate::defs::retl_type::Type::*;
+use crate::parser::module_loader::{Module, ModuleLoader};
+use crate::scanner::scanner::{Scanner, interpolation_end};
+
+/// Where recovery after a syntax error resumes parsing
+enum Sync {
+    Statement,
+    Block,
+    Case
+}
 
 pub struct Parser {
     pub error: bool,
+    panicking: bool, // inside a statement that already reported an error
     pub root_exp: Exp,
+    pub loader: ModuleLoader,
     tokens: Vec<Token>,
     index: usize,
     dummy_count: i32,
-    aliases: HashMap<String, Type>
+    aliases: HashMap<String, Type>,
+    type_params: Vec<String>
 }
 
 fn get_token_as_string(token: Token) -> String {
@@ -36,32 +50,50 @@ fn get_return_type(exp: &Option<Exp>) -> Type {
     }
 }
 
-fn make_list_def_range(start: usize, end: usize, token: &Token) -> Vec<Exp> {
-    let mut range: Vec<Exp> = vec![];
-    let mut index = start;
-    while index < end + 1 {
-        range.push(
-            Exp{
-                exp: Expression::Lit{lit: IntLit{literal: index as i32}},
-                exp_type: IntType,
-                token: token.clone()
-            }
-        );
-        index += 1;
+/// Character of the escape sequence following a backslash and the number of characters it takes up
+fn decode_escape(chars: &[char]) -> Option<(char, usize)> {
+    match chars.first()? {
+        'n' => Some(('\n', 1)),
+        't' => Some(('\t', 1)),
+        'r' => Some(('\r', 1)),
+        '0' => Some(('\0', 1)),
+        c @ ('\\' | '\"' | '\'' | '{' | '}') => Some((*c, 1)),
+        'u' if chars.get(1) == Some(&'{') => {
+            let close = chars.iter().position(|c| { *c == '}' })?;
+            let hex: String = chars[2..close].iter().collect();
+            let code = u32::from_str_radix(&hex, 16).ok()?;
+            char::from_u32(code).map(|c| { (c, close + 1) })
+        },
+        _ => None
+    }
+}
+
+/// Token for part of a literal token, so errors inside a string point at the right column
+fn literal_part_token(token: &Token, chars: &[char], index: usize, text: String) -> Token {
+    let mut fp = get_file_position(token);
+    match chars[..index].iter().rposition(|c| { *c == '\n' }) {
+        Some(newline) => { // lines after the first of a multi-line string start at column 0
+            fp.line += chars[..index].iter().filter(|c| { **c == '\n' }).count();
+            fp.column = index - newline - 1;
+            fp.line_text = chars[newline + 1..].iter().take_while(|c| { **c != '\n' }).collect();
+        },
+        None => fp.column += index
     }
-    range
+    Token::Value{value: text, fp}
 }
 
 impl Parser {
     pub fn init() -> Parser {
         Parser{
             error: false,
+            panicking: false,
             root_exp: Exp{
                 exp: Expression::Empty,
                 exp_type: NullType,
                 token: make_empty_token()
             },
-            tokens: vec![], index: 0, dummy_count: 0, aliases: HashMap::new()
+            loader: ModuleLoader::default(),
+            tokens: vec![], index: 0, dummy_count: 0, aliases: HashMap::new(), type_params: vec![]
         }
     }
 
@@ -76,6 +108,12 @@ impl Parser {
         }
     }
 
+    fn expected_expression(&mut self) -> Exp {
+        self.report(Code::InvalidSyntax, &format!("Expected expression, got {:?}", self.describe_curr()),
+            &self.curr_token());
+        self.make_empty_exp()
+    }
+
     fn curr(&self) -> Option<Token> {
         if self.index >= self.tokens.len() {
             trace!("curr: EMPTY");
@@ -90,23 +128,129 @@ impl Parser {
         self.index += 1
     }
 
+    fn report(&mut self, code: Code, msg: &str, token: &Token) {
+        self.report_diagnostic(Diagnostic::error(code, msg, token))
+    }
+
+    /// Reports an unknown or duplicate member, its tokens are well-formed so parsing goes on without recovery
+    fn report_member(&mut self, diagnostic: Diagnostic) {
+        let panicking = self.panicking;
+        self.report_diagnostic(diagnostic);
+        self.panicking = panicking
+    }
+
+    fn report_diagnostic(&mut self, diagnostic: Diagnostic) {
+        self.error = true;
+        if !self.panicking { // later errors in the same statement are usually caused by the first
+            self.panicking = true;
+            diagnostic.emit()
+        }
+    }
+
+    /// Token that errors at the end of the script point to
+    fn last_token(&self) -> Token {
+        self.tokens.last().cloned().unwrap_or_else(make_empty_token)
+    }
+
+    fn curr_token(&self) -> Token {
+        self.curr().unwrap_or_else(|| { self.last_token() })
+    }
+
+    fn describe_curr(&self) -> String {
+        match self.curr() {
+            Some(token) => get_token_as_string(token),
+            _ => String::from("end of file")
+        }
+    }
+
+    fn at_delimiter(&self, delim: Delimiter) -> bool {
+        matches!(self.curr(), Some(Token::Delimiter{delim: d, ..}) if d == delim)
+    }
+
+    fn at_keyword(&self, keyword: Keyword) -> bool {
+        matches!(self.curr(), Some(Token::Keyword{keyword: k, ..}) if k == keyword)
+    }
+
+    /// A literal followed by a colon starts a dict pattern, `[x: int]` stays a list of a type pattern
+    fn at_dict_key(&self) -> bool {
+        let literal = matches!(self.curr(), Some(Token::Value{..}) |
+            Some(Token::Keyword{keyword: Keyword::True | Keyword::False, ..}));
+        literal && matches!(self.tokens.get(self.index + 1), Some(Token::Delimiter{delim: Delimiter::DenoteType, ..}))
+    }
+
+    /// Skips the rest of a statement, block or case arm that has an error, nested brackets are skipped whole
+    fn synchronize(&mut self, sync: Sync) {
+        if !self.panicking {
+            return
+        }
+        let mut depth = 0;
+        while let Some(token) = self.curr() {
+            match (token, &sync) {
+                (Token::Delimiter{delim: Delimiter::ParenLeft | Delimiter::BracketLeft | Delimiter::BraceLeft, ..}, _) =>
+                    depth += 1,
+                (Token::Delimiter{delim: Delimiter::BraceRight, ..}, _) if depth == 0 => break,
+                (Token::Delimiter{delim: Delimiter::ParenRight | Delimiter::BracketRight | Delimiter::BraceRight, ..}, _) =>
+                    depth = std::cmp::max(depth - 1, 0),
+                (Token::Delimiter{delim: Delimiter::StatementEnd, ..}, Sync::Statement) if depth == 0 => break,
+                (Token::Keyword{keyword: Keyword::Case, ..}, Sync::Case) if depth == 0 => break,
+                (Token::Keyword{keyword: Keyword::Let | Keyword::Var | Keyword::Fn | Keyword::Import, ..}, Sync::Statement)
+                    if depth == 0 => break,
+                _ => ()
+            }
+            self.advance()
+        }
+        self.panicking = false
+    }
+
+    fn match_statement_end(&mut self) -> bool {
+        self.synchronize(Sync::Statement);
+        self.match_optional_delimiter(Delimiter::StatementEnd)
+    }
+
+    fn match_block_end(&mut self) -> bool {
+        if !self.at_delimiter(Delimiter::BraceRight) {
+            self.report(Code::ExpectedToken, &format!("Expected \"}}\", got {:?}", self.describe_curr()),
+                &self.curr_token());
+        }
+        self.synchronize(Sync::Block);
+        self.match_optional_delimiter(Delimiter::BraceRight)
+    }
+
+    /// Loop condition for comma separated items closed by `end`, stopping at the end of the script; every item
+    /// but the first needs a comma before it
+    fn match_list_item(&mut self, end: Delimiter, first: bool) -> bool {
+        if self.panicking { // an item failed to parse, recovery takes over from here
+            return false
+        }
+        if self.curr().is_none() {
+            self.report(Code::ExpectedToken, &format!("Expected {:?}, got \"end of file\"", end.to_string()),
+                &self.curr_token());
+            return false
+        }
+        if self.match_optional_delimiter(end.clone()) {
+            return false
+        }
+        if first || self.match_optional_delimiter(Delimiter::Comma) {
+            return true
+        }
+        self.report(Code::ExpectedToken, &format!("Expected \",\" or {:?}, got {:?}", end.to_string(), self.describe_curr()),
+            &self.curr_token());
+        false
+    }
+
     fn match_required_delimiter(&mut self, delim: Delimiter) -> bool {
         let matched = match self.curr() {
             Some(Token::Delimiter{delim: d, fp: _, .. }) if d == delim => true,
             _ => false
         };
 
-        if !matched {
-            self.error = true;
-            error!("Expected {:?}, got {:?}: {}",
-                delim.to_string(),
-                get_token_as_string(self.curr().unwrap().clone()).to_string(),
-                get_fp_from_token(&self.curr().unwrap()));
+        if !matched { // the token stays for recovery, it may be the end of the statement or block
+            self.report(Code::ExpectedToken, &format!("Expected {:?}, got {:?}",
+                delim.to_string(), self.describe_curr()), &self.curr_token());
         } else {
             trace!("MATCHED DELIM: curr {:?}, delim {:?}", self.curr(), delim);
+            self.advance()
         }
-
-        self.advance();
         matched
     }
 
@@ -117,11 +261,8 @@ impl Parser {
         };
 
         if !matched {
-            self.error = true;
-            error!("Expected {:?}, got {:?}: {}",
-                keyword.to_string(),
-                get_token_as_string(self.curr().unwrap().clone()),
-                get_fp_from_token(&self.curr().unwrap()))
+            self.report(Code::ExpectedToken, &format!("Expected {:?}, got {:?}",
+                keyword.to_string(), self.describe_curr()), &self.curr_token())
         } else {
             trace!("MATCHED KEYWORD: curr {:?}, delim {:?}", self.curr(), keyword);
         }
@@ -166,10 +307,8 @@ impl Parser {
                 ident
             },
             _ => {
-                self.error = true;
-                error!("Expected identifier, got {:?}: {}",
-                    get_token_as_string(self.curr().unwrap()),
-                    get_fp_from_token(&self.curr().unwrap()));
+                self.report(Code::ExpectedToken, &format!("Expected identifier, got {:?}",
+                    self.describe_curr()), &self.curr_token());
                 String::from("")
             }
         }
@@ -198,53 +337,25 @@ impl Parser {
         match exp.exp {
             Expression::Lit{lit} if lit != NullLit => lit,
             _ => {
-                self.error = true;
-                error!("Expected non-null literal value: {:?}", get_fp_from_token(&exp.token));
+                self.report(Code::InvalidSyntax, "Expected non-null literal value", &exp.token);
                 NullLit
             }
         }
     }
 
-    fn get_int_literal_value(&mut self, exp: Expression) -> usize {
-        match exp {
-            Expression::Lit{lit} => {
-                match lit {
-                    IntLit{literal} => {
-                        if literal < 0 {
-                            self.error = true;
-                            error!("Range requires positive integer value: {}",
-                                get_fp_from_token(&self.curr().unwrap()));
-                            0
-                        } else {
-                            literal as usize
-                        }
-                    },
-                    _ => {
-                        self.error = true;
-                        error!("Range requires positive integer value: {}",
-                            get_fp_from_token(&self.curr().unwrap()));
-                        0
-                    }
-                }
-            },
-            _ => {
-                self.error = true;
-                error!("Range requires positive integer value: {}",
-                    get_fp_from_token(&self.curr().unwrap()));
-                0
-            }
-        }
-    }
-
     pub fn parse(&mut self, tokens: &Vec<Token>) {
         self.tokens = tokens.clone();
         self.root_exp = self.parse_expression();
-        match self.curr() {
-            Some(_) =>  {
-                error!("\';\' missing, rest of file cannot be parsed: {}",
-                    get_fp_from_token(&self.curr().unwrap()));
-            },
-            _ => ()
+        while self.curr().is_some() { // keep parsing after the error only to report the rest of the script's errors
+            if self.at_delimiter(Delimiter::BraceRight) {
+                self.report(Code::InvalidSyntax, "Unexpected \"}\" without an opening \"{\"", &self.curr_token());
+            } else {
+                self.report(Code::MissingStatementEnd, "\';\' missing", &self.curr_token());
+            }
+            self.advance();
+            self.synchronize(Sync::Statement);
+            self.match_optional_delimiter(Delimiter::StatementEnd);
+            self.parse_expression();
         }
         debug!("ROOT EXPRESSION\n====================\n{:#?}\n====================\n", self.root_exp)
     }
@@ -255,9 +366,19 @@ impl Parser {
             Some(Token::Keyword{..})
                 if self.match_optional_keyword(Keyword::Let)
                     => self.parse_let(),
+            Some(Token::Keyword{..})
+                if self.match_optional_keyword(Keyword::Var)
+                    => self.parse_var(),
+            Some(Token::Keyword{..})
+                if self.match_optional_keyword(Keyword::Fn)
+                    => self.parse_fn_group(),
+            Some(Token::Keyword{..})
+                if self.match_optional_keyword(Keyword::Import)
+                    => self.parse_import(),
+            Some(Token::Delimiter{delim: Delimiter::BraceRight, ..}) => self.make_empty_exp(), // empty block
             Some(_) => {
                 let smp = self.parse_simple_expression();
-                if self.match_optional_delimiter(Delimiter::StatementEnd) {
+                if self.match_statement_end() {
                     let token = smp.token.clone();
                     let dummy_ident = self.dummy();
                     let let_type = get_return_type(&Some(smp.clone()));
@@ -282,8 +403,16 @@ impl Parser {
 
     fn parse_let(&mut self) -> Exp {
         trace!("parse_let");
-        let token = self.curr().unwrap();
-        let ident = self.match_ident();
+        let token = self.curr_token();
+        let pattern = if self.is_destructuring_pattern() {
+            Some(self.parse_pattern())
+        } else {
+            None
+        };
+        let ident = match pattern {
+            Some(_) => String::new(),
+            _ => self.match_ident()
+        };
 
         let mut let_type = UnknownType;
         if self.match_optional_delimiter(Delimiter::DenoteType) {
@@ -294,81 +423,329 @@ impl Parser {
         let let_exp = self.parse_simple_expression();
 
         let mut after_let_exp: Option<Exp> = None;
-        if self.match_optional_delimiter(Delimiter::StatementEnd) {
+        if self.match_statement_end() {
             after_let_exp = Some(self.parse_expression())
         }
         let exp_type = get_return_type(&after_let_exp);
 
         Exp{
-            exp: Expression::Let{
+            exp: match pattern {
+                Some(pattern) => Expression::LetPattern{
+                    pattern: Box::new(pattern),
+                    let_type,
+                    let_exp: Box::new(let_exp),
+                    after_let_exp: Box::new(after_let_exp)
+                },
+                _ => Expression::Let{
+                    ident,
+                    let_type,
+                    let_exp: Box::new(let_exp),
+                    after_let_exp: Box::new(after_let_exp)
+                }
+            },
+            exp_type,
+            token
+        }
+    }
+
+    fn parse_var(&mut self) -> Exp {
+        trace!("parse_var");
+        let token = self.curr_token();
+        let ident = self.match_ident();
+        let mut var_type = UnknownType;
+        if self.match_optional_delimiter(Delimiter::DenoteType) {
+            var_type = self.parse_type()
+        }
+
+        self.match_required_delimiter(Delimiter::Assignment);
+        let var_exp = self.parse_simple_expression();
+
+        let mut after_var_exp: Option<Exp> = None;
+        if self.match_statement_end() {
+            after_var_exp = Some(self.parse_expression())
+        }
+        let exp_type = get_return_type(&after_var_exp);
+
+        Exp{
+            exp: Expression::Var{
                 ident,
-                let_type,
-                let_exp: Box::new(let_exp),
-                after_let_exp: Box::new(after_let_exp)
+                var_type,
+                var_exp: Box::new(var_exp),
+                after_var_exp: Box::new(after_var_exp)
+            },
+            exp_type,
+            token
+        }
+    }
+
+    fn parse_assign(&mut self) -> Exp {
+        trace!("parse_assign");
+        let token = self.curr_token();
+        let ident = self.match_ident();
+        self.match_required_delimiter(Delimiter::Assignment);
+        let value = self.parse_simple_expression();
+        Exp{
+            exp: Expression::Assign{ident, value: Box::new(value)},
+            exp_type: NullType,
+            token
+        }
+    }
+
+    fn parse_import(&mut self) -> Exp {
+        trace!("parse_import");
+        let token = self.curr_token();
+        let mut items: Option<Vec<String>> = None;
+        if self.match_optional_delimiter(Delimiter::BraceLeft) {
+            let mut selected: Vec<String> = vec![];
+            while self.match_list_item(Delimiter::BraceRight, selected.is_empty()) {
+                selected.push(self.match_ident())
+            }
+            self.match_required_keyword(Keyword::From);
+            items = Some(selected)
+        }
+
+        let path_token = self.curr_token();
+        let import_path = match self.parse_literal().exp {
+            Expression::Lit{lit: StringLit{literal}} => literal,
+            _ => {
+                self.report(Code::ImportFailed, "Import requires a string literal path", &path_token);
+                String::new()
+            }
+        };
+        let namespace = match items {
+            None if self.match_optional_keyword(Keyword::As) => Some(self.match_ident()),
+            _ => None
+        };
+
+        let (path, module) = match self.load_module(&import_path, &path_token) {
+            Some((path, module)) => {
+                for (ident, alias) in module.aliases.iter() {
+                    if items.as_ref().is_none_or(|selected| { selected.contains(ident) }) {
+                        self.aliases.insert(ident.clone(), alias.clone());
+                    }
+                }
+                (path, module.exp)
+            },
+            _ => (import_path, self.make_empty_exp())
+        };
+        let aliases = &self.aliases;
+        let items = items.map(|selected| { // type aliases are resolved here, only values remain
+            selected.into_iter().filter(|ident| { !aliases.contains_key(ident) }).collect()
+        });
+
+        let mut after_import_exp: Option<Exp> = None;
+        if self.match_statement_end() {
+            after_import_exp = Some(self.parse_expression())
+        }
+        let exp_type = get_return_type(&after_import_exp);
+
+        Exp{
+            exp: Expression::Import{
+                path,
+                module: Box::new(module),
+                items,
+                namespace,
+                after_import_exp: Box::new(after_import_exp)
             },
             exp_type,
             token
         }
     }
 
+    fn load_module(&mut self, import_path: &str, token: &Token) -> Option<(String, Module)> {
+        trace!("load_module: {}", import_path);
+        let path = match self.loader.resolve(import_path) {
+            Ok(path) => path,
+            Err(e) => {
+                self.report(Code::ImportFailed, &e.to_string(), token);
+                return None
+            }
+        };
+        let path_string = path.display().to_string();
+        if let Some(module) = self.loader.cached(&path) {
+            return Some((path_string, module))
+        }
+        if let Err(e) = self.loader.begin(&path) {
+            self.report(Code::ImportFailed, &e.to_string(), token);
+            return None
+        }
+
+        let script = match crate::read_retl_file(&path) {
+            Ok(script) => script,
+            Err(e) => {
+                self.loader.abandon();
+                self.report(Code::ImportFailed, &e.to_string(), token);
+                return None
+            }
+        };
+        let scanner = &mut Scanner::init(&path_string);
+        scanner.scan(&script);
+        let module_parser = &mut Parser::init();
+        module_parser.loader = std::mem::take(&mut self.loader);
+        if !scanner.error {
+            module_parser.parse(&scanner.tokens);
+        }
+        self.loader = std::mem::take(&mut module_parser.loader);
+
+        if scanner.error || module_parser.error {
+            self.loader.abandon();
+            self.report(Code::ImportFailed, &format!("Module \"{}\" contains errors", path_string), token);
+            None
+        } else {
+            let module = Module{exp: module_parser.root_exp.clone(), aliases: module_parser.aliases.clone()};
+            self.loader.finish(&path, module.clone());
+            Some((path_string, module))
+        }
+    }
+
+    fn is_destructuring_pattern(&self) -> bool {
+        match self.curr() {
+            Some(Token::Delimiter{delim: Delimiter::ParenLeft, ..}) |
+            Some(Token::Delimiter{delim: Delimiter::BracketLeft, ..}) => true,
+            Some(Token::Ident{ident, ..}) => match self.aliases.get(&ident) {
+                Some(RecordType{..}) => true,
+                _ => false
+            },
+            _ => false
+        }
+    }
+
     fn parse_simple_expression(&mut self) -> Exp {
         trace!("parse_simple_expression");
         match self.curr() {
+            Some(Token::Ident{..})
+                if matches!(self.tokens.get(self.index + 1), Some(Token::Delimiter{delim: Delimiter::Assignment, ..}))
+                    => self.parse_assign(),
             Some(Token::Keyword{..})
                 if self.match_optional_keyword(Keyword::If)
                     => self.parse_branch(),
             Some(Token::Keyword{..})
                 if self.match_optional_keyword(Keyword::Iter)
                     => self.parse_iter(),
+            Some(Token::Keyword{..})
+                if self.match_optional_keyword(Keyword::While)
+                    => self.parse_while(),
+            Some(Token::Keyword{..})
+                if self.match_optional_keyword(Keyword::For)
+                    => self.parse_for(),
+            Some(Token::Keyword{keyword: keyword @ (Keyword::Break | Keyword::Continue), ..}) => {
+                let token = self.curr_token();
+                self.advance();
+                Exp{
+                    exp: if keyword == Keyword::Break { Expression::Break } else { Expression::Continue },
+                    exp_type: UnknownType, // never produces a value, so it fits wherever it is used
+                    token
+                }
+            },
+            Some(Token::Keyword{..})
+                if self.match_optional_keyword(Keyword::Return)
+                    => self.parse_return(),
             Some(Token::Delimiter{..})
                 if self.match_optional_delimiter(Delimiter::BracketLeft) => {
-                let first_collection = self.parse_collection_def();
+                let token = self.curr_token();
+                let collection = self.parse_collection_def();
+                let first_collection = self.parse_application_args(collection, token.clone());
                 if self.match_optional_delimiter(Delimiter::ListConcat) {
                     let second_collection = self.parse_tight();
+                    let concat_type = Operator::CollectionConcat
+                        .result_type(&first_collection.exp_type, &second_collection.exp_type)
+                        .unwrap_or(UnknownType);
                     Exp{
                         exp: Expression::Primitive{
                             operator: Operator::CollectionConcat,
                             left: Box::new(first_collection),
                             right: Box::new(second_collection)
                         },
-                        exp_type: IntType,
-                        token: self.curr().unwrap().clone()
+                        exp_type: concat_type,
+                        token: self.curr_token()
                     }
                 } else {
-                    first_collection
+                    let operation = self.parse_binary_ops(first_collection, 0, token);
+                    self.parse_range(operation)
                 }
             },
             Some(Token::Delimiter{..})
                 if self.match_optional_delimiter(Delimiter::ParenLeft)
-                    => self.parse_tuple_def_or_simple_expression(),
+                    => {
+                let token = self.curr_token();
+                let exp = self.parse_tuple_def_or_simple_expression();
+                self.match_required_delimiter(Delimiter::ParenRight);
+                let app = self.parse_application_args(exp, token.clone());
+                let operation = self.parse_binary_ops(app, 0, token);
+                self.parse_range(operation)
+            },
             Some(Token::Delimiter{..})
                 if self.match_optional_delimiter(Delimiter::SchemaStart)
                     => self.parse_schema_def(),
             Some(Token::Keyword{..})
                 if self.match_optional_keyword(Keyword::Match)
                     => self.parse_match(),
+            Some(Token::Keyword{..})
+                if self.match_optional_keyword(Keyword::Try)
+                    => self.parse_try(),
             Some(Token::Delimiter{..})
                 if self.match_optional_delimiter(Delimiter::LambdaSig)
                     => self.parse_lambda(),
             Some(Token::Keyword{..})
                 if self.match_optional_keyword(Keyword::Alias)
                     => self.parse_alias(),
-            Some(_) => self.parse_utight_with_min(0),
-            _ => self.make_empty_exp()
+            Some(Token::Keyword{..})
+                if self.match_optional_keyword(Keyword::Record)
+                    => self.parse_record(),
+            Some(Token::Keyword{..})
+                if self.match_optional_keyword(Keyword::Enum)
+                    => self.parse_enum(),
+            Some(_) => {
+                let utight = self.parse_utight_with_min(0);
+                self.parse_range(utight)
+            },
+            _ => self.expected_expression()
+        }
+    }
+
+    fn parse_range(&mut self, start: Exp) -> Exp {
+        trace!("parse_range");
+        if !self.match_optional_delimiter(Delimiter::Range) {
+            return start
+        }
+        let inclusive = !self.match_optional_delimiter(Delimiter::LessThan);
+        let end = self.parse_utight_with_min(0);
+        let mut step: Option<Exp> = None;
+        if self.match_optional_keyword(Keyword::Step) {
+            step = Some(self.parse_utight_with_min(0))
+        }
+        let range_type = match start.exp_type {
+            CharType => CharType,
+            _ => IntType
+        };
+        let token = start.token.clone();
+        Exp{
+            exp: Expression::RangeDef{
+                start: Box::new(start),
+                end: Box::new(end),
+                step: Box::new(step),
+                inclusive
+            },
+            exp_type: ListType{list_type: Box::new(range_type)},
+            token
         }
     }
 
     fn parse_alias(&mut self) -> Exp {
         trace!("parse_alias");
-        let token = self.curr().unwrap();
+        let token = self.curr_token();
         let ident = self.match_ident();
         self.match_required_delimiter(Delimiter::Assignment);
         let alias = self.parse_type();
+        self.parse_type_definition(ident, alias, token)
+    }
 
+    fn parse_type_definition(&mut self, ident: String, alias: Type, token: Token) -> Exp {
+        trace!("parse_type_definition");
         self.aliases.insert(ident.clone(), alias.clone());
 
         let mut after_alias_exp: Option<Exp> = None;
-        if self.match_optional_delimiter(Delimiter::StatementEnd) {
+        if self.match_statement_end() {
             after_alias_exp = Some(self.parse_expression())
         }
         let exp_type = get_return_type(&after_alias_exp);
@@ -384,16 +761,76 @@ impl Parser {
         }
     }
 
+    fn parse_record(&mut self) -> Exp {
+        trace!("parse_record");
+        let token = self.curr_token();
+        let ident = self.match_ident();
+        self.aliases.insert(ident.clone(), RecordType{ident: ident.clone(), fields: vec![]});
+        self.match_required_delimiter(Delimiter::BraceLeft);
+        let mut fields: Vec<(String, Type)> = vec![];
+
+        while self.match_list_item(Delimiter::BraceRight, fields.is_empty()) {
+            let field_token = self.curr_token();
+            let field = self.match_ident();
+            self.match_required_delimiter(Delimiter::DenoteType);
+            let field_type = self.parse_type();
+            if fields.iter().any(|f| f.0 == field) {
+                self.report_member(Diagnostic::error(Code::DuplicateMember,
+                    &format!("Field \"{}\" declared more than once", field), &field_token));
+            }
+            fields.push((field, field_type));
+        }
+
+        let record_type = RecordType{ident: ident.clone(), fields};
+        self.parse_type_definition(ident, record_type, token)
+    }
+
+    fn parse_enum(&mut self) -> Exp {
+        trace!("parse_enum");
+        let token = self.curr_token();
+        let ident = self.match_ident();
+        self.aliases.insert(ident.clone(), EnumType{ident: ident.clone(), variants: vec![]});
+        self.match_required_delimiter(Delimiter::BraceLeft);
+        let mut variants: Vec<(String, Vec<Type>)> = vec![];
+
+        while self.match_list_item(Delimiter::BraceRight, variants.is_empty()) {
+            let variant_token = self.curr_token();
+            let variant = self.match_ident();
+            let mut variant_types: Vec<Type> = vec![];
+            if self.match_optional_delimiter(Delimiter::ParenLeft) {
+                variant_types.push(self.parse_type());
+                while self.match_optional_delimiter(Delimiter::Comma) {
+                    variant_types.push(self.parse_type())
+                }
+                self.match_required_delimiter(Delimiter::ParenRight);
+            }
+            if variants.iter().any(|v| v.0 == variant) {
+                self.report_member(Diagnostic::error(Code::DuplicateMember,
+                    &format!("Variant \"{}\" declared more than once", variant), &variant_token));
+            }
+            variants.push((variant, variant_types));
+        }
+
+        let enum_type = EnumType{ident: ident.clone(), variants};
+        self.parse_type_definition(ident, enum_type, token)
+    }
+
     fn parse_utight_with_min(&mut self, min: i32) -> Exp {
         trace!("parse_utight_with_min");
-        let token = self.curr().unwrap();
-        let mut left = self.parse_utight();
+        let token = self.curr_token();
+        let left = self.parse_utight();
+        self.parse_binary_ops(left, min, token)
+    }
+
+    /// Binary operators following an already parsed left operand
+    fn parse_binary_ops(&mut self, mut left: Exp, min: i32, token: Token) -> Exp {
+        trace!("parse_binary_ops");
         while self.is_binary_op(min) {
-            let operator = self.curr().unwrap().clone().to_operator().unwrap();
+            let operator = self.curr_token().to_operator().unwrap();
             let temp_min = operator.get_precedence() + 1;
             self.advance();
             let right = self.parse_utight_with_min(temp_min);
-            let operator_type = type_conforms_no_error(&left.exp_type, &right.exp_type, &token);
+            let operator_type = operator.result_type(&left.exp_type, &right.exp_type).unwrap_or(UnknownType);
             left = Exp{
                 exp: Expression::Primitive{
                     operator,
@@ -408,7 +845,7 @@ impl Parser {
 
     fn parse_utight(&mut self) -> Exp {
         trace!("parse_utight");
-        let token = self.curr().unwrap();
+        let token = self.curr_token();
         let mut operator: Option<Operator> = None;
         if self.match_optional_keyword(Keyword::Not) {
             operator = Some(Operator::Not)
@@ -452,21 +889,33 @@ impl Parser {
             Some(Token::Delimiter{..})
                 if self.match_optional_delimiter(Delimiter::BracketLeft)
                     => {
-                self.parse_collection_def()
+                let token = self.curr_token();
+                let collection = self.parse_collection_def();
+                self.parse_application_args(collection, token)
             },
             Some(Token::Delimiter{..})
                 if self.match_optional_delimiter(Delimiter::BraceLeft)
                     => {
+                if self.is_dict_comprehension() {
+                    let token = self.curr_token();
+                    let key = self.parse_simple_expression();
+                    self.match_required_delimiter(Delimiter::DenoteType);
+                    let value = self.parse_simple_expression();
+                    let comprehension = self.parse_comprehension(Some(key), value, token);
+                    self.match_required_delimiter(Delimiter::BraceRight);
+                    return comprehension
+                }
                 let exp = self.parse_expression();
-                self.match_required_delimiter(Delimiter::BraceRight);
+                self.match_block_end();
                 exp
             },
             Some(Token::Delimiter{..})
                 if self.match_optional_delimiter(Delimiter::ParenLeft)
                     => {
+                let token = self.curr_token();
                 let exp = self.parse_simple_expression();
                 self.match_required_delimiter(Delimiter::ParenRight);
-                exp
+                self.parse_application_args(exp, token)
             },
             Some(_) => {
                 let mut inner_app = self.parse_application();
@@ -474,27 +923,28 @@ impl Parser {
                     let mut outer_app = self.parse_application();
                     match &mut outer_app.exp {
                         Expression::Application{ident: _, ref mut args} => {
-                            args.insert(0, inner_app.clone());
+                            match args.iter().position(|arg| { arg.exp == Expression::Placeholder }) {
+                                Some(i) => args[i] = inner_app.clone(), // fills the first "_"
+                                _ => args.insert(0, inner_app.clone())
+                            }
                             inner_app = outer_app
                         },
-                        Expression::Reference{ident: _} => {
+                        Expression::Lit{..} => {
+                            self.report(Code::InvalidSyntax, "Function chain requires valid function application", &outer_app.token);
+                            ()
+                        },
+                        _ => {
                             inner_app = Exp{
                                 exp: Expression::Application{ident: Box::new(outer_app.clone()), args: vec![inner_app]},
                                 exp_type: outer_app.exp_type.clone(),
                                 token: outer_app.token.clone()
                             }
-                        },
-                        _ => {
-                            self.error = true;
-                            error!("Function chain requires valid function application: {}",
-                                get_fp_from_token(&outer_app.token));
-                            ()
                         }
                     }
                 }
                 inner_app
             },
-            _ => self.make_empty_exp()
+            _ => self.expected_expression()
         }
     }
 
@@ -506,52 +956,76 @@ impl Parser {
                 match lit {
                     IntLit{literal} => literal,
                     _ => {
-                        self.error = true;
-                        error!("Tuple access requires integer literal for index: {}",
-                                get_fp_from_token(&index.token));
+                        self.report(Code::InvalidSyntax, "Tuple access requires integer literal for index", &index.token);
                         -1
                     }
                 }
             },
             _ => {
-                self.error = true;
-                error!("Tuple access requires integer literal for index: {}",
-                                get_fp_from_token(&index.token));
+                self.report(Code::InvalidSyntax, "Tuple access requires integer literal for index", &index.token);
                 -1
             }
         }
     }
 
+    /// `.field` of a record or `.N` of a tuple, the dot is already matched
+    fn parse_access(&mut self, atom: Exp) -> Exp {
+        trace!("parse_access");
+        let token = self.curr_token();
+        match token.clone() {
+            Token::Ident{ident: field, fp: _} => {
+                self.advance();
+                Exp{
+                    exp: Expression::FieldAccess{
+                        ident: Box::new(atom),
+                        field
+                    },
+                    exp_type: UnknownType,
+                    token
+                }
+            },
+            _ => {
+                let access_index = self.parse_access_index() as usize;
+                Exp{
+                    exp: Expression::TupleAccess{
+                        ident: Box::new(atom),
+                        index: access_index
+                    },
+                    exp_type: UnknownType,
+                    token
+                }
+            }
+        }
+    }
+
     fn parse_atom(&mut self) -> Exp {
         trace!("parse_atom");
         match self.curr() {
             Some(Token::Ident{ident, fp: _}) => {
-                let reference = Exp{
-                    exp: Expression::Reference{ident},
-                    exp_type: UnknownType,
-                    token: self.curr().unwrap().clone()
-                };
-
+                let token = self.curr_token();
                 self.advance();
 
-                if self.match_optional_delimiter(Delimiter::TupleAccess) {
-                    let token = self.curr().unwrap().clone();
-                    let access_index = self.parse_access_index() as usize;
-                    let tuple_access = Exp{
-                        exp: Expression::TupleAccess{
-                            ident: Box::new(reference),
-                            index: access_index
-                        },
+                let mut atom = match self.aliases.get(&ident).cloned() {
+                    Some(record_type@RecordType{..})
+                        if self.match_optional_delimiter(Delimiter::BraceLeft)
+                            => self.parse_record_def(record_type, token),
+                    Some(enum_type@EnumType{..})
+                        if self.match_optional_delimiter(Delimiter::PathSep)
+                            => self.parse_enum_def(enum_type, token),
+                    _ => Exp{
+                        exp: Expression::Reference{ident},
                         exp_type: UnknownType,
-                        token: token.clone()
-                    };
-                    tuple_access
-                } else {
-                    reference
+                        token
+                    }
+                };
+
+                while self.match_optional_delimiter(Delimiter::TupleAccess) {
+                    atom = self.parse_access(atom)
                 }
+                atom
             },
             Some(Token::Keyword{keyword, fp: _}) if keyword.is_builtin_function() => {
-                let token = self.curr().unwrap().clone();
+                let token = self.curr_token();
                 self.advance();
                 Exp{
                     exp: Expression::Reference{ident: keyword.to_string()},
@@ -566,29 +1040,110 @@ impl Parser {
                     smp
             }
             Some(_) => self.parse_literal(),
-            _ => self.make_empty_exp()
+            _ => self.expected_expression()
         }
     }
 
-    fn parse_literal(&mut self) -> Exp {
-        trace!("parse_literal");
-        let token = self.curr().unwrap();
-        match self.curr() {
-            Some(Token::Keyword{..}) if self.match_optional_keyword(Keyword::True) => {
-                    Exp{
-                        exp: Expression::Lit{lit: BoolLit{literal: true}},
-                        exp_type: BoolType,
-                        token: token.clone()
-                    }
-            },
-            Some(Token::Keyword{..}) if self.match_optional_keyword(Keyword::False) => {
-                    Exp{
-                        exp: Expression::Lit{lit: BoolLit{literal: false}},
-                        exp_type: BoolType,
-                        token: token.clone()
-                    }
-            },
-            Some(Token::Keyword{..}) if self.match_optional_keyword(Keyword::Null) => {
+    fn parse_record_def(&mut self, record_type: Type, token: Token) -> Exp {
+        trace!("parse_record_def");
+        let (ident, record_fields) = match &record_type {
+            RecordType{ident, fields} => (ident.clone(), fields.clone()),
+            _ => (String::from(""), vec![])
+        };
+        let mut field_exps: Vec<(String, Exp)> = vec![];
+
+        while self.match_list_item(Delimiter::BraceRight, field_exps.is_empty()) {
+            let field_token = self.curr_token();
+            let field = self.match_ident();
+            self.match_required_delimiter(Delimiter::DenoteType);
+            let field_exp = self.parse_simple_expression();
+            if !record_fields.iter().any(|f| f.0 == field) {
+                let help = suggest(&field, record_fields.iter().map(|f| { &f.0 }));
+                self.report_member(Diagnostic::error(Code::UnknownMember,
+                    &format!("Record \"{}\" has no field \"{}\"", ident, field), &field_token).help(help));
+            } else if field_exps.iter().any(|f| f.0 == field) {
+                self.report_member(Diagnostic::error(Code::DuplicateMember,
+                    &format!("Field \"{}\" assigned more than once", field), &field_token));
+            }
+            field_exps.push((field, field_exp));
+        }
+
+        let mut fields: Vec<(String, Exp)> = vec![];
+        for record_field in record_fields.iter() {
+            match field_exps.iter().find(|f| f.0 == record_field.0) {
+                Some(field_exp) => fields.push(field_exp.clone()),
+                _ => {
+                    self.report_member(Diagnostic::error(Code::UnknownMember,
+                        &format!("Missing field \"{}\" for record \"{}\"", record_field.0, ident), &token));
+                }
+            }
+        }
+
+        Exp{
+            exp: Expression::RecordDef{ident, fields},
+            exp_type: record_type,
+            token
+        }
+    }
+
+    fn parse_enum_def(&mut self, enum_type: Type, token: Token) -> Exp {
+        trace!("parse_enum_def");
+        let variant_token = self.curr_token();
+        let variant = self.match_ident();
+        let (ident, variant_types) = match &enum_type {
+            EnumType{ident, variants} =>
+                (ident.clone(), variants.iter().find(|v| v.0 == variant).map(|v| v.1.clone())),
+            _ => (String::from(""), None)
+        };
+
+        let mut values: Vec<Exp> = vec![];
+        if self.match_optional_delimiter(Delimiter::ParenLeft) {
+            values = self.parse_arguments()
+        }
+
+        match variant_types {
+            Some(types) if types.len() != values.len() => {
+                self.report_member(Diagnostic::error(Code::UnknownMember,
+                    &format!("Variant \"{}::{}\" expects {} value(s), got {}", ident, variant, types.len(), values.len()),
+                    &variant_token));
+            },
+            None => {
+                let help = match &enum_type {
+                    EnumType{variants, ..} => suggest(&variant, variants.iter().map(|v| { &v.0 })),
+                    _ => None
+                };
+                self.report_member(Diagnostic::error(Code::UnknownMember,
+                    &format!("Enum \"{}\" has no variant \"{}\"", ident, variant), &variant_token).help(help));
+            },
+            _ => ()
+        }
+
+        Exp{
+            exp: Expression::EnumDef{ident, variant, values},
+            exp_type: enum_type,
+            token
+        }
+    }
+
+    fn parse_literal(&mut self) -> Exp {
+        trace!("parse_literal");
+        let token = self.curr_token();
+        match self.curr() {
+            Some(Token::Keyword{..}) if self.match_optional_keyword(Keyword::True) => {
+                    Exp{
+                        exp: Expression::Lit{lit: BoolLit{literal: true}},
+                        exp_type: BoolType,
+                        token: token.clone()
+                    }
+            },
+            Some(Token::Keyword{..}) if self.match_optional_keyword(Keyword::False) => {
+                    Exp{
+                        exp: Expression::Lit{lit: BoolLit{literal: false}},
+                        exp_type: BoolType,
+                        token: token.clone()
+                    }
+            },
+            Some(Token::Keyword{..}) if self.match_optional_keyword(Keyword::Null) => {
                     Exp{
                         exp: Expression::Lit{lit: NullLit},
                         exp_type: NullType,
@@ -601,72 +1156,185 @@ impl Parser {
                     Exp{
                         exp: Expression::Lit{
                             lit: CharLit{
-                                literal: value.substring(1, value.len() - 1).to_string()
+                                literal: self.parse_char_literal(&value, &token)
                             }
                         },
                         exp_type: CharType,
                         token: token.clone()
                     }
-                } else if value.starts_with('\"') {
+                } else if value.starts_with('\"') || value.starts_with("r\"") {
                     self.advance();
-                    Exp{
-                        exp: Expression::Lit{
-                            lit: StringLit{
-                                literal: value.substring(1, value.len() - 1).to_string()
-                            }
-                        },
-                        exp_type: StringType,
-                        token: token.clone()
-                    }
+                    self.parse_string_literal(&value, &token)
                 } else {
-                    let int_literal = Exp{
+                    self.advance();
+                    Exp{
                         exp: Expression::Lit{lit: IntLit{literal: value.parse().unwrap()}},
                         exp_type: IntType,
                         token: token.clone()
-                    };
-                    self.advance();
-                    if self.match_optional_delimiter(Delimiter::Range) {
-                        let int_literal_range_bound_noninclusive = self.parse_literal();
-                        let range_start = self.get_int_literal_value(int_literal.exp.clone());
-                        let range_end = self.get_int_literal_value(int_literal_range_bound_noninclusive.exp);
-                        if range_end <= range_start {
-                            self.error = true;
-                            error!("Integer range construction requires valid bounds: {}",
-                                get_fp_from_token(&int_literal_range_bound_noninclusive.token));
-                            int_literal
-                        } else {
-                            Exp{
-                                exp: Expression::ListDef{
-                                    values: make_list_def_range(range_start, range_end, &token)
-                                },
-                                exp_type: ListType{list_type: Box::new(IntType)},
-                                token
-                            }
-                        }
-                    } else {
-                        int_literal
                     }
                 }
             }
-            _ => self.make_empty_exp()
+            _ => self.expected_expression()
+        }
+    }
+
+    fn parse_char_literal(&mut self, value: &str, token: &Token) -> String {
+        trace!("parse_char_literal");
+        let chars: Vec<char> = value.chars().collect();
+        match &chars[1..chars.len() - 1] {
+            ['\\', escape @ ..] => match decode_escape(escape) {
+                Some((c, len)) if len == escape.len() => c.to_string(),
+                _ => {
+                    self.report(Code::InvalidEscape, &format!("Invalid escape sequence in {}", value), token);
+                    String::new()
+                }
+            },
+            body => body.iter().collect()
         }
     }
 
+    /// Decodes escapes and splits a string literal into literal text and interpolated expressions.
+    /// Multi-line strings drop the newline after the opening quotes and the indentation of the closing quotes
+    fn parse_string_literal(&mut self, value: &str, token: &Token) -> Exp {
+        trace!("parse_string_literal");
+        let chars: Vec<char> = value.chars().collect();
+        let raw = chars[0] == 'r';
+        let open = if raw { 1 } else { 0 };
+        let quote_len = if chars[open..].starts_with(&['\"'; 3]) { 3 } else { 1 };
+        let mut i = open + quote_len;
+        let mut end = chars.len() - quote_len;
+        let mut indent = 0;
+        let mut line_start = false;
+        if quote_len == 3 {
+            if chars.get(i) == Some(&'\n') {
+                i += 1;
+                line_start = true
+            }
+            if let Some(newline) = chars[..end].iter().rposition(|c| { *c == '\n' }) {
+                if chars[newline + 1..end].iter().all(|c| { c.is_whitespace() }) {
+                    indent = end - newline - 1;
+                    end = newline.max(i)
+                }
+            }
+        }
+
+        let mut parts: Vec<Exp> = vec![];
+        let mut literal = String::new();
+        while i < end {
+            if line_start {
+                let mut skipped = 0;
+                while skipped < indent && i < end && chars[i] != '\n' && chars[i].is_whitespace() {
+                    i += 1;
+                    skipped += 1
+                }
+                line_start = false;
+                continue;
+            }
+            match chars[i] {
+                '\\' if !raw => match decode_escape(&chars[i + 1..end]) {
+                    Some((c, len)) => {
+                        literal.push(c);
+                        i += len + 1
+                    },
+                    None => {
+                        let escape: String = chars[i..(i + 2).min(end)].iter().collect();
+                        let escape_token = literal_part_token(token, &chars, i, escape.clone());
+                        self.report(Code::InvalidEscape, &format!("Invalid escape sequence \"{}\"", escape),
+                            &escape_token);
+                        i += 2
+                    }
+                },
+                '{' if !raw => {
+                    let close = interpolation_end(&chars, i).unwrap_or(end).min(end);
+                    if !literal.is_empty() {
+                        parts.push(Exp{
+                            exp: Expression::Lit{lit: StringLit{literal: std::mem::take(&mut literal)}},
+                            exp_type: StringType,
+                            token: token.clone()
+                        })
+                    }
+                    let fragment: String = chars[i + 1..close].iter().collect();
+                    let fragment_token = literal_part_token(token, &chars, i + 1, fragment.clone());
+                    parts.push(self.parse_interpolation(&fragment, fragment_token));
+                    i = close + 1
+                },
+                c => {
+                    line_start = c == '\n';
+                    literal.push(c);
+                    i += 1
+                }
+            }
+        }
+
+        if parts.is_empty() {
+            return Exp{
+                exp: Expression::Lit{lit: StringLit{literal}},
+                exp_type: StringType,
+                token: token.clone()
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Exp{
+                exp: Expression::Lit{lit: StringLit{literal}},
+                exp_type: StringType,
+                token: token.clone()
+            })
+        }
+        Exp{
+            exp: Expression::Interpolation{parts},
+            exp_type: StringType,
+            token: token.clone()
+        }
+    }
+
+    /// Parses the expression between the braces of a string interpolation
+    fn parse_interpolation(&mut self, fragment: &str, token: Token) -> Exp {
+        trace!("parse_interpolation: {}", fragment);
+        let origin = get_file_position(&token);
+        let scanner = &mut Scanner::init(&origin.file);
+        scanner.scan(&fragment.to_string());
+        if scanner.error {
+            self.error = true;
+            return Exp{exp: Expression::Empty, exp_type: NullType, token}
+        } else if scanner.tokens.is_empty() {
+            self.report(Code::InvalidSyntax, "Expected expression in string interpolation", &token);
+            return Exp{exp: Expression::Empty, exp_type: NullType, token}
+        }
+
+        let parser = &mut Parser::init();
+        parser.tokens = scanner.tokens.iter().map(|t| { offset_token(t, &origin) }).collect();
+        parser.panicking = self.panicking;
+        parser.dummy_count = self.dummy_count;
+        parser.aliases = self.aliases.clone();
+        parser.type_params = self.type_params.clone();
+        parser.loader = std::mem::take(&mut self.loader);
+        let exp = parser.parse_simple_expression();
+        if parser.curr().is_some() {
+            parser.report(Code::InvalidSyntax,
+                &format!("Unexpected {:?} in string interpolation", parser.describe_curr()), &parser.curr_token());
+        }
+        self.loader = std::mem::take(&mut parser.loader);
+        self.dummy_count = parser.dummy_count;
+        self.error |= parser.error;
+        self.panicking |= parser.panicking;
+        exp
+    }
+
     fn parse_branch(&mut self) -> Exp {
         trace!("parse_branch");
-        let token = self.curr().unwrap();
+        let token = self.curr_token();
         self.match_required_delimiter(Delimiter::ParenLeft);
         let condition = self.parse_simple_expression();
         self.match_required_delimiter(Delimiter::ParenRight);
         self.match_required_delimiter(Delimiter::BraceLeft);
         let if_branch = self.parse_expression();
-        self.match_required_delimiter(Delimiter::BraceRight);
+        self.match_block_end();
 
         let mut else_branch = None;
         if self.match_optional_keyword(Keyword::Else) {
             self.match_required_delimiter(Delimiter::BraceLeft);
             else_branch = Some(self.parse_expression());
-            self.match_required_delimiter(Delimiter::BraceRight);
+            self.match_block_end();
         }
         let exp_type = if else_branch == None {
             NullType
@@ -687,13 +1355,13 @@ impl Parser {
 
     fn parse_iter(&mut self) -> Exp {
         trace!("parse_iter");
-        let token = self.curr().unwrap();
+        let token = self.curr_token();
         self.match_required_delimiter(Delimiter::ParenLeft);
         let iter = self.parse_simple_expression();
         self.match_required_delimiter(Delimiter::ParenRight);
         self.match_required_delimiter(Delimiter::BraceLeft);
         let iter_exp = self.parse_expression();
-        self.match_required_delimiter(Delimiter::BraceRight);
+        self.match_block_end();
 
         Exp{
             exp: Expression::Iter{
@@ -705,9 +1373,96 @@ impl Parser {
         }
     }
 
+    fn parse_while(&mut self) -> Exp {
+        trace!("parse_while");
+        let token = self.curr_token();
+        self.match_required_delimiter(Delimiter::ParenLeft);
+        let condition = self.parse_simple_expression();
+        self.match_required_delimiter(Delimiter::ParenRight);
+        self.match_required_delimiter(Delimiter::BraceLeft);
+        let body = self.parse_expression();
+        self.match_block_end();
+
+        Exp{
+            exp: Expression::While{
+                condition: Box::new(condition),
+                body: Box::new(body)
+            },
+            exp_type: NullType,
+            token
+        }
+    }
+
+    /// Loop variable of a for loop, a name or a destructuring pattern
+    fn parse_loop_pattern(&mut self) -> Pattern {
+        trace!("parse_loop_pattern");
+        if self.is_destructuring_pattern() {
+            return self.parse_pattern()
+        }
+        match self.match_ident().as_str() {
+            "_" => Pattern::Any,
+            ident => Pattern::Bind{ident: ident.to_string()}
+        }
+    }
+
+    /// `[index,] pattern in iter`, shared by for loops and comprehensions
+    fn parse_for_head(&mut self) -> (Option<String>, Pattern, Exp) {
+        trace!("parse_for_head");
+        let token = self.curr_token();
+        let mut index: Option<String> = None;
+        let mut pattern = self.parse_loop_pattern();
+        if self.match_optional_delimiter(Delimiter::Comma) {
+            match pattern {
+                Pattern::Bind{ident} => index = Some(ident),
+                Pattern::Any => (),
+                _ => self.report(Code::InvalidPattern, "Loop index must be a name", &token)
+            }
+            pattern = self.parse_loop_pattern()
+        }
+        self.match_required_keyword(Keyword::In);
+        let iter = self.parse_simple_expression();
+        (index, pattern, iter)
+    }
+
+    fn parse_for(&mut self) -> Exp {
+        trace!("parse_for");
+        let token = self.curr_token();
+        let (index, pattern, iter) = self.parse_for_head();
+        self.match_required_delimiter(Delimiter::BraceLeft);
+        let body = self.parse_expression();
+        self.match_block_end();
+
+        Exp{
+            exp: Expression::For{
+                index,
+                pattern: Box::new(pattern),
+                iter: Box::new(iter),
+                body: Box::new(body)
+            },
+            exp_type: NullType,
+            token
+        }
+    }
+
+    fn parse_return(&mut self) -> Exp {
+        trace!("parse_return");
+        let token = self.tokens[self.index - 1].clone(); // the return keyword
+        let value = if self.curr().is_none() || self.at_delimiter(Delimiter::StatementEnd) ||
+            self.at_delimiter(Delimiter::BraceRight) {
+            None
+        } else {
+            Some(self.parse_simple_expression())
+        };
+        Exp{
+            exp: Expression::Return{value: Box::new(value)},
+            exp_type: UnknownType, // never produces a value, so it fits wherever it is used
+            token
+        }
+    }
+
     fn parse_collection_def(&mut self) -> Exp {
         trace!("parse_collection_def");
-        let token = self.curr().unwrap().clone();
+        let token = self.curr_token();
         if self.match_optional_delimiter(Delimiter::BracketRight) {
             return Exp{
                 exp: Expression::ListDef{values: vec![]},
@@ -718,12 +1473,16 @@ impl Parser {
 
         let first_element = self.parse_simple_expression();
 
-        if self.match_optional_delimiter(Delimiter::Comma) {
+        if self.at_keyword(Keyword::For) {
+            let comprehension = self.parse_comprehension(None, first_element, token);
+            self.match_required_delimiter(Delimiter::BracketRight);
+            comprehension
+        } else if self.match_optional_delimiter(Delimiter::Comma) {
             let mut elements: Vec<Exp> = vec![first_element, self.parse_simple_expression()];
             while self.match_optional_delimiter(Delimiter::Comma) {
                 elements.push(self.parse_simple_expression())
             }
-            self.match_optional_delimiter(Delimiter::BracketRight);
+            self.match_required_delimiter(Delimiter::BracketRight);
             let list_type = if elements.is_empty() {
                 UnknownType
             } else {
@@ -735,9 +1494,14 @@ impl Parser {
                 token
             }
         } else if self.match_optional_delimiter(Delimiter::DenoteType) {
+            let first_value = self.parse_simple_expression();
+            if self.at_keyword(Keyword::For) {
+                let comprehension = self.parse_comprehension(Some(first_element), first_value, token);
+                self.match_required_delimiter(Delimiter::BracketRight);
+                return comprehension
+            }
             let mut mapping: Vec<(Literal, Exp)> = vec![];
             let first_key = self.get_exp_literal(first_element.clone());
-            let first_value = self.parse_simple_expression();
             mapping.push((first_key, first_value.clone()));
 
             while self.match_optional_delimiter(Delimiter::Comma) {
@@ -747,7 +1511,7 @@ impl Parser {
                 let value = self.parse_simple_expression();
                 mapping.push((key, value));
             }
-            self.match_optional_delimiter(Delimiter::BracketRight);
+            self.match_required_delimiter(Delimiter::BracketRight);
             Exp{
                 exp: Expression::DictDef{mapping},
                 exp_type: DictType{
@@ -757,7 +1521,7 @@ impl Parser {
                 token
             }
         } else {
-            self.match_optional_delimiter(Delimiter::BracketRight);
+            self.match_required_delimiter(Delimiter::BracketRight);
             let list_type = first_element.exp_type.clone();
             Exp{
                 exp: Expression::ListDef{values: vec![first_element]},
@@ -767,9 +1531,91 @@ impl Parser {
         }
     }
 
+    /// Clauses of `value for x in xs if p(x) ...`, the key is set for dictionary comprehensions
+    fn parse_comprehension(&mut self, key: Option<Exp>, value: Exp, token: Token) -> Exp {
+        trace!("parse_comprehension");
+        let mut clauses: Vec<Clause> = vec![];
+        loop {
+            if self.match_optional_keyword(Keyword::For) {
+                let (index, pattern, iter) = self.parse_for_head();
+                clauses.push(Clause::For{index, pattern, iter})
+            } else if self.match_optional_keyword(Keyword::If) {
+                clauses.push(Clause::If{condition: self.parse_simple_expression()})
+            } else {
+                break
+            }
+        }
+        let exp_type = match &key {
+            Some(key_exp) => DictType{
+                key_type: Box::new(key_exp.exp_type.clone()),
+                value_type: Box::new(value.exp_type.clone())
+            },
+            _ => ListType{list_type: Box::new(value.exp_type.clone())}
+        };
+        Exp{
+            exp: Expression::Comprehension{key: Box::new(key), value: Box::new(value), clauses},
+            exp_type,
+            token
+        }
+    }
+
+    /// Looks ahead from just after "{" for `key: value for ...`, anything else is a block
+    fn is_dict_comprehension(&self) -> bool {
+        let mut depth = 0;
+        let mut seen_colon = false;
+        for token in self.tokens[self.index..].iter() {
+            match token {
+                Token::Delimiter{delim: Delimiter::ParenLeft, ..} |
+                Token::Delimiter{delim: Delimiter::BracketLeft, ..} |
+                Token::Delimiter{delim: Delimiter::BraceLeft, ..} => depth += 1,
+                Token::Delimiter{delim: Delimiter::ParenRight, ..} |
+                Token::Delimiter{delim: Delimiter::BracketRight, ..} |
+                Token::Delimiter{delim: Delimiter::BraceRight, ..} => {
+                    if depth == 0 {
+                        return false
+                    }
+                    depth -= 1
+                },
+                Token::Delimiter{delim: Delimiter::StatementEnd, ..} if depth == 0 => return false,
+                Token::Delimiter{delim: Delimiter::DenoteType, ..} if depth == 0 => seen_colon = true,
+                Token::Keyword{keyword: Keyword::For, ..} if depth == 0 => return seen_colon,
+                Token::Keyword{keyword, ..} if depth == 0 && !keyword.is_builtin_function() && !seen_colon => return false,
+                _ => ()
+            }
+        }
+        false
+    }
+
+    /// `[i]` or `[start:end:step]` with every part of a slice optional
+    fn parse_index_or_slice(&mut self) -> Exp {
+        trace!("parse_index_or_slice");
+        let token = self.curr_token();
+        let start = if self.at_delimiter(Delimiter::DenoteType) { None } else { Some(self.parse_simple_expression()) };
+        if !self.match_optional_delimiter(Delimiter::DenoteType) {
+            self.match_required_delimiter(Delimiter::BracketRight);
+            return start.unwrap_or_else(|| { self.expected_expression() })
+        }
+        let end = if self.at_delimiter(Delimiter::DenoteType) || self.at_delimiter(Delimiter::BracketRight) {
+            None
+        } else {
+            Some(self.parse_simple_expression())
+        };
+        let step = if self.match_optional_delimiter(Delimiter::DenoteType) && !self.at_delimiter(Delimiter::BracketRight) {
+            Some(self.parse_simple_expression())
+        } else {
+            None
+        };
+        self.match_required_delimiter(Delimiter::BracketRight);
+        Exp{
+            exp: Expression::Slice{start: Box::new(start), end: Box::new(end), step: Box::new(step)},
+            exp_type: UnknownType,
+            token
+        }
+    }
+
     fn parse_tuple_def_or_simple_expression(&mut self) -> Exp {
         trace!("parse_tuple_def_or_simple_expression");
-        let token= self.curr().unwrap().clone();
+        let token = self.curr_token();
         let first_element = self.parse_simple_expression();
 
         let mut tuple_types = vec![first_element.exp_type.clone()];
@@ -779,7 +1625,6 @@ impl Parser {
             tuple_types.push(tuple_element.exp_type.clone());
             tuple_elements.push(tuple_element);
         }
-        self.match_optional_delimiter(Delimiter::ParenRight);
 
         if tuple_elements.len() == 1 {
             first_element
@@ -794,50 +1639,146 @@ impl Parser {
 
     fn parse_schema_def(&mut self) -> Exp {
         trace!("parse_schema_def");
-        let token = self.curr().unwrap().clone();
+        let token = self.curr_token();
         self.match_required_delimiter(Delimiter::BraceLeft);
         let mut mapping: Vec<(String, Type)> = vec![];
+        let mut constraints: Vec<Constraint> = vec![];
 
-        while self.match_optional_delimiter(Delimiter::Comma) ||
-            !self.match_optional_delimiter(Delimiter::BraceRight) {
+        while self.match_list_item(Delimiter::BraceRight, mapping.is_empty() && constraints.is_empty()) {
+            let is_check = matches!(self.curr(), Some(Token::Ident{ident, ..}) if ident == "check") &&
+                !matches!(self.tokens.get(self.index + 1), Some(Token::Delimiter{delim: Delimiter::DenoteType, ..}));
+            if is_check { // a column may still be called "check"
+                self.advance();
+                constraints.push(Constraint::Check{condition: Box::new(self.parse_simple_expression())});
+                continue
+            }
             let ident = self.match_ident();
             self.match_required_delimiter(Delimiter::DenoteType);
             let col_type = self.parse_type();
+            constraints.append(&mut self.parse_column_constraints(&ident));
             mapping.push((ident, col_type));
         }
 
         Exp {
-            exp: Expression::SchemaDef{mapping: mapping.clone()},
+            exp: Expression::SchemaDef{mapping: mapping.clone(), constraints},
             exp_type: SchemaType{col_types: mapping.iter().map(|col| { col.1.clone() }).collect()},
             token
         }
     }
 
+    /// Constraints following a schema column's type: not null, unique, min n, max n, in [literals] and ~"regex"
+    fn parse_column_constraints(&mut self, column: &str) -> Vec<Constraint> {
+        trace!("parse_column_constraints");
+        let column = column.to_string();
+        let mut constraints: Vec<Constraint> = vec![];
+        loop {
+            let constraint = match self.curr() {
+                Some(Token::Keyword{..}) if self.match_optional_keyword(Keyword::Not) => {
+                    self.match_required_keyword(Keyword::Null);
+                    Constraint::NotNull{column: column.clone()}
+                },
+                Some(Token::Keyword{..}) if self.match_optional_keyword(Keyword::In) => {
+                    self.match_required_delimiter(Delimiter::BracketLeft);
+                    let mut values: Vec<Literal> = vec![];
+                    while self.match_list_item(Delimiter::BracketRight, values.is_empty()) {
+                        let literal = self.parse_literal();
+                        values.push(self.get_exp_literal(literal))
+                    }
+                    Constraint::Allowed{column: column.clone(), values}
+                },
+                Some(Token::Delimiter{..}) if self.match_optional_delimiter(Delimiter::Regex) => {
+                    match self.parse_regex_pattern() {
+                        Pattern::Regex{regex} => Constraint::Regex{column: column.clone(), regex},
+                        _ => continue // already reported
+                    }
+                },
+                Some(Token::Ident{ident, ..}) if ident == "unique" => {
+                    self.advance();
+                    Constraint::Unique{column: column.clone()}
+                },
+                Some(Token::Ident{ident, ..}) if ident == "min" || ident == "max" => {
+                    self.advance();
+                    let token = self.curr_token();
+                    let negative = self.match_optional_delimiter(Delimiter::Minus);
+                    let bound = match self.curr() {
+                        Some(Token::Value{value, ..}) if value.parse::<i32>().is_ok() => {
+                            self.advance();
+                            let literal = value.parse::<i32>().unwrap();
+                            if negative { -literal } else { literal }
+                        },
+                        _ => {
+                            self.report(Code::InvalidSyntax, &format!("Expected an int bound for \"{}\"", ident), &token);
+                            return constraints
+                        }
+                    };
+                    if ident == "min" {
+                        Constraint::Min{column: column.clone(), min: bound}
+                    } else {
+                        Constraint::Max{column: column.clone(), max: bound}
+                    }
+                },
+                _ => return constraints
+            };
+            constraints.push(constraint)
+        }
+    }
+
     fn parse_pattern(&mut self) -> Pattern {
         trace!("parse_pattern");
         match self.curr() {
-            Some(Token::Ident{ident, fp: _}) if ident != "_" => { // type case
+            Some(Token::Ident{ident, fp: _}) if ident != "_" => {
                 self.advance();
-                self.match_required_delimiter(Delimiter::DenoteType);
-                let case_type = self.parse_type();
-                let mut predicate: Option<Exp> = None;
-                if self.match_optional_keyword(Keyword::If) {
-                    predicate = Some(self.parse_simple_expression())
-                }
-                Pattern::TypePattern{
-                    ident,
-                    case_type,
-                    predicate
+                match self.aliases.get(&ident).cloned() {
+                    Some(EnumType{ident, variants})
+                        if self.match_optional_delimiter(Delimiter::PathSep)
+                            => self.parse_variant_pattern(ident, variants),
+                    Some(RecordType{ident, fields})
+                        if self.match_optional_delimiter(Delimiter::BraceLeft)
+                            => self.parse_record_pattern(ident, fields),
+                    _ if self.match_optional_delimiter(Delimiter::DenoteType) => { // type case
+                        let case_type = self.parse_type();
+                        Pattern::TypePattern{ident, case_type}
+                    },
+                    _ => Pattern::Bind{ident}
                 }
             },
             Some(Token::Ident{ident, fp: _}) if ident == "_" => { // catch-all
                 self.advance();
                 Pattern::Any
             },
-            Some(Token::Value{..}) => {
+            Some(Token::Delimiter{..}) if self.match_optional_delimiter(Delimiter::ParenLeft) => {
+                Pattern::Tuple{fields: self.parse_pattern_list(Delimiter::ParenRight)}
+            },
+            Some(Token::Delimiter{..}) if self.match_optional_delimiter(Delimiter::BracketLeft) => {
+                self.parse_list_pattern()
+            },
+            Some(Token::Delimiter{..}) if self.match_optional_delimiter(Delimiter::Regex) => {
+                self.parse_regex_pattern()
+            },
+            Some(Token::Value{..}) |
+            Some(Token::Keyword{keyword: Keyword::True, ..}) |
+            Some(Token::Keyword{keyword: Keyword::False, ..}) |
+            Some(Token::Keyword{keyword: Keyword::Null, ..}) => {
                 let lit_pattern = self.parse_literal();
                 match lit_pattern.exp.clone() {
-                    list@Expression::ListDef{..} => Pattern::Range{range: list},
+                    Expression::Lit{lit} if self.match_optional_delimiter(Delimiter::Range) => {
+                        let inclusive = !self.match_optional_delimiter(Delimiter::LessThan);
+                        let end_token = self.curr_token();
+                        let end = self.parse_literal();
+                        let valid_bounds = match (&lit, &end.exp) {
+                            (IntLit{literal: s}, Expression::Lit{lit: IntLit{literal: e}}) =>
+                                if inclusive { s <= e } else { s < e },
+                            (CharLit{literal: s}, Expression::Lit{lit: CharLit{literal: e}}) =>
+                                if inclusive { s <= e } else { s < e },
+                            _ => false
+                        };
+                        if valid_bounds {
+                            Pattern::Range{start: lit, end: self.get_exp_literal(end), inclusive}
+                        } else {
+                            self.report(Code::InvalidPattern, "Range pattern requires int or char bounds in ascending order", &end_token);
+                            Pattern::Any
+                        }
+                    },
                     Expression::Lit{lit} => {
                         if self.match_optional_delimiter(Delimiter::LambdaSig) {
                             let mut literals: Vec<Literal> = vec![
@@ -857,49 +1798,187 @@ impl Parser {
                         }
                     },
                     _ => {
-                        self.error = true;
-                        error!("Invalid pattern: {}",
-                            get_fp_from_token(&lit_pattern.token));
+                        self.report(Code::InvalidPattern, "Invalid pattern", &lit_pattern.token);
                         Pattern::Any
                     }
                 }
             },
             Some(_) => {
-                self.error = true;
-                error!("Invalid pattern: {}",
-                    get_fp_from_token(&self.curr().unwrap()));
+                self.report(Code::InvalidPattern, "Invalid pattern", &self.curr_token());
                 Pattern::Any
             },
             _ => {
-                self.error = true;
-                error!("Invalid pattern or EOF");
+                self.report(Code::InvalidPattern, "Invalid pattern or EOF", &self.last_token());
+                Pattern::Any
+            }
+        }
+    }
+
+    fn parse_pattern_list(&mut self, end: Delimiter) -> Vec<Pattern> {
+        trace!("parse_pattern_list");
+        let mut patterns: Vec<Pattern> = vec![];
+        if self.match_optional_delimiter(end.clone()) {
+            return patterns
+        }
+        patterns.push(self.parse_pattern());
+        while self.match_optional_delimiter(Delimiter::Comma) {
+            patterns.push(self.parse_pattern())
+        }
+        self.match_required_delimiter(end);
+        patterns
+    }
+
+    fn parse_list_pattern(&mut self) -> Pattern {
+        trace!("parse_list_pattern");
+        let mut elems: Vec<Pattern> = vec![];
+        let mut rest: Option<Box<Pattern>> = None;
+        if self.match_optional_delimiter(Delimiter::BracketRight) {
+            return Pattern::List{elems, rest}
+        }
+        loop {
+            if elems.is_empty() && self.at_dict_key() {
+                return self.parse_dict_pattern()
+            }
+            if self.match_optional_delimiter(Delimiter::Range) { // tail of the list
+                rest = Some(Box::new(match self.curr() {
+                    Some(Token::Delimiter{delim: Delimiter::BracketRight, ..}) => Pattern::Any,
+                    _ => self.parse_pattern()
+                }));
+                break
+            }
+            elems.push(self.parse_pattern());
+            if !self.match_optional_delimiter(Delimiter::Comma) {
+                break
+            }
+        }
+        self.match_required_delimiter(Delimiter::BracketRight);
+        Pattern::List{elems, rest}
+    }
+
+    fn parse_dict_pattern(&mut self) -> Pattern {
+        trace!("parse_dict_pattern");
+        let mut entries: Vec<(Literal, Pattern)> = vec![];
+        loop {
+            let key = self.parse_literal();
+            let key = self.get_exp_literal(key);
+            self.match_required_delimiter(Delimiter::DenoteType);
+            entries.push((key, self.parse_pattern()));
+            if !self.match_optional_delimiter(Delimiter::Comma) {
+                break
+            }
+        }
+        self.match_required_delimiter(Delimiter::BracketRight);
+        Pattern::Dict{entries}
+    }
+
+    /// Regex patterns are always read raw, so backslashes and braces reach the regex unchanged
+    fn parse_regex_pattern(&mut self) -> Pattern {
+        trace!("parse_regex_pattern");
+        let token = self.curr_token();
+        match self.curr() {
+            Some(Token::Value{value, ..}) if value.starts_with('\"') || value.starts_with("r\"") => {
+                self.advance();
+                let quote_len = if value.trim_start_matches('r').starts_with("\"\"\"") { 3 } else { 1 };
+                let start = quote_len + if value.starts_with('r') { 1 } else { 0 };
+                let literal = value.chars().take(value.chars().count() - quote_len).skip(start).collect::<String>();
+                if let Err(e) = Regex::new(&literal) {
+                    self.report(Code::InvalidPattern, &format!("Invalid regex pattern, {}", e), &token);
+                }
+                Pattern::Regex{regex: literal}
+            },
+            _ => {
+                self.report(Code::InvalidPattern, "Regex pattern requires a string literal", &token);
                 Pattern::Any
             }
         }
     }
 
+    fn parse_variant_pattern(&mut self, ident: String, variants: Vec<(String, Vec<Type>)>) -> Pattern {
+        trace!("parse_variant_pattern");
+        let token = self.curr_token();
+        let variant = self.match_ident();
+        let mut fields: Vec<Pattern> = vec![];
+        if self.match_optional_delimiter(Delimiter::ParenLeft) {
+            fields.push(self.parse_pattern());
+            while self.match_optional_delimiter(Delimiter::Comma) {
+                fields.push(self.parse_pattern())
+            }
+            self.match_required_delimiter(Delimiter::ParenRight);
+        }
+
+        match variants.iter().find(|v| v.0 == variant) {
+            Some(v) if v.1.len() != fields.len() => {
+                self.report(Code::InvalidPattern, &format!("Variant \"{}::{}\" has {} value(s), pattern has {}",
+                    ident, variant, v.1.len(), fields.len()), &token);
+            },
+            None => {
+                let help = suggest(&variant, variants.iter().map(|v| { &v.0 }));
+                self.report_member(Diagnostic::error(Code::UnknownMember,
+                    &format!("Enum \"{}\" has no variant \"{}\"", ident, variant), &token).help(help));
+            },
+            _ => ()
+        }
+        Pattern::Variant{ident, variant, fields}
+    }
+
+    fn parse_record_pattern(&mut self, ident: String, record_fields: Vec<(String, Type)>) -> Pattern {
+        trace!("parse_record_pattern");
+        let mut fields: Vec<(String, Pattern)> = vec![];
+        while self.match_list_item(Delimiter::BraceRight, fields.is_empty()) {
+            let token = self.curr_token();
+            let field = self.match_ident();
+            let field_pattern = if self.match_optional_delimiter(Delimiter::DenoteType) {
+                self.parse_pattern()
+            } else {
+                Pattern::Bind{ident: field.clone()}
+            };
+            if !record_fields.iter().any(|f| f.0 == field) {
+                let help = suggest(&field, record_fields.iter().map(|f| { &f.0 }));
+                self.report_member(Diagnostic::error(Code::UnknownMember,
+                    &format!("Record \"{}\" has no field \"{}\"", ident, field), &token).help(help));
+            }
+            fields.push((field, field_pattern));
+        }
+        Pattern::Record{ident, fields}
+    }
+
     fn parse_case(&mut self) -> Case {
         trace!("parse_case");
         self.match_required_keyword(Keyword::Case);
+        let token = self.curr_token();
         let pattern = self.parse_pattern();
+        let mut guard: Option<Exp> = None;
+        if self.match_optional_keyword(Keyword::If) {
+            guard = Some(self.parse_simple_expression())
+        }
         self.match_required_delimiter(Delimiter::CaseExp);
         let case_exp = self.parse_simple_expression();
         Case{
             pattern,
-            case_exp
+            guard,
+            case_exp,
+            token
         }
     }
 
     fn parse_match(&mut self) -> Exp {
         trace!("parse_match");
-        let token = self.curr().unwrap().clone();
+        let token = self.curr_token();
         let value = self.parse_atom();
         self.match_required_delimiter(Delimiter::BraceLeft);
         let mut cases = vec![self.parse_case()];
-        while self.match_optional_delimiter(Delimiter::Comma) {
+        loop {
+            let recovering = self.panicking;
+            self.synchronize(Sync::Case);
+            if self.at_keyword(Keyword::Case) && !recovering { // a case skipped by recovery has its error already
+                self.report(Code::ExpectedToken, "Expected \",\", got \"case\"", &self.curr_token());
+                self.panicking = false
+            } else if !self.match_optional_delimiter(Delimiter::Comma) && !self.at_keyword(Keyword::Case) {
+                break
+            }
             cases.push(self.parse_case())
         }
-        self.match_required_delimiter(Delimiter::BraceRight);
+        self.match_block_end();
         let exp_type = cases.first().unwrap().case_exp.exp_type.clone();
         Exp{
             exp: Expression::Match{
@@ -911,31 +1990,125 @@ impl Parser {
         }
     }
 
-    fn parse_parameter(&mut self) -> Parameter {
+    fn parse_try(&mut self) -> Exp {
+        trace!("parse_try");
+        let token = self.curr_token();
+        self.match_required_delimiter(Delimiter::BraceLeft);
+        let try_exp = self.parse_expression();
+        self.match_block_end();
+        self.match_required_keyword(Keyword::Catch);
+        let catch_ident = self.match_ident();
+        self.match_required_delimiter(Delimiter::BraceLeft);
+        let catch_exp = self.parse_expression();
+        self.match_block_end();
+        let exp_type = try_exp.exp_type.clone();
+        Exp{
+            exp: Expression::Try{
+                try_exp: Box::new(try_exp),
+                catch_ident,
+                catch_exp: Box::new(catch_exp)
+            },
+            exp_type,
+            token
+        }
+    }
+
+    fn parse_parameter(&mut self) -> (Parameter, Option<Pattern>) {
         trace!("parse_parameter");
-        let token = self.curr().unwrap().clone();
-        let ident = self.match_ident();
+        let token = self.curr_token();
+        let (ident, pattern) = if self.is_destructuring_pattern() {
+            let pattern = self.parse_pattern();
+            (self.dummy(), Some(pattern))
+        } else {
+            (self.match_ident(), None)
+        };
         self.match_required_delimiter(Delimiter::DenoteType);
         let param_type = self.parse_type();
-        Parameter{ident, param_type, token}
+        (Parameter{ident, param_type, token}, pattern)
     }
     
     fn parse_lambda(&mut self) -> Exp {
         trace!("parse_lambda");
-        let token = self.curr().unwrap().clone();
+        let token = self.curr_token();
+        let type_params_scope = self.type_params.len();
+        if self.match_optional_delimiter(Delimiter::LessThan) {
+            self.parse_type_params()
+        }
+        self.parse_function(token, type_params_scope, Delimiter::LambdaSig)
+    }
+
+    fn parse_fn_group(&mut self) -> Exp {
+        trace!("parse_fn_group");
+        let token = self.curr_token();
+        let mut defs: Vec<(String, Exp)> = vec![];
+        let mut after_fn_exp: Option<Exp> = None;
+        loop {
+            let ident = self.match_ident();
+            let type_params_scope = self.type_params.len();
+            if self.match_optional_delimiter(Delimiter::LessThan) {
+                self.parse_type_params()
+            }
+            let fn_token = self.curr_token();
+            self.match_required_delimiter(Delimiter::ParenLeft);
+            defs.push((ident, self.parse_function(fn_token, type_params_scope, Delimiter::ParenRight)));
+
+            if !self.match_statement_end() {
+                break
+            }
+            match self.curr() {
+                Some(Token::Keyword{..}) if self.match_optional_keyword(Keyword::Fn) => (), // same recursive group
+                _ => {
+                    after_fn_exp = Some(self.parse_expression());
+                    break
+                }
+            }
+        }
+        let exp_type = get_return_type(&after_fn_exp);
+
+        Exp{
+            exp: Expression::FnDef{defs, after_fn_exp: Box::new(after_fn_exp)},
+            exp_type,
+            token
+        }
+    }
+
+    fn parse_function(&mut self, token: Token, type_params_scope: usize, params_end: Delimiter) -> Exp {
+        trace!("parse_function");
         let mut params: Vec<Parameter> = vec![];
-        if !self.match_optional_delimiter(Delimiter::LambdaSig) {
-            while self.match_optional_delimiter(Delimiter::Comma) ||
-                !self.match_optional_delimiter(Delimiter::LambdaSig) {
-                params.push(self.parse_parameter());
+        let mut param_patterns: Vec<(Parameter, Pattern)> = vec![];
+        if !self.match_optional_delimiter(params_end.clone()) {
+            while self.match_list_item(params_end.clone(), params.is_empty()) {
+                let (param, pattern) = self.parse_parameter();
+                if let Some(pattern) = pattern {
+                    param_patterns.push((param.clone(), pattern))
+                }
+                params.push(param);
             }
         }
 
         self.match_required_delimiter(Delimiter::ReturnType);
         let return_type = self.parse_type();
         self.match_required_delimiter(Delimiter::BraceLeft);
-        let body = self.parse_expression();
-        self.match_required_delimiter(Delimiter::BraceRight);
+        let mut body = self.parse_expression();
+        self.match_block_end();
+        self.type_params.truncate(type_params_scope);
+
+        for (param, pattern) in param_patterns.into_iter().rev() { // destructure parameters before the body
+            body = Exp{
+                exp_type: body.exp_type.clone(),
+                exp: Expression::LetPattern{
+                    pattern: Box::new(pattern),
+                    let_type: param.param_type.clone(),
+                    let_exp: Box::new(Exp{
+                        exp: Expression::Reference{ident: param.ident.clone()},
+                        exp_type: param.param_type.clone(),
+                        token: param.token.clone()
+                    }),
+                    after_let_exp: Box::new(Some(body))
+                },
+                token: param.token
+            }
+        }
 
         let mut param_types: Vec<Type> = vec![];
         for p in params.iter() {
@@ -950,13 +2123,22 @@ impl Parser {
             exp: Expression::Lambda{
                 params,
                 return_type: return_type.clone(),
-                body: Box::new(body)
+                body: Arc::new(Body::new(body))
             },
             exp_type: func_type.clone(),
             token: token.clone()
         }
     }
 
+    fn parse_type_params(&mut self) {
+        trace!("parse_type_params");
+        let start = self.type_params.len();
+        while self.match_list_item(Delimiter::GreaterThan, self.type_params.len() == start) {
+            let type_param = self.match_ident();
+            self.type_params.push(type_param)
+        }
+    }
+
     fn parse_arguments(&mut self) -> Vec<Exp> {
         trace!("parse_arguments");
         let mut args: Vec<Exp> = vec![];
@@ -964,41 +2146,58 @@ impl Parser {
         if self.match_optional_delimiter(Delimiter::ParenRight) {
             args
         } else {
-            args.push(self.parse_simple_expression());
-            while self.match_optional_delimiter(Delimiter::Comma) ||
-                !self.match_optional_delimiter(Delimiter::ParenRight) {
-                args.push(self.parse_simple_expression())
+            args.push(self.parse_argument());
+            while self.match_list_item(Delimiter::ParenRight, false) {
+                args.push(self.parse_argument())
             }
             args
         }
     }
 
+    /// An argument expression, or a lone "_" leaving that parameter open for partial application
+    fn parse_argument(&mut self) -> Exp {
+        trace!("parse_argument");
+        let is_placeholder = matches!(self.curr(), Some(Token::Ident{ident, ..}) if ident == "_") &&
+            matches!(self.tokens.get(self.index + 1),
+                Some(Token::Delimiter{delim: Delimiter::Comma, ..}) | Some(Token::Delimiter{delim: Delimiter::ParenRight, ..}));
+        if is_placeholder {
+            let token = self.curr_token();
+            self.advance();
+            Exp{exp: Expression::Placeholder, exp_type: UnknownType, token}
+        } else {
+            self.parse_simple_expression()
+        }
+    }
+
     fn parse_application(&mut self) -> Exp {
         trace!("parse_application");
-        let token = self.curr().unwrap().clone();
+        let token = self.curr_token();
         let ident: Exp = self.parse_atom();
+        self.parse_application_args(ident, token)
+    }
 
-        match ident.exp {
-            Expression::Lit{..} => ident,
-            _ => {
-                if self.match_optional_delimiter(Delimiter::ParenLeft) {
-                    let args: Vec<Exp> = self.parse_arguments();
-                    let mut app = Exp{
-                        exp: Expression::Application{ident: Box::new(ident), args},
-                        exp_type: UnknownType,
-                        token
-                    };
-
-                    while self.match_optional_delimiter(Delimiter::ParenLeft) {
-                        let outer_args = self.parse_arguments();
-                        app.exp = Expression::Application{ident: Box::new(app.clone()), args: outer_args}
-                    }
-                    app
-                } else {
-                    ident
-                }
+    /// Applies `(args)`, `[index]` and `.field` suffixes in order, e.g. `f(x)(y)`, `rows[1:](0)` or `ps[0].id`
+    fn parse_application_args(&mut self, ident: Exp, token: Token) -> Exp {
+        trace!("parse_application_args");
+        let mut app = ident;
+        loop {
+            let args = if self.match_optional_delimiter(Delimiter::ParenLeft) {
+                self.parse_arguments()
+            } else if self.match_optional_delimiter(Delimiter::BracketLeft) {
+                vec![self.parse_index_or_slice()] // l[i] indexes like l(i)
+            } else if self.match_optional_delimiter(Delimiter::TupleAccess) {
+                app = self.parse_access(app);
+                continue
+            } else {
+                break
+            };
+            app = Exp{
+                exp: Expression::Application{ident: Box::new(app), args},
+                exp_type: UnknownType,
+                token: token.clone()
             }
         }
+        app
     }
 
     fn parse_type(&mut self) -> Type {
@@ -1016,7 +2215,7 @@ impl Parser {
                 while self.match_optional_delimiter(Delimiter::Comma) {
                     union_types.push(self.parse_type())
                 }
-                self.match_optional_delimiter(Delimiter::BracketRight);
+                self.match_required_delimiter(Delimiter::BracketRight);
                 UnionType{union_types}
             },
             Some(Token::Keyword{..}) if self.match_optional_keyword(Keyword::List) => {
@@ -1025,6 +2224,12 @@ impl Parser {
                 self.match_required_delimiter(Delimiter::BracketRight);
                 ListType{list_type: Box::new(list_type)}
             },
+            Some(Token::Keyword{..}) if self.match_optional_keyword(Keyword::Seq) => {
+                self.match_required_delimiter(Delimiter::BracketLeft);
+                let seq_type = self.parse_type();
+                self.match_required_delimiter(Delimiter::BracketRight);
+                SeqType{seq_type: Box::new(seq_type)}
+            },
             Some(Token::Keyword{..}) if self.match_optional_keyword(Keyword::Dict) => {
                 self.match_required_delimiter(Delimiter::BracketLeft);
                 let key_type = self.parse_type();
@@ -1060,6 +2265,10 @@ impl Parser {
                 let return_type = self.parse_type();
                 FuncType{param_types, return_type: Box::new(return_type)}
             },
+            Some(Token::Ident{ident, .. }) if self.type_params.contains(&ident) => {
+                self.advance();
+                GenericType{ident}
+            },
             Some(Token::Ident{ident, .. }) => {
                 match self.aliases.get(&*ident) {
                     Some(aliased_type) => {
@@ -1068,21 +2277,17 @@ impl Parser {
                         type_sig
                     },
                     _ => {
-                        error!("Invalid type-alias: {}",
-                            get_fp_from_token(&self.curr().unwrap()));
+                        self.report(Code::InvalidType, "Invalid type-alias", &self.curr_token());
                         UnknownType
                     }
                 }
             } ,
             Some(_) => {
-                self.error = true;
-                error!("Invalid type signature: {}",
-                    get_fp_from_token(&self.curr().unwrap()));
+                self.report(Code::InvalidType, "Invalid type signature", &self.curr_token());
                 UnknownType
             },
             _ => {
-                self.error = true;
-                error!("Invalid type signature or EOF");
+                self.report(Code::InvalidType, "Invalid type signature or EOF", &self.last_token());
                 UnknownType
             }
         };
@@ -3,26 +3,28 @@ use std::str::FromStr;
 use regex::Regex;
 use either::*;
 use substring::Substring;
-use log::{debug, error};
+use log::debug;
 
 use crate::defs::keyword;
 use crate::defs::delimiter;
 use crate::defs::raw_delimiter;
 use crate::utils::file_position::FilePosition;
 use crate::scanner::token::Token;
+use crate::utils::diagnostic::{Diagnostic, Code, Span};
 
 pub struct Scanner {
     pub error: bool,
-    pub tokens: Vec<Token>
+    pub tokens: Vec<Token>,
+    file: String
 }
 
-fn is_valid_character(c: &char, inside_quotes: &bool) -> bool {
-    *inside_quotes || c.is_alphanumeric() || c.is_whitespace() || *c == '#' || *c == '_' || *c == '\'' || *c == '\"' || is_raw_delim(c, inside_quotes.borrow())
+fn is_valid_character(c: &char) -> bool {
+    c.is_alphanumeric() || c.is_whitespace() || *c == '#' || *c == '_' || *c == '\'' || *c == '\"' || is_raw_delim(c)
 }
 
-fn is_raw_delim(c: &char, inside_quotes: &bool) -> bool {
+fn is_raw_delim(c: &char) -> bool {
     match raw_delimiter::RawDelimiter::from_str(&c.to_string()) {
-        Ok(_) if !inside_quotes => true,
+        Ok(_) => true,
         _ => false
     }
 }
@@ -60,14 +62,67 @@ fn is_ident(token: &String) -> bool {
     ident_regex.is_match(token.borrow())
 }
 
-fn is_whitespace(c: char, inside_quotes: bool) -> bool {
+fn is_whitespace(c: char) -> bool {
     match c {
-        '\n' | '\r' | '\t' => true,
-        ' ' => if inside_quotes { false } else { true },
+        '\n' | '\r' | '\t' | ' ' => true,
+        _ => false
+    }
+}
+
+fn is_literal_start(text: &[char], i: usize) -> bool {
+    match text[i] {
+        '\'' | '\"' => true,
+        'r' => text.get(i + 1) == Some(&'\"') && (i == 0 || !(text[i - 1].is_alphanumeric() || text[i - 1] == '_')),
         _ => false
     }
 }
 
+/// Index just past the char or string literal starting at start, None when it is not terminated.
+/// An 'r' prefix turns off escapes and interpolation, triple quotes allow the string to span lines
+pub fn literal_end(text: &[char], start: usize) -> Option<usize> {
+    let raw = text[start] == 'r';
+    let open = if raw { start + 1 } else { start };
+    let quote = text[open];
+    let multi_line = quote == '\"' && text[open..].starts_with(&['\"'; 3]);
+    let quote_len = if multi_line { 3 } else { 1 };
+    let mut i = open + quote_len;
+    while i < text.len() {
+        match text[i] {
+            '\n' if !multi_line => return None,
+            '\\' if !raw => i += 2,
+            '{' if !raw && quote == '\"' => i = interpolation_end(text, i)? + 1,
+            c if c == quote && (!multi_line || text[i..].starts_with(&['\"'; 3])) => return Some(i + quote_len),
+            _ => i += 1
+        }
+    }
+    None
+}
+
+/// Index of the '}' closing the string interpolation opened at start, literals inside it are skipped whole
+pub fn interpolation_end(text: &[char], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = start;
+    while i < text.len() {
+        match text[i] {
+            '\n' => return None,
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i)
+                }
+            },
+            _ if is_literal_start(text, i) => {
+                i = literal_end(text, i)?;
+                continue
+            },
+            _ => ()
+        }
+        i += 1
+    }
+    None
+}
+
 fn update_file_pos(c: char, file_pos: &mut FilePosition) {
     match c {
         '\n' => {
@@ -100,8 +155,8 @@ fn token_adjusted_fp(file_pos: &FilePosition, token: &String) -> FilePosition {
 }
 
 impl Scanner {
-    pub fn init() -> Scanner {
-        Scanner{ error: false, tokens: vec![] }
+    pub fn init(file: &str) -> Scanner {
+        Scanner{ error: false, tokens: vec![], file: file.to_string() }
     }
 
     fn push_delim_token(&mut self, token: &String, file_pos: &FilePosition) {
@@ -147,7 +202,8 @@ impl Scanner {
             )
         } else {
             self.error = true;
-            error!("Unexpected: {}", file_pos.position());
+            Diagnostic::error_at(Code::UnexpectedToken, &format!("Unexpected \"{}\"", token),
+                Span{fp: temp_file_pos, width: token.chars().count()}).emit();
         }
         token.clear()
     }
@@ -170,33 +226,71 @@ impl Scanner {
         }
     }
 
+    /// Pushes the char or string literal starting at start whole, returning the index just past it
+    fn push_literal_token(&mut self, text: &[char], start: usize, file_pos: &FilePosition) -> usize {
+        match literal_end(text, start) {
+            Some(end) => {
+                self.tokens.push(
+                    Token::Value {
+                        value: text[start..end].iter().collect(),
+                        fp: file_pos.clone()
+                    }
+                );
+                end
+            },
+            None => {
+                self.error = true;
+                let kind = if text[start] == '\'' { "char" } else { "string" };
+                Diagnostic::error_at(Code::UnterminatedLiteral, &format!("Unterminated {} literal", kind),
+                    Span{fp: file_pos.clone(), width: 1}).emit();
+                text[start..].iter().position(|c| { *c == '\n' }).map_or(text.len(), |p| { start + p })
+            }
+        }
+    }
+
     pub fn scan(&mut self, script: &String) {
         debug!("SCRIPT\n====================\n{:?}\n====================\n", script);
 
         let lines = script.lines();
         let text: Vec<char> = script.chars().collect();
         let mut token = String::from("");
-        let mut inside_quotes = false;
         let mut in_comment = false;
-        let file_pos = &mut FilePosition { line: 0, column: 0, line_text: String::from("") };
+        let mut literal_end = 0;
+        let file_pos = &mut FilePosition { file: self.file.clone(), line: 0, column: 0, line_text: String::from("") };
         let mut skip = false;
         
-        let peek_raw_delim = |i: &usize, text: &Vec<char>, inside_quotes: &bool| -> bool { 
-            *i < text.len() - 1 && is_raw_delim(&text[i + 1], inside_quotes.borrow()) 
+        let peek_raw_delim = |i: &usize, text: &Vec<char>| -> bool { 
+            *i < text.len() - 1 && is_raw_delim(&text[i + 1]) 
         };
 
         for i in 0..text.len() {
             let c = text[i];
-            file_pos.line_text = lines.clone().nth(file_pos.line).unwrap().to_string();
+            file_pos.line_text = lines.clone().nth(file_pos.line).unwrap_or("").to_string();
 
-            if !is_valid_character(&c, &inside_quotes.borrow()) {
+            if i < literal_end { // inside a literal that was already pushed
+                update_file_pos(c, file_pos);
+                continue;
+            }
+
+            if !is_valid_character(&c) {
                 self.error = true;
-                error!("Invalid character \'{}\': {}", &c, file_pos.position());
+                Diagnostic::error_at(Code::InvalidCharacter, &format!("Invalid character \'{}\'", &c),
+                    Span{fp: file_pos.clone(), width: 1}).emit();
+                continue;
+            }
+
+            if !in_comment && !skip && is_literal_start(&text, i) {
+                self.push_non_delim_token(&mut token, &incremented_fp(&file_pos));
+                literal_end = self.push_literal_token(&text, i, file_pos);
+                update_file_pos(c, file_pos);
                 continue;
             }
 
+            if c == '\n' { // push a token ending the line before the position moves to the next one
+                self.push_non_delim_token(&mut token, &incremented_fp(&file_pos));
+            }
             update_file_pos(c, file_pos);
-            if c == '#' && !inside_quotes {
+            if c == '#' {
                 in_comment = true;
                 continue;
             } else if in_comment && c != '\n' {
@@ -204,7 +298,7 @@ impl Scanner {
             } else if in_comment && c == '\n' {
                 in_comment = false;
                 continue;
-            } else if is_whitespace(c, inside_quotes) {
+            } else if is_whitespace(c) {
                 self.push_non_delim_token(&mut token, &file_pos);
                 continue;
             } else if skip {
@@ -212,10 +306,10 @@ impl Scanner {
                 continue;
             }
 
-            if is_raw_delim(&c, inside_quotes.borrow()) {
+            if is_raw_delim(&c) {
                 self.push_non_delim_token(&mut token, &file_pos);
                 let mut delim = String::from(text[i]);
-                if peek_raw_delim(&i, &text, &inside_quotes.borrow()) {
+                if peek_raw_delim(&i, &text) {
                     skip = true;
                     delim.push(text[i + 1]);
                     self.add_delim_token(Right(&delim), &file_pos)
@@ -223,13 +317,7 @@ impl Scanner {
                     self.add_delim_token(Left(c), &file_pos)
                 }
             } else {
-                if c == '\'' || c == '\"' {
-                    inside_quotes = !inside_quotes;
-                }
-                
-                if (c == ' ' && inside_quotes) || !c.is_whitespace() {
-                    token.push(c)
-                }
+                token.push(c)
             }
         }
         
@@ -6,6 +6,7 @@ use crate::defs::operator::Operator;
 
 pub fn make_empty_token() -> Token {
     Token::Ident{ident: "".to_string(), fp: FilePosition{
+        file: "".to_string(),
         line: 0,
         column: 0,
         line_text: "".to_string()
@@ -40,11 +41,24 @@ impl Token {
     }
 }
 
-pub fn get_fp_from_token(token: &Token) -> String {
+pub fn get_file_position(token: &Token) -> FilePosition {
     match token {
-        Token::Delimiter{delim: _, fp} => fp.position().clone(),
-        Token::Keyword{keyword: _, fp} => fp.position().clone(),
-        Token::Value{value: _, fp} => fp.position().clone(),
-        Token::Ident{ident: _, fp} => fp.position().clone()
+        Token::Delimiter{delim: _, fp} |
+        Token::Keyword{keyword: _, fp} |
+        Token::Value{value: _, fp} |
+        Token::Ident{ident: _, fp} => fp.clone()
+    }
+}
+
+/// Moves a token scanned from part of a line to where that part sits in the script
+pub fn offset_token(token: &Token, origin: &FilePosition) -> Token {
+    let offset = |fp: &FilePosition| -> FilePosition {
+        FilePosition{column: origin.column + fp.column, ..origin.clone()}
+    };
+    match token {
+        Token::Delimiter{delim, fp} => Token::Delimiter{delim: delim.clone(), fp: offset(fp)},
+        Token::Keyword{keyword, fp} => Token::Keyword{keyword: keyword.clone(), fp: offset(fp)},
+        Token::Value{value, fp} => Token::Value{value: value.clone(), fp: offset(fp)},
+        Token::Ident{ident, fp} => Token::Ident{ident: ident.clone(), fp: offset(fp)}
     }
 }
\ No newline at end of file
@@ -0,0 +1,287 @@
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+use log::{error, warn};
+use strum_macros::Display;
+
+use crate::scanner::token::{Token, get_file_position};
+use crate::utils::file_position::FilePosition;
+
+#[derive(Display, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    #[strum(serialize = "error")]
+    Error,
+    #[strum(serialize = "warning")]
+    Warning
+}
+
+#[derive(Display, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Code {
+    // scanner
+    #[strum(serialize = "E0001")]
+    InvalidCharacter,
+    #[strum(serialize = "E0002")]
+    UnexpectedToken,
+    #[strum(serialize = "E0003")]
+    UnterminatedLiteral,
+    // parser
+    #[strum(serialize = "E0101")]
+    ExpectedToken,
+    #[strum(serialize = "E0102")]
+    MissingStatementEnd,
+    #[strum(serialize = "E0103")]
+    InvalidSyntax,
+    #[strum(serialize = "E0104")]
+    InvalidPattern,
+    #[strum(serialize = "E0105")]
+    InvalidType,
+    #[strum(serialize = "E0106")]
+    UnknownMember,
+    #[strum(serialize = "E0107")]
+    DuplicateMember,
+    #[strum(serialize = "E0108")]
+    ImportFailed,
+    #[strum(serialize = "E0109")]
+    InvalidEscape,
+    // checker
+    #[strum(serialize = "E0201")]
+    TypeMismatch,
+    #[strum(serialize = "E0202")]
+    UnknownReference,
+    #[strum(serialize = "E0203")]
+    ArgumentCount,
+    #[strum(serialize = "E0204")]
+    UnknownField,
+    #[strum(serialize = "E0205")]
+    InvalidOperation,
+    #[strum(serialize = "E0206")]
+    NonExhaustiveMatch,
+    #[strum(serialize = "W0207")]
+    UnreachableCase,
+    #[strum(serialize = "E0208")]
+    ImmutableAssignment,
+    #[strum(serialize = "E0209")]
+    InvalidControlFlow,
+    // interpreter
+    #[strum(serialize = "E0301")]
+    RuntimeType,
+    #[strum(serialize = "E0302")]
+    RuntimeReference,
+    #[strum(serialize = "E0303")]
+    Index,
+    #[strum(serialize = "E0304")]
+    Key,
+    #[strum(serialize = "E0305")]
+    Match,
+    #[strum(serialize = "E0306")]
+    Arithmetic,
+    #[strum(serialize = "E0307")]
+    Conversion,
+    #[strum(serialize = "E0308")]
+    Io,
+    #[strum(serialize = "E0309")]
+    Runtime,
+    #[strum(serialize = "E0310")]
+    Assertion,
+    #[strum(serialize = "E0311")]
+    Validation
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum DiagnosticFormat {
+    Human,
+    Json
+}
+
+struct Output {
+    format: DiagnosticFormat,
+    colour: bool
+}
+
+static OUTPUT: OnceLock<Output> = OnceLock::new();
+
+/// Chooses how diagnostics are written, colour is only used on a terminal
+pub fn init_output(format: DiagnosticFormat) {
+    let colour = std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+    let _ = OUTPUT.set(Output{format, colour});
+}
+
+pub fn is_json_output() -> bool {
+    matches!(OUTPUT.get(), Some(Output{format: DiagnosticFormat::Json, ..}))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub fp: FilePosition,
+    pub width: usize
+}
+
+/// Secondary span pointing at code related to the diagnostic, e.g. a declaration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub message: String,
+    pub span: Span
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Code,
+    pub message: String,
+    pub span: Span,
+    pub labels: Vec<Label>,
+    pub help: Option<String>
+}
+
+impl Span {
+    /// Span covering the source text of a token
+    pub fn of(token: &Token) -> Span {
+        let width = match token {
+            Token::Delimiter{delim, ..} => delim.to_string().chars().count(),
+            Token::Keyword{keyword, ..} => keyword.to_string().chars().count(),
+            Token::Value{value, ..} => value.lines().next().unwrap_or("").chars().count(), // first line of multi-line strings
+            Token::Ident{ident, ..} => ident.chars().count()
+        };
+        Span{fp: get_file_position(token), width: width.max(1)}
+    }
+
+    fn to_json(&self) -> String {
+        format!("\"file\":{},\"line\":{},\"column\":{},\"end_column\":{}",
+            json_string(&self.fp.file), self.fp.line + 1, self.fp.column + 1, self.fp.column + 1 + self.width)
+    }
+
+    fn render(&self, message: &str, ansi: &str, colour: bool) -> String {
+        let location = if self.fp.file.is_empty() {
+            format!("{}:{}", self.fp.line + 1, self.fp.column + 1)
+        } else {
+            format!("{}:{}:{}", self.fp.file, self.fp.line + 1, self.fp.column + 1)
+        };
+        let mut rendered = format!("\n    {} {}\n    {}\n    {}{}", paint("-->", "34", colour), location,
+            self.fp.line_text, "-".repeat(self.fp.column), paint(&"^".repeat(self.width), ansi, colour));
+        if !message.is_empty() {
+            rendered += &format!(" {}", paint(message, ansi, colour));
+        }
+        rendered
+    }
+}
+
+fn paint(text: &str, ansi: &str, colour: bool) -> String {
+    if colour { format!("\x1b[{}m{}\x1b[0m", ansi, text) } else { text.to_string() }
+}
+
+fn json_string(text: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c)
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b_chars: Vec<char> = b.chars().collect();
+    let mut prev_row: Vec<usize> = (0..=b_chars.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut row = vec![i + 1];
+        for (j, cb) in b_chars.iter().enumerate() {
+            let substitution = prev_row[j] + if ca == *cb { 0 } else { 1 };
+            row.push(substitution.min(prev_row[j + 1] + 1).min(row[j] + 1));
+        }
+        prev_row = row;
+    }
+    prev_row[b_chars.len()]
+}
+
+/// Closest candidate within a third of the name's length, for "did you mean" help
+pub fn suggest<'a, I: IntoIterator<Item = &'a String>>(name: &str, candidates: I) -> Option<String> {
+    let max_distance = (name.chars().count() / 3).max(1);
+    candidates.into_iter()
+        .filter(|candidate| { candidate.as_str() != name && !candidate.contains('$') })
+        .map(|candidate| { (levenshtein(name, candidate), candidate) })
+        .filter(|(distance, _)| { *distance <= max_distance })
+        .min_by(|a, b| { a.0.cmp(&b.0).then_with(|| { a.1.cmp(b.1) }) })
+        .map(|(_, candidate)| { format!("did you mean \"{}\"?", candidate) })
+}
+
+impl Diagnostic {
+    pub fn error(code: Code, message: &str, token: &Token) -> Diagnostic {
+        Diagnostic::error_at(code, message, Span::of(token))
+    }
+
+    pub fn error_at(code: Code, message: &str, span: Span) -> Diagnostic {
+        Diagnostic{
+            severity: Severity::Error,
+            code,
+            message: message.to_string(),
+            span,
+            labels: vec![],
+            help: None
+        }
+    }
+
+    pub fn warning(code: Code, message: &str, token: &Token) -> Diagnostic {
+        Diagnostic{severity: Severity::Warning, ..Diagnostic::error(code, message, token)}
+    }
+
+    pub fn label(mut self, message: &str, token: &Token) -> Diagnostic {
+        self.labels.push(Label{message: message.to_string(), span: Span::of(token)});
+        self
+    }
+
+    pub fn help(mut self, help: Option<String>) -> Diagnostic {
+        self.help = help;
+        self
+    }
+
+    pub fn emit(&self) {
+        match OUTPUT.get() {
+            Some(Output{format: DiagnosticFormat::Json, ..}) => eprintln!("{}", self.to_json()),
+            output => {
+                let rendered = self.render(output.map(|o| { o.colour }).unwrap_or(false));
+                match self.severity {
+                    Severity::Error => error!("{}", rendered),
+                    Severity::Warning => warn!("{}", rendered)
+                }
+            }
+        }
+    }
+
+    pub fn render(&self, colour: bool) -> String {
+        let ansi = match self.severity {
+            Severity::Error => "1;31",
+            Severity::Warning => "1;33"
+        };
+        let mut rendered = format!("{}: {}", paint(&self.code.to_string(), ansi, colour), self.message);
+        rendered += &self.span.render("", ansi, colour);
+        for label in self.labels.iter() {
+            rendered += &label.span.render(&label.message, "1;34", colour);
+        }
+        if let Some(help) = &self.help {
+            rendered += &format!("\n    {}: {}", paint("help", "1;36", colour), help);
+        }
+        rendered
+    }
+
+    pub fn to_json(&self) -> String {
+        let labels: Vec<String> = self.labels.iter()
+            .map(|label| { format!("{{\"message\":{},{}}}", json_string(&label.message), label.span.to_json()) })
+            .collect();
+        format!("{{\"severity\":\"{}\",\"code\":\"{}\",\"message\":{},{},\"labels\":[{}],\"help\":{}}}",
+            self.severity,
+            self.code,
+            json_string(&self.message),
+            self.span.to_json(),
+            labels.join(","),
+            match &self.help {
+                Some(help) => json_string(help),
+                _ => "null".to_string()
+            })
+    }
+}
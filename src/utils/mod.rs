@@ -1 +1,2 @@
-pub mod file_position;
\ No newline at end of file
+pub mod file_position;
+pub mod diagnostic;
\ No newline at end of file
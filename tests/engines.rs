@@ -0,0 +1,204 @@
+//! Differential tests of the two engines, the scripts in tests/scripts are run with the tree interpreter and
+//! the VM and both have to print the same output and errors and exit with the same code, which also have to
+//! match the name.out and name.err files next to the script. RETL_BLESS=1 rewrites those files from the tree
+//! interpreter run
+
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Longest a script may run, an engine stuck in a loop fails the test instead of hanging it
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+struct Run {
+    code: Option<i32>,
+    stdout: String,
+    stderr: String
+}
+
+/// Path of a test file relative to the crate root, scripts are run from there so errors show the same path
+/// on every machine
+fn script(name: &str) -> PathBuf {
+    Path::new("tests").join("scripts").join(name)
+}
+
+fn run(engine: &str, script: &Path, args: &[&str]) -> Run {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_retl"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(["--engine", engine, "-f"])
+        .arg(script)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("could not run retl");
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().expect("could not wait for retl") {
+            break status
+        }
+        if start.elapsed() > TIMEOUT {
+            let _ = child.kill();
+            panic!("{} engine did not finish {} within {:?}", engine, script.display(), TIMEOUT)
+        }
+        thread::sleep(Duration::from_millis(10))
+    };
+    let mut stdout = String::new();
+    let mut stderr = String::new();
+    if let Some(mut out) = child.stdout.take() {
+        out.read_to_string(&mut stdout).expect("could not read retl output");
+    }
+    if let Some(mut err) = child.stderr.take() {
+        err.read_to_string(&mut stderr).expect("could not read retl errors");
+    }
+    // imported modules are reported by their absolute path
+    let root = concat!(env!("CARGO_MANIFEST_DIR"), "/");
+    Run{code: status.code(), stdout: stdout.replace(root, ""), stderr: stderr.replace(root, "")}
+}
+
+/// Runs a script on both engines, the tree interpreter has to exit with expected_code for the comparison to
+/// mean anything
+fn assert_engines_agree(name: &str, expected_code: i32) {
+    assert_engines_agree_with(name, &[], expected_code)
+}
+
+/// Runs a script on both engines with extra command line arguments after the script
+fn assert_engines_agree_with(name: &str, args: &[&str], expected_code: i32) {
+    let script = script(name);
+    let tree = run("tree", &script, args);
+    let vm = run("vm", &script, args);
+    assert_eq!(tree.code, Some(expected_code), "tree exit code of {}, stderr:\n{}", name, tree.stderr);
+    assert_eq!(vm.stdout, tree.stdout, "stdout of {}", name);
+    assert_eq!(vm.stderr, tree.stderr, "stderr of {}", name);
+    assert_eq!(vm.code, tree.code, "exit code of {}", name);
+    assert_expected(&script.with_extension("out"), &tree.stdout);
+    assert_expected(&script.with_extension("err"), &tree.stderr);
+}
+
+/// Compares output with the expected file, a missing file expects no output
+fn assert_expected(expected: &Path, output: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(expected);
+    if std::env::var_os("RETL_BLESS").is_some() {
+        if output.is_empty() {
+            let _ = fs::remove_file(&path);
+        } else {
+            fs::write(&path, output).expect("could not write expected output");
+        }
+        return
+    }
+    let expected_output = fs::read_to_string(&path).unwrap_or_default();
+    assert_eq!(output, expected_output, "output does not match {}", expected.display());
+}
+
+#[test]
+fn control_flow() {
+    assert_engines_agree("control_flow.retl", 0)
+}
+
+#[test]
+fn vars() {
+    assert_engines_agree("vars.retl", 0)
+}
+
+#[test]
+fn interpolation() {
+    assert_engines_agree("interpolation.retl", 0)
+}
+
+#[test]
+fn fn_groups() {
+    assert_engines_agree("fn_groups.retl", 0)
+}
+
+#[test]
+fn indexing() {
+    assert_engines_agree("indexing.retl", 0)
+}
+
+#[test]
+fn literals() {
+    assert_engines_agree("literals.retl", 0)
+}
+
+#[test]
+fn contracts() {
+    assert_engines_agree("contracts.retl", 6)
+}
+
+#[test]
+fn contract_errors() {
+    assert_engines_agree("contract_errors.retl", 5)
+}
+
+#[test]
+fn imports() {
+    assert_engines_agree_with("imports.retl", &["-I", "tests/scripts/include"], 0)
+}
+
+#[test]
+fn import_errors() {
+    assert_engines_agree("import_errors.retl", 4)
+}
+
+#[test]
+fn params() {
+    let args = ["-p", "input=data.csv", "--param", "n=41", "-p", "verbose=true", "-p", "sep=;", "-p", "date=2026-10-01",
+        "-p", "bad=x", "first", "second"];
+    assert_engines_agree_with("params.retl", &args, 6)
+}
+
+#[test]
+fn json_syntax_errors() {
+    assert_engines_agree_with("json_syntax_errors.retl", &["--error-format", "json"], 4)
+}
+
+#[test]
+fn json_check_errors() {
+    assert_engines_agree_with("json_check_errors.retl", &["--error-format", "json"], 5)
+}
+
+/// A missing script and one without the .retl extension are reported before either engine starts, one JSON
+/// line each
+#[test]
+fn json_file_errors() {
+    let mut stderr = String::new();
+    for path in &[script("missing.retl"), script("data").join("people.csv")] {
+        let tree = run("tree", path, &["--error-format", "json"]);
+        let vm = run("vm", path, &["--error-format", "json"]);
+        assert_eq!(tree.code, Some(7), "tree exit code of {}, stderr:\n{}", path.display(), tree.stderr);
+        assert_eq!(vm.stderr, tree.stderr, "stderr of {}", path.display());
+        assert_eq!(vm.code, tree.code, "exit code of {}", path.display());
+        assert!(tree.stdout.is_empty(), "stdout of {}", path.display());
+        stderr.push_str(&tree.stderr);
+    }
+    assert_expected(&script("json_file_errors.err"), &stderr);
+}
+
+#[test]
+fn match_patterns() {
+    assert_engines_agree("match.retl", 0)
+}
+
+#[test]
+fn parallel() {
+    assert_engines_agree_with("parallel.retl", &["--workers", "3"], 0)
+}
+
+#[test]
+fn runtime_errors() {
+    assert_engines_agree("runtime_errors.retl", 6)
+}
+
+#[test]
+fn syntax_errors() {
+    assert_engines_agree("syntax_errors.retl", 4)
+}
+
+#[test]
+fn assign_errors() {
+    assert_engines_agree("assign_errors.retl", 5)
+}